@@ -0,0 +1,37 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use envhist_core::storage::Storage;
+use std::path::PathBuf;
+
+pub fn archive(older_than: Duration, output: PathBuf) -> Result<()> {
+    let storage = Storage::new()?;
+    let summary = storage.archive(Utc::now() - older_than, &output)?;
+
+    if summary.sessions == 0 && summary.snapshot_names.is_empty() {
+        println!("Nothing old enough to archive.");
+        return Ok(());
+    }
+
+    println!(
+        "✓ Archived {} session(s) and {} snapshot(s) to {}",
+        summary.sessions,
+        summary.snapshot_names.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+pub fn import_archive(file: PathBuf) -> Result<()> {
+    let storage = Storage::new()?;
+    let summary = storage.import_archive(&file)?;
+
+    println!(
+        "✓ Restored {} session(s) and {} snapshot(s) from {}",
+        summary.sessions,
+        summary.snapshot_names.len(),
+        file.display()
+    );
+
+    Ok(())
+}
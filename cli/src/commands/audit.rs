@@ -0,0 +1,47 @@
+use crate::format::{self, Table, TimestampFormat};
+use anyhow::Result;
+use colored::*;
+use envhist_core::audit::{self, AuditOutcome};
+use envhist_core::Config;
+
+pub fn audit(limit: Option<usize>, json: bool, timestamp: TimestampFormat) -> Result<()> {
+    let mut entries = audit::read(&Config::audit_log_path())?;
+    if let Some(limit) = limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+
+    if json {
+        for entry in &entries {
+            println!("{}", serde_json::to_string(entry)?);
+        }
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No audited commands yet.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    for entry in &entries {
+        let outcome = match entry.outcome {
+            AuditOutcome::Ok => "ok".green().to_string(),
+            AuditOutcome::Error => "error".red().to_string(),
+        };
+        table.push(vec![
+            timestamp.format(entry.timestamp).dimmed().to_string(),
+            entry.command.bold().to_string(),
+            outcome,
+            entry.args.join(" "),
+        ]);
+    }
+    let width = format::terminal_width();
+    for (line, entry) in table.render(width).into_iter().zip(&entries) {
+        println!("{}", line);
+        if let Some(ref error) = entry.error {
+            println!("    {}", error.red());
+        }
+    }
+
+    Ok(())
+}
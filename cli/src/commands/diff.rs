@@ -1,46 +1,275 @@
-use crate::{daemon_client, DiffArgs};
-use anyhow::Result;
+use super::{log, snapshot};
+use crate::format::{
+    description_suffix, display_change, display_key_value, display_value, group_by_prefix,
+    is_masked, GroupBy,
+};
+use crate::{daemon_client, shell, DiffArgs};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
 use colored::*;
 use envhist_core::{
-    differ::{diff_envs, EnvDiff},
-    storage::Storage,
+    differ::{
+        context_mask, diff3_envs, diff_envs, diff_envs_with, diff_list, sort_diffs, Diff3Entry,
+        Diff3Status, DiffOptions, DiffOrder, DiffSummary, EnvDiff,
+    },
+    envfile, secrets,
+    session::Session,
+    storage::{strip_values, Snapshot, Storage},
+    Catalog, Config, Env, StorageBackend,
 };
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiffSort {
+    /// By variable name
+    #[default]
+    Key,
+    /// By new value (old value for removals)
+    Value,
+    /// Biggest change in value length first
+    ChangeSize,
+    /// Added, then removed, then changed
+    Type,
+}
+
+impl From<DiffSort> for DiffOrder {
+    fn from(sort: DiffSort) -> Self {
+        match sort {
+            DiffSort::Key => DiffOrder::Key,
+            DiffSort::Value => DiffOrder::Value,
+            DiffSort::ChangeSize => DiffOrder::ChangeSize,
+            DiffSort::Type => DiffOrder::Type,
+        }
+    }
+}
 
 pub fn diff(args: DiffArgs) -> Result<()> {
+    let exit_code = args.exit_code || args.quiet;
+    if run_diff(args)? && exit_code {
+        return Err(super::failed("snapshots differ"));
+    }
+    Ok(())
+}
+
+/// Print the requested diff and report whether any variable differs.
+fn run_diff(args: DiffArgs) -> Result<bool> {
     let storage = Storage::new()?;
     let session = daemon_client::get_active_session().ok().flatten();
 
     let session_ref = session.as_ref();
 
-    let (old_env, old_name) = if let Some(ref name) = args.snapshot1 {
-        let snapshot = storage.load_snapshot(name, session_ref)?;
-        (snapshot.environment, name.clone())
-    } else {
-        // Use last snapshot
-        let snapshots = storage.list_snapshots(session_ref)?;
-        if snapshots.is_empty() {
-            anyhow::bail!("No snapshots found. Create one with: envhist snapshot <name>");
+    if let (Some(base), Some(ours), Some(theirs)) = (&args.base, &args.ours, &args.theirs) {
+        let sides = [base, ours, theirs]
+            .map(|name| storage.load_snapshot(name, session_ref))
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        // Names are all a keys-only snapshot can be compared on
+        let keys_only = sides.iter().any(|snap| snap.keys_only);
+        let envs: Vec<_> = sides
+            .iter()
+            .map(|snap| {
+                if keys_only {
+                    strip_values(&snap.environment)
+                } else {
+                    snap.environment.clone()
+                }
+            })
+            .collect();
+
+        let entries = diff3_envs(&envs[0], &envs[1], &envs[2]);
+        let changed = entries
+            .iter()
+            .any(|entry| entry.status != Diff3Status::Unchanged);
+        if args.quiet {
+            return Ok(changed);
         }
-        let snapshot = &snapshots[0];
-        (snapshot.environment.clone(), snapshot.name.clone())
-    };
 
-    let (new_env, new_name) = if let Some(ref name) = args.snapshot2 {
-        let snapshot = storage.load_snapshot(name, session_ref)?;
-        (snapshot.environment, name.clone())
+        println!("--- base: {} ---", base);
+        println!("<<< ours: {} <<<", ours);
+        println!(">>> theirs: {} >>>", theirs);
+        println!();
+
+        print!("{}", format_diff3_colored(&entries));
+        return Ok(changed);
+    }
+
+    if args.snapshot1.as_deref() == Some(STDIN_SIDE)
+        && args.snapshot2.as_deref() == Some(STDIN_SIDE)
+    {
+        anyhow::bail!("Only one side of a diff can be read from stdin");
+    }
+    if args.only_secrets && args.json && !args.summary_only {
+        anyhow::bail!(
+            "--only-secrets with --json needs --summary-only; JSON values are not masked"
+        );
+    }
+
+    // The new side is the daemon's copy, whose `filters.hash_patterns`
+    // values are hashed and long values truncated, while snapshots keep them
+    // raw
+    let recorded = args.against_session && args.snapshot2.is_none();
+    let (new_env, new_name, new_keys_only) = if let Some(ref name) = args.snapshot2 {
+        load_side(&storage, storage.config(), name, session_ref)?
+    } else if args.against_session {
+        // The CLI's own env is a child copy; the daemon holds the shell's
+        let shell_session = log::get_session_for_pid(std::process::id())?;
+        let metadata = Session::load_metadata(&shell_session.metadata_path()).with_context(|| {
+            format!(
+                "No captured environment for session {} yet (the shell hook captures it periodically)",
+                shell_session.id
+            )
+        })?;
+        (
+            metadata.current_env,
+            format!("session {}", shell_session.id),
+            false,
+        )
     } else {
         // Use current env
-        (Storage::get_current_env(), "current".to_string())
+        (Storage::get_current_env(), "current".to_string(), false)
+    };
+
+    let context = args
+        .context
+        .unwrap_or(storage.config().display.diff_context);
+    let options = DiffOptions {
+        ignore_case: args.ignore_case,
+        ignore_whitespace: args.ignore_whitespace,
     };
+    let catalog = Catalog::load();
+
+    if let Some(count) = args.recent {
+        diff_recent(
+            &storage,
+            session_ref,
+            count,
+            &new_env,
+            &new_name,
+            context,
+            &options,
+            &catalog,
+            args.summary_only,
+            recorded,
+        )?;
+        return Ok(false);
+    }
+
+    let (old_env, old_name, old_keys_only) = if let Some(ref name) = args.snapshot1 {
+        load_side(&storage, storage.config(), name, session_ref)?
+    } else {
+        let snapshot = latest_snapshot(&storage, session_ref)?;
+        (snapshot.environment, snapshot.name, snapshot.keys_only)
+    };
+
+    let old_env = if recorded {
+        storage.recorded_env(old_env)?
+    } else {
+        old_env
+    };
+
+    // A keys-only side has no values to compare, so only keys can differ
+    let mut diffs = if old_keys_only || new_keys_only {
+        diff_envs(&strip_values(&old_env), &strip_values(&new_env))
+    } else {
+        diff_envs_with(&old_env, &new_env, &options)
+    };
+    if let Some(ref path) = args.only_from {
+        let keys = read_key_list(path)?;
+        diffs.retain(|d| keys.contains(d.key()));
+    }
+    sort_diffs(&mut diffs, args.sort.into());
+    let changed = diffs
+        .iter()
+        .any(|d| !matches!(d, EnvDiff::Unchanged { .. }));
+    if args.quiet {
+        return Ok(changed);
+    }
+    if args.only_secrets {
+        diffs.retain(|d| is_secret_diff(d, storage.config()));
+    }
 
-    let diffs = diff_envs(&old_env, &new_env);
+    if args.json && args.summary_only {
+        let summary = DiffSummary::from_diffs(&diffs);
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(changed);
+    }
+
+    if args.json {
+        let changes: Vec<&EnvDiff> = diffs
+            .iter()
+            .filter(|d| !matches!(d, EnvDiff::Unchanged { .. }))
+            .collect();
+        let output = serde_json::json!({
+            "from": old_name,
+            "to": new_name,
+            "diffs": changes,
+            "summary": DiffSummary::from_diffs(&diffs),
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(changed);
+    }
+
+    if args.summary_only {
+        println!("{}", format_summary(&diffs).trim());
+        return Ok(changed);
+    }
 
     println!("--- {} ---", old_name);
     println!("+++ {} +++", new_name);
     println!();
 
-    let output = format_diff_colored(&diffs, false);
-    print!("{}", output);
+    if args.only_secrets {
+        print!("{}", format_secret_diff(&diffs));
+        print!("{}", format_summary(&diffs));
+        return Ok(changed);
+    }
+
+    match args.group_by {
+        _ if args.grouped => print!(
+            "{}",
+            format_diff_grouped(&diffs, context, storage.config(), &catalog)
+        ),
+        Some(GroupBy::Prefix) => {
+            let prefixes = &storage.config().display.group_prefixes;
+            let groups = group_by_prefix(diffs.clone(), |d| d.key(), prefixes);
+            for (i, (group, group_diffs)) in groups
+                .into_iter()
+                .filter(|(_, g)| {
+                    args.show_unchanged || g.iter().any(|d| !matches!(d, EnvDiff::Unchanged { .. }))
+                })
+                .enumerate()
+            {
+                if i > 0 {
+                    println!();
+                }
+                println!("{}", format!("[{}]", group).bold());
+                print!(
+                    "{}",
+                    format_diff_colored(
+                        &group_diffs,
+                        args.show_unchanged,
+                        context,
+                        storage.config(),
+                        &catalog
+                    )
+                );
+            }
+        }
+        None => print!(
+            "{}",
+            format_diff_colored(
+                &diffs,
+                args.show_unchanged,
+                context,
+                storage.config(),
+                &catalog
+            )
+        ),
+    }
+    print!("{}", format_summary(&diffs));
 
     if args.exports {
         let commands = exports_for_diffs(&diffs);
@@ -52,51 +281,386 @@ pub fn diff(args: DiffArgs) -> Result<()> {
         }
     }
 
+    Ok(changed)
+}
+
+/// The variable names listed in an `--only-from` file.
+pub(crate) fn read_key_list(path: &Path) -> Result<BTreeSet<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read variable list {:?}", path))?;
+    envfile::parse_key_list(&content).with_context(|| format!("Invalid variable list {:?}", path))
+}
+
+/// Positional argument that reads `KEY=VALUE` lines from stdin instead of a snapshot.
+const STDIN_SIDE: &str = "-";
+
+/// The env and display name for one side of the diff: a snapshot, or stdin
+/// for `-` (filtered like `snapshot --stdin`), and whether it is keys-only.
+fn load_side(
+    storage: &dyn StorageBackend,
+    config: &Config,
+    name: &str,
+    session: Option<&Session>,
+) -> Result<(Env, String, bool)> {
+    if name == STDIN_SIDE {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read environment from stdin")?;
+        let env = snapshot::filter_tracked(envfile::parse_env_lines(&input), config, false);
+        return Ok((env, "stdin".to_string(), false));
+    }
+
+    let snapshot = storage.load_snapshot(name, session)?;
+    Ok((snapshot.environment, name.to_string(), snapshot.keys_only))
+}
+
+/// The newest snapshot visible from `session`, the default old side.
+fn latest_snapshot(storage: &dyn StorageBackend, session: Option<&Session>) -> Result<Snapshot> {
+    storage
+        .list_snapshots(session)?
+        .into_iter()
+        .next()
+        .context("No snapshots found. Create one with: envhist snapshot <name>")
+}
+
+/// Diff `new_env` against each of the `count` newest snapshots, newest first.
+/// `recorded` says `new_env` is the daemon's copy, so each snapshot is
+/// hashed and truncated the same way first.
+#[allow(clippy::too_many_arguments)]
+fn diff_recent(
+    storage: &Storage,
+    session: Option<&Session>,
+    count: usize,
+    new_env: &Env,
+    new_name: &str,
+    context: usize,
+    options: &DiffOptions,
+    catalog: &Catalog,
+    summary_only: bool,
+    recorded: bool,
+) -> Result<()> {
+    let snapshots = storage.list_snapshots(session)?;
+    if snapshots.is_empty() {
+        anyhow::bail!("No snapshots found. Create one with: envhist snapshot <name>");
+    }
+
+    for (i, snapshot) in snapshots.iter().take(count).enumerate() {
+        let diffs = if snapshot.keys_only {
+            diff_envs(&snapshot.environment, &strip_values(new_env))
+        } else if recorded {
+            let old_env = storage.recorded_env(snapshot.environment.clone())?;
+            diff_envs_with(&old_env, new_env, options)
+        } else {
+            diff_envs_with(&snapshot.environment, new_env, options)
+        };
+        if summary_only {
+            println!(
+                "{} -> {}: {}",
+                snapshot.name,
+                new_name,
+                format_summary(&diffs).trim()
+            );
+            continue;
+        }
+
+        if i > 0 {
+            println!();
+        }
+        println!(
+            "{}",
+            format!("=== {} -> {} ===", snapshot.name, new_name).bold()
+        );
+        println!();
+
+        print!(
+            "{}",
+            format_diff_colored(&diffs, false, context, storage.config(), catalog)
+        );
+        print!("{}", format_summary(&diffs));
+    }
+
     Ok(())
 }
 
-fn format_diff_colored(diffs: &[EnvDiff], show_unchanged: bool) -> String {
+pub(crate) fn format_diff_colored(
+    diffs: &[EnvDiff],
+    show_unchanged: bool,
+    context: usize,
+    config: &Config,
+    catalog: &Catalog,
+) -> String {
     let mut output = String::new();
 
-    let mut added_count = 0;
-    let mut removed_count = 0;
-    let mut changed_count = 0;
+    let visible = if show_unchanged {
+        vec![true; diffs.len()]
+    } else {
+        context_mask(diffs, context)
+    };
+    let mut skipped = false;
 
+    for (diff, visible) in diffs.iter().zip(visible) {
+        if !visible {
+            skipped = true;
+            continue;
+        }
+        if skipped && !output.is_empty() {
+            output.push_str(&format!("{}\n", "  ...".dimmed()));
+        }
+        skipped = false;
+        push_diff(&mut output, diff, context, config, catalog);
+    }
+
+    output
+}
+
+/// Added, removed and changed variables under their own headers instead of
+/// interleaved, each section in the order of `diffs`. Unchanged variables
+/// are omitted.
+pub(crate) fn format_diff_grouped(
+    diffs: &[EnvDiff],
+    context: usize,
+    config: &Config,
+    catalog: &Catalog,
+) -> String {
+    let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
     for diff in diffs {
         match diff {
-            EnvDiff::Added { key, value } => {
-                output.push_str(&format!("+ {}: {}\n", key.to_string().green(), value));
-                added_count += 1;
-            }
-            EnvDiff::Removed { key, old_value } => {
-                output.push_str(&format!("- {}: {}\n", key.to_string().red(), old_value));
-                removed_count += 1;
-            }
-            EnvDiff::Changed {
-                key,
-                old_value,
-                new_value,
-            } => {
-                output.push_str(&format!("~ {}:\n", key.to_string().yellow()));
-                output.push_str(&format!("  - {}\n", old_value));
-                output.push_str(&format!("  + {}\n", new_value));
-                changed_count += 1;
+            EnvDiff::Added { .. } => added.push(diff),
+            EnvDiff::Removed { .. } => removed.push(diff),
+            EnvDiff::Changed { .. } => changed.push(diff),
+            EnvDiff::Unchanged { .. } => {}
+        }
+    }
+
+    let mut output = String::new();
+    for (header, section) in [
+        ("Added:", added),
+        ("Removed:", removed),
+        ("Changed:", changed),
+    ] {
+        if section.is_empty() {
+            continue;
+        }
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&format!("{}\n", header.bold()));
+        for diff in section {
+            push_diff(&mut output, diff, context, config, catalog);
+        }
+    }
+
+    output
+}
+
+/// One variable's lines in a diff.
+fn push_diff(
+    output: &mut String,
+    diff: &EnvDiff,
+    context: usize,
+    config: &Config,
+    catalog: &Catalog,
+) {
+    match diff {
+        EnvDiff::Added { key, value } => {
+            output.push_str(&format!(
+                "+ {}: {}{}\n",
+                key.to_string().green(),
+                display_key_value(key, value),
+                description_suffix(catalog, key)
+            ));
+        }
+        EnvDiff::Removed { key, old_value } => {
+            output.push_str(&format!(
+                "- {}: {}{}\n",
+                key.to_string().red(),
+                display_key_value(key, old_value),
+                description_suffix(catalog, key)
+            ));
+        }
+        EnvDiff::Changed {
+            key,
+            old_value,
+            new_value,
+        } => {
+            output.push_str(&format!(
+                "~ {}:{}\n",
+                key.to_string().yellow(),
+                description_suffix(catalog, key)
+            ));
+            match config.list_delimiter(key) {
+                _ if is_masked(key) => {
+                    let (old_shown, new_shown) = display_change(key, old_value, new_value);
+                    output.push_str(&format!("  - {}\n", old_shown));
+                    output.push_str(&format!("  + {}\n", new_shown));
+                }
+                Some(delimiter) => {
+                    let list_diff = diff_list(old_value, new_value, delimiter);
+                    for element in &list_diff.removed {
+                        output.push_str(&format!("  - {}\n", display_value(element).red()));
+                    }
+                    for element in &list_diff.added {
+                        output.push_str(&format!("  + {}\n", display_value(element).green()));
+                    }
+                    if list_diff.added.is_empty() && list_diff.removed.is_empty() {
+                        output.push_str(&format!("  {}\n", "(reordered)".dimmed()));
+                    }
+                }
+                None if old_value.contains('\n') || new_value.contains('\n') => {
+                    push_line_diff(output, old_value, new_value, context);
+                }
+                None => {
+                    output.push_str(&format!("  - {}\n", display_value(old_value)));
+                    output.push_str(&format!("  + {}\n", display_value(new_value)));
+                }
             }
-            EnvDiff::Unchanged { key, value } => {
-                if show_unchanged {
-                    output.push_str(&format!("  {}: {}\n", key, value));
+        }
+        EnvDiff::Unchanged { key, value } => {
+            output.push_str(&format!(
+                "{}\n",
+                format!("  {}: {}", key, display_key_value(key, value)).dimmed()
+            ));
+        }
+    }
+}
+
+/// Line-by-line diff of a multiline value, keeping `context` unchanged lines
+/// around each change.
+fn push_line_diff(output: &mut String, old_value: &str, new_value: &str, context: usize) {
+    let text_diff = TextDiff::from_lines(old_value, new_value);
+    for (i, group) in text_diff.grouped_ops(context).iter().enumerate() {
+        if i > 0 {
+            output.push_str(&format!("  {}\n", "...".dimmed()));
+        }
+        for op in group {
+            for change in text_diff.iter_changes(op) {
+                let line = change.value().trim_end_matches(['\n', '\r']);
+                let line = display_value(line);
+                match change.tag() {
+                    ChangeTag::Delete => output.push_str(&format!("  - {}\n", line.red())),
+                    ChangeTag::Insert => output.push_str(&format!("  + {}\n", line.green())),
+                    ChangeTag::Equal => {
+                        output.push_str(&format!("{}\n", format!("    {}", line).dimmed()))
+                    }
                 }
             }
         }
     }
+}
 
-    if !show_unchanged {
-        output.push_str(&format!(
-            "\n{} changed, {} added, {} removed\n",
-            changed_count, added_count, removed_count
-        ));
+/// A changed variable whose name matches a secret pattern or whose old or new
+/// value looks like a credential.
+fn is_secret_diff(diff: &EnvDiff, config: &Config) -> bool {
+    let values: Vec<&String> = match diff {
+        EnvDiff::Added { value, .. } => vec![value],
+        EnvDiff::Removed { old_value, .. } => vec![old_value],
+        EnvDiff::Changed {
+            old_value,
+            new_value,
+            ..
+        } => vec![old_value, new_value],
+        EnvDiff::Unchanged { .. } => return false,
+    };
+    config.is_secret_key(diff.key()) || values.into_iter().any(|v| secrets::looks_secret(v))
+}
+
+/// Changes without their values, for auditing secrets.
+fn format_secret_diff(diffs: &[EnvDiff]) -> String {
+    let mut output = String::new();
+    for diff in diffs {
+        let line = match diff {
+            EnvDiff::Added { key, .. } => format!("+ {}: added", key.to_string().green()),
+            EnvDiff::Removed { key, .. } => format!("- {}: removed", key.to_string().red()),
+            EnvDiff::Changed { key, .. } => {
+                format!("~ {}: value changed", key.to_string().yellow())
+            }
+            EnvDiff::Unchanged { .. } => continue,
+        };
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+pub(crate) fn format_summary(diffs: &[EnvDiff]) -> String {
+    let summary = DiffSummary::from_diffs(diffs);
+    format!(
+        "\n{} changed, {} added, {} removed\n",
+        summary.changed, summary.added, summary.removed
+    )
+}
+
+fn format_diff3_colored(entries: &[Diff3Entry]) -> String {
+    let mut output = String::new();
+
+    let mut ours_count = 0;
+    let mut theirs_count = 0;
+    let mut both_count = 0;
+    let mut conflict_count = 0;
+
+    let show = |key: &str, value: &Option<String>| match value {
+        Some(value) => display_key_value(key, value).into_owned(),
+        None => "(unset)".to_string(),
+    };
+    // `base -> new`, marked when masking hides the difference
+    let change = |key: &str, base: &Option<String>, new: &Option<String>| match (base, new) {
+        (Some(base), Some(new)) => {
+            let (base, new) = display_change(key, base, new);
+            format!("{} -> {}", base, new)
+        }
+        _ => format!("{} -> {}", show(key, base), show(key, new)),
+    };
+
+    for entry in entries {
+        match entry.status {
+            Diff3Status::Unchanged => {}
+            Diff3Status::Ours => {
+                output.push_str(&format!(
+                    "< {}: {}\n",
+                    entry.key.to_string().green(),
+                    change(&entry.key, &entry.base, &entry.ours)
+                ));
+                ours_count += 1;
+            }
+            Diff3Status::Theirs => {
+                output.push_str(&format!(
+                    "> {}: {}\n",
+                    entry.key.to_string().cyan(),
+                    change(&entry.key, &entry.base, &entry.theirs)
+                ));
+                theirs_count += 1;
+            }
+            Diff3Status::Both => {
+                output.push_str(&format!(
+                    "= {}: {}\n",
+                    entry.key.to_string().yellow(),
+                    change(&entry.key, &entry.base, &entry.ours)
+                ));
+                both_count += 1;
+            }
+            Diff3Status::Conflict => {
+                output.push_str(&format!(
+                    "{} {}:\n",
+                    "!".red().bold(),
+                    entry.key.to_string().red().bold()
+                ));
+                output.push_str(&format!("    base:   {}\n", show(&entry.key, &entry.base)));
+                output.push_str(&format!("    ours:   {}\n", show(&entry.key, &entry.ours)));
+                output.push_str(&format!(
+                    "    theirs: {}\n",
+                    show(&entry.key, &entry.theirs)
+                ));
+                conflict_count += 1;
+            }
+        }
     }
 
+    output.push_str(&format!(
+        "\n{} ours, {} theirs, {} both, {} conflicts\n",
+        ours_count, theirs_count, both_count, conflict_count
+    ));
+
     output
 }
 
@@ -106,13 +670,13 @@ fn exports_for_diffs(diffs: &[EnvDiff]) -> Vec<String> {
     for diff in diffs {
         match diff {
             EnvDiff::Removed { key, old_value } => {
-                commands.push(export_line(key, old_value));
+                commands.push(shell::export_line(key, old_value));
             }
             EnvDiff::Changed { key, old_value, .. } => {
-                commands.push(export_line(key, old_value));
+                commands.push(shell::export_line(key, old_value));
             }
             EnvDiff::Added { key, .. } => {
-                commands.push(format!("unset {}", key));
+                commands.push(shell::unset_line(key));
             }
             EnvDiff::Unchanged { .. } => {}
         }
@@ -121,7 +685,51 @@ fn exports_for_diffs(diffs: &[EnvDiff]) -> Vec<String> {
     commands
 }
 
-fn export_line(key: &str, value: &str) -> String {
-    let escaped = value.replace('"', "\\\"");
-    format!("export {}=\"{}\"", key, escaped)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use envhist_core::MemoryBackend;
+
+    fn snapshot(name: &str, age_secs: i64, keys_only: bool) -> Snapshot {
+        Snapshot {
+            name: name.to_string(),
+            created_at: Utc::now() - Duration::seconds(age_secs),
+            description: None,
+            environment: Env::from([("A".to_string(), name.to_string())]),
+            tags: Vec::new(),
+            session_id: None,
+            keys_only,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_latest_snapshot() {
+        let backend = MemoryBackend::new();
+        assert!(latest_snapshot(&backend, None).is_err());
+
+        backend
+            .save_snapshot(&snapshot("old", 60, false), None)
+            .unwrap();
+        backend
+            .save_snapshot(&snapshot("new", 0, false), None)
+            .unwrap();
+        assert_eq!(latest_snapshot(&backend, None).unwrap().name, "new");
+    }
+
+    #[test]
+    fn test_load_side() {
+        let backend = MemoryBackend::new();
+        let config = Config::default();
+        backend
+            .save_snapshot(&snapshot("shared", 0, true), None)
+            .unwrap();
+
+        let (env, name, keys_only) = load_side(&backend, &config, "shared", None).unwrap();
+        assert_eq!(env.get("A").map(String::as_str), Some("shared"));
+        assert_eq!(name, "shared");
+        assert!(keys_only);
+        assert!(load_side(&backend, &config, "missing", None).is_err());
+    }
 }
@@ -0,0 +1,212 @@
+use crate::shell::ShellKind;
+use crate::{daemon_client, ExportArgs, ImportArgs};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use clap::ValueEnum;
+use envhist_core::{
+    envfile,
+    storage::{Failures, Snapshot, SnapshotBundle, Storage, StoreExport},
+    Env,
+};
+use std::path::{Path, PathBuf};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// KEY=value lines for .env loaders
+    #[default]
+    Dotenv,
+    /// Ready-to-source export lines for the shell chosen with --shell
+    Shell,
+    Json,
+    Yaml,
+    /// The snapshot with its metadata in a versioned JSON bundle that
+    /// `envhist import` reads back on another host; only tracked variables
+    /// are kept
+    Bundle,
+}
+
+pub fn export(args: ExportArgs) -> Result<()> {
+    let storage = Storage::new()?;
+    if args.format == ExportFormat::Bundle {
+        return export_bundle(&storage, args);
+    }
+
+    let Some(name) = args.name else {
+        let mut failures = Failures::new(args.keep_going);
+        let export = storage.export_all_with(&mut failures)?;
+        let content = serde_json::to_string_pretty(&export)? + "\n";
+        match args.output {
+            Some(path) => {
+                std::fs::write(&path, content)
+                    .with_context(|| format!("Failed to write export to {:?}", path))?;
+                println!(
+                    "✓ Exported {} snapshots and {} sessions to {}",
+                    export.snapshots.len(),
+                    export.sessions.len(),
+                    path.display()
+                );
+            }
+            None => print!("{}", content),
+        }
+        return super::check_failures(&failures);
+    };
+
+    let session = daemon_client::get_active_session().ok().flatten();
+    let snapshot = storage.load_snapshot(&name, session.as_ref())?;
+    if snapshot.keys_only {
+        anyhow::bail!(
+            "Snapshot '{}' is keys-only; it has no values to export (--format bundle copies it as is)",
+            name
+        );
+    }
+
+    let content = render(&snapshot.environment, args.format, args.shell)?;
+
+    match args.output {
+        Some(path) => {
+            std::fs::write(&path, content)
+                .with_context(|| format!("Failed to write export to {:?}", path))?;
+            println!("✓ Exported snapshot {} to {}", name, path.display());
+        }
+        None => print!("{}", content),
+    }
+
+    Ok(())
+}
+
+/// Write one snapshot, or with `--all` every listed snapshot, as a
+/// [`SnapshotBundle`] file.
+fn export_bundle(storage: &Storage, args: ExportArgs) -> Result<()> {
+    let session = daemon_client::get_active_session().ok().flatten();
+    let (snapshots, default_path) = match args.name {
+        Some(ref name) => (
+            vec![storage.load_snapshot(name, session.as_ref())?],
+            format!("{}.envhist.json", name),
+        ),
+        None => (
+            storage.list_snapshots(session.as_ref())?,
+            "snapshots.envhist.json".to_string(),
+        ),
+    };
+
+    let bundle = SnapshotBundle::new(snapshots, storage.config());
+    let path = args.output.unwrap_or_else(|| PathBuf::from(default_path));
+    std::fs::write(&path, serde_json::to_string_pretty(&bundle)? + "\n")
+        .with_context(|| format!("Failed to write bundle to {:?}", path))?;
+    println!(
+        "✓ Exported {} snapshot(s) to {}",
+        bundle.snapshots.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn render(env: &Env, format: ExportFormat, shell: ShellKind) -> Result<String> {
+    let sorted = envfile::normalize(env);
+
+    Ok(match format {
+        ExportFormat::Dotenv => envfile::to_dotenv(env),
+        ExportFormat::Shell => sorted
+            .iter()
+            .map(|(key, value)| shell.export_line(key, value) + "\n")
+            .collect(),
+        ExportFormat::Json => serde_json::to_string_pretty(&sorted)? + "\n",
+        // JSON strings are valid YAML double-quoted scalars
+        ExportFormat::Yaml => sorted
+            .iter()
+            .map(|(key, value)| Ok(format!("{}: {}\n", key, serde_json::to_string(value)?)))
+            .collect::<Result<String>>()?,
+        ExportFormat::Bundle => unreachable!("bundles are written by export_bundle"),
+    })
+}
+
+pub fn import(args: ImportArgs) -> Result<()> {
+    let content = std::fs::read_to_string(&args.file)
+        .with_context(|| format!("Failed to read {:?}", args.file))?;
+
+    if args.all {
+        let export: StoreExport = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse store export {:?}", args.file))?;
+        let summary = Storage::new()?.import_all(&export)?;
+        println!(
+            "✓ Restored config, {} snapshots and {} sessions ({} already present, skipped)",
+            summary.snapshots, summary.sessions, summary.skipped
+        );
+        return Ok(());
+    }
+
+    if let Ok(bundle) = serde_json::from_str::<SnapshotBundle>(&content) {
+        return import_bundle(bundle, args.name);
+    }
+    if serde_json::from_str::<StoreExport>(&content).is_ok() {
+        anyhow::bail!(
+            "{:?} is a whole-store export; pass --all to restore it",
+            args.file
+        );
+    }
+
+    let environment = if content.trim_start().starts_with('{') {
+        let parsed = match serde_json::from_str::<Snapshot>(&content) {
+            Ok(snapshot) => snapshot.environment,
+            Err(_) => serde_json::from_str::<Env>(&content)
+                .with_context(|| format!("Failed to parse JSON from {:?}", args.file))?,
+        };
+        envfile::normalize(&parsed).into_iter().collect()
+    } else {
+        envfile::parse_env_file(&content)
+            .with_context(|| format!("Failed to parse {:?}", args.file))?
+    };
+
+    let name = match args.name {
+        Some(name) => name,
+        None => Path::new(&args.file)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(|s| s.trim_start_matches('.').to_string())
+            .filter(|s| !s.is_empty())
+            .context("Cannot derive a snapshot name from the file; pass --name")?,
+    };
+
+    let snapshot = Snapshot {
+        name: name.clone(),
+        created_at: Utc::now(),
+        description: Some(format!("Imported from {}", args.file.display())),
+        environment,
+        tags: Vec::new(),
+        session_id: None,
+        keys_only: false,
+        expires_at: None,
+    };
+
+    let storage = Storage::new()?;
+    storage.ensure_directories()?;
+    storage.save_snapshot(&snapshot, None)?;
+    println!(
+        "✓ Imported {} variables as snapshot: {}",
+        snapshot.environment.len(),
+        name
+    );
+
+    Ok(())
+}
+
+/// Save a bundle's snapshots under their own names, or the one snapshot in
+/// it under `name`.
+fn import_bundle(mut bundle: SnapshotBundle, name: Option<String>) -> Result<()> {
+    if let Some(name) = name {
+        let [snapshot] = bundle.snapshots.as_mut_slice() else {
+            anyhow::bail!(
+                "--name needs a bundle with one snapshot; this one has {}",
+                bundle.snapshots.len()
+            );
+        };
+        snapshot.name = name;
+    }
+
+    let summary = Storage::new()?.import_bundle(&bundle)?;
+    println!(
+        "✓ Imported {} snapshot(s) ({} already present, skipped)",
+        summary.snapshots, summary.skipped
+    );
+    Ok(())
+}
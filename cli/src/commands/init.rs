@@ -1,8 +1,8 @@
 use crate::daemon_client;
-use crate::shell::zsh;
+use crate::shell::{zsh, ShellKind};
 use anyhow::{Context, Result};
 use envhist_core::Config;
-use envhist_daemon::EnvEvent;
+use envhist_daemon::{EnvEvent, EnvResponse};
 use std::process::{Command, Stdio};
 
 pub fn init(check: bool) -> Result<()> {
@@ -21,7 +21,7 @@ pub fn init(check: bool) -> Result<()> {
         .context("Failed to find home directory")?
         .join(".zshrc");
 
-    zsh::install_hooks(&zshrc_path)
+    zsh::install_hooks(&zshrc_path, storage.config().core.capture_on_cd)
         .with_context(|| format!("Failed to install hooks to {:?}", zshrc_path))?;
 
     println!("✓ Installed shell hooks to ~/.zshrc");
@@ -40,6 +40,13 @@ pub fn init(check: bool) -> Result<()> {
     Ok(())
 }
 
+/// For dotfile managers: `eval "$(envhist init --print-hook)"` instead of
+/// letting `init` edit the rc file.
+pub fn print_hook(shell: ShellKind) -> Result<()> {
+    print!("{}", shell.init_script(Config::load()?.core.capture_on_cd));
+    Ok(())
+}
+
 fn check_installation() -> Result<()> {
     let zshrc_path = dirs::home_dir()
         .context("Failed to find home directory")?
@@ -64,7 +71,7 @@ fn check_installation() -> Result<()> {
 
 fn is_daemon_running() -> Result<bool> {
     let socket_path = Config::daemon_socket_path();
-    Ok(socket_path.exists())
+    Ok(socket_path.exists() && !daemon_client::is_stale_socket(&socket_path))
 }
 
 pub fn start_daemon() -> Result<()> {
@@ -102,52 +109,125 @@ pub fn run_daemon() -> Result<()> {
 }
 
 pub fn stop_daemon() -> Result<()> {
-    // Find daemon process and kill it
-    let socket_path = Config::daemon_socket_path();
-    if !socket_path.exists() {
+    if !Config::daemon_socket_path().exists() {
         println!("Daemon is not running");
         return Ok(());
     }
 
-    // Try to find the daemon process
-    let output = Command::new("lsof")
-        .arg("-t")
-        .arg(socket_path.to_string_lossy().as_ref())
-        .output()?;
-
-    if output.stdout.is_empty() {
-        println!("Could not find daemon process");
-        return Ok(());
+    match daemon_pid()? {
+        Some(pid) => {
+            Command::new("kill").arg(pid.to_string()).output()?;
+            println!("✓ Stopped daemon (PID: {})", pid);
+        }
+        None => println!("Could not find daemon process"),
     }
 
-    let pid_str = String::from_utf8(output.stdout)?.trim().to_string();
+    Ok(())
+}
 
-    if let Ok(pid) = pid_str.parse::<u32>() {
+pub fn restart_daemon() -> Result<()> {
+    if let Some(pid) = daemon_pid()? {
         Command::new("kill").arg(pid.to_string()).output()?;
+        if !wait_for(|| !process_alive(pid)) {
+            anyhow::bail!("Daemon (PID: {}) did not exit; not starting a new one", pid);
+        }
         println!("✓ Stopped daemon (PID: {})", pid);
     }
 
+    // The old daemon leaves its socket file behind when killed, so clear it
+    // before waiting for the new one to create its own
+    let socket_path = Config::daemon_socket_path();
+    if socket_path.exists() {
+        std::fs::remove_file(&socket_path)
+            .with_context(|| format!("Failed to remove stale socket {:?}", socket_path))?;
+    }
+
+    start_daemon_and_report()
+}
+
+pub fn repair_daemon() -> Result<()> {
+    let socket_path = Config::daemon_socket_path();
+
+    if daemon_client::is_stale_socket(&socket_path) {
+        std::fs::remove_file(&socket_path)
+            .with_context(|| format!("Failed to remove stale socket {:?}", socket_path))?;
+        println!("✓ Removed stale socket {:?}", socket_path);
+    } else if let Some(pid) = daemon_pid()? {
+        println!("✓ Daemon is running (PID: {})", pid);
+        return Ok(());
+    }
+
+    start_daemon_and_report()
+}
+
+fn start_daemon_and_report() -> Result<()> {
+    let socket_path = Config::daemon_socket_path();
+    start_daemon()?;
+
+    let mut pid = None;
+    wait_for(|| {
+        pid = daemon_pid().ok().flatten();
+        pid.is_some()
+    });
+    match pid {
+        Some(pid) => println!("✓ Started daemon (PID: {})", pid),
+        None => anyhow::bail!("Daemon did not start listening on {:?}", socket_path),
+    }
+
     Ok(())
 }
 
+/// PID of the process holding the daemon socket, if any
+fn daemon_pid() -> Result<Option<u32>> {
+    let socket_path = Config::daemon_socket_path();
+    if !socket_path.exists() {
+        return Ok(None);
+    }
+
+    let output = Command::new("lsof")
+        .arg("-t")
+        .arg(socket_path.to_string_lossy().as_ref())
+        .output()
+        .context("Failed to run lsof")?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .and_then(|line| line.trim().parse().ok()))
+}
+
+fn process_alive(pid: u32) -> bool {
+    Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Polls `done` for up to five seconds, returning whether it became true
+fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+    for _ in 0..100 {
+        if done() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    done()
+}
+
 pub fn daemon_status() -> Result<()> {
     let socket_path = Config::daemon_socket_path();
 
-    if socket_path.exists() {
+    if daemon_client::is_stale_socket(&socket_path) {
+        println!("✗ Daemon is not running (stale socket {:?})", socket_path);
+        println!("  Run `envhist daemon repair` to clean up and restart it");
+    } else if socket_path.exists() {
         println!("✓ Daemon is running");
         println!("  Socket: {:?}", socket_path);
 
-        // Try to get PID
-        let output = Command::new("lsof")
-            .arg("-t")
-            .arg(socket_path.to_string_lossy().as_ref())
-            .output();
-
-        if let Ok(output) = output {
-            if !output.stdout.is_empty() {
-                let pid = String::from_utf8(output.stdout)?.trim().to_string();
-                println!("  PID: {}", pid);
-            }
+        if let Ok(Some(pid)) = daemon_pid() {
+            println!("  PID: {}", pid);
         }
     } else {
         println!("✗ Daemon is not running");
@@ -156,22 +236,98 @@ pub fn daemon_status() -> Result<()> {
     Ok(())
 }
 
-pub fn send_set(pid: u32, key: String, value: String) -> Result<()> {
+/// Silent on success; on failure prints one line to stderr and exits 1.
+pub fn healthcheck(timeout_ms: u64) -> Result<()> {
+    let timeout = std::time::Duration::from_millis(timeout_ms);
+    let problem = match daemon_client::send_event_with_timeout(EnvEvent::Ping, timeout) {
+        Ok(Some(EnvResponse::Ok)) => return Ok(()),
+        Ok(Some(response)) => format!("unexpected response {:?}", response),
+        Ok(None) => "daemon is not running".to_string(),
+        Err(e) => format!("{:#}", e),
+    };
+    eprintln!("unhealthy: {}", problem);
+    Err(super::failed(problem))
+}
+
+pub fn send_set(pid: u32, key: String, value: String, echo: bool) -> Result<()> {
     let event = EnvEvent::Set { pid, key, value };
+    if echo {
+        return echo_event(&event);
+    }
     let _ = daemon_client::send_event(event)?;
     Ok(())
 }
 
-pub fn send_unset(pid: u32, key: String) -> Result<()> {
+pub fn send_unset(pid: u32, key: String, echo: bool) -> Result<()> {
     let event = EnvEvent::Unset { pid, key };
+    if echo {
+        return echo_event(&event);
+    }
     let _ = daemon_client::send_event(event)?;
     Ok(())
 }
 
-pub fn send_capture(pid: u32) -> Result<()> {
+pub fn send_capture(pid: u32, echo: bool) -> Result<()> {
     use envhist_core::Env;
     let env: Env = std::env::vars().collect();
+    if echo {
+        return echo_event(&EnvEvent::Capture { pid, env });
+    }
+    if Config::load()?.core.debounce_captures {
+        return daemon_client::send_capture_debounced(pid, env);
+    }
     let event = EnvEvent::Capture { pid, env };
     let _ = daemon_client::send_event(event)?;
     Ok(())
 }
+
+pub fn send_chdir(pid: u32, dir: String, echo: bool) -> Result<()> {
+    use envhist_core::Env;
+    let env: Env = std::env::vars().collect();
+    let event = EnvEvent::Chdir { pid, dir, env };
+    if echo {
+        return echo_event(&event);
+    }
+    let _ = daemon_client::send_event(event)?;
+    Ok(())
+}
+
+pub fn send_set_many(pid: u32, echo: bool) -> Result<()> {
+    use envhist_core::Env;
+    let vars: Env = std::env::vars().collect();
+    let event = EnvEvent::SetMany { pid, vars };
+    if echo {
+        return echo_event(&event);
+    }
+    let _ = daemon_client::send_event(event)?;
+    Ok(())
+}
+
+/// `--echo` for the send-* commands: print the event as it would go over
+/// the socket, and what the daemon's filters would make of it, without
+/// sending anything.
+fn echo_event(event: &EnvEvent) -> Result<()> {
+    println!("{}", serde_json::to_string(event)?);
+
+    let config = Config::load()?;
+    match event {
+        EnvEvent::Set { key, .. } | EnvEvent::Unset { key, .. } => {
+            let decision = config.should_track_explained(key);
+            let verdict = if decision.track {
+                "tracked"
+            } else {
+                "not tracked"
+            };
+            eprintln!("{}: {}", key, verdict);
+            eprintln!("  rule: {}", decision.reason);
+        }
+        EnvEvent::Capture { env, .. }
+        | EnvEvent::Chdir { env, .. }
+        | EnvEvent::SetMany { vars: env, .. } => {
+            let tracked = env.keys().filter(|key| config.should_track(key)).count();
+            eprintln!("{} of {} variable(s) tracked", tracked, env.len());
+        }
+        _ => {}
+    }
+    Ok(())
+}
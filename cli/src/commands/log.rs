@@ -1,29 +1,66 @@
-use crate::daemon_client;
-use anyhow::Result;
+use crate::format::{
+    description_suffix, display_change, display_key_value, display_value, group_by_prefix,
+    is_masked, terminal_width, GroupBy, Table, TimestampFormat,
+};
+use crate::{daemon_client, shell, LogArgs};
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use envhist_core::{session::Session, storage::Storage, storage::TimelineEntry};
+use colored::*;
+use envhist_core::{
+    differ::diff_list,
+    duration::{parse_at, parse_since},
+    secrets,
+    session::Session,
+    storage::Storage,
+    storage::TimelineEntry,
+    timeline::{change_counts, detect_renames, follow, value_at, TimelineItem, ValueAt},
+    Catalog, Config, StorageBackend,
+};
+use envhist_daemon::{EnvEvent, EnvResponse};
+use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::process;
 
-pub fn log(since: Option<String>, grep: Option<String>) -> Result<()> {
+pub fn log(args: LogArgs) -> Result<()> {
     let storage = Storage::new()?;
-    let pid = process::id();
-
-    // Try to get session for this PID
-    let session = get_session_for_pid(pid)?;
-    let entries = storage.read_timeline(&session)?;
+    let since = since_cutoff(&storage, args.since.as_deref())?;
+    if args.graph {
+        return print_graph(&storage, &args, since);
+    }
 
-    let filtered_entries: Vec<&TimelineEntry> = entries
+    let (entries, owners) = if args.all_sessions {
+        let sessions = storage.list_sessions()?;
+        merged_timeline(&storage, &sessions, args.session_tag.as_deref())?
+    } else {
+        // Try to get session for this PID
+        let session = get_session_for_pid(process::id())?;
+        (storage.read_timeline(&session)?, Vec::new())
+    };
+    let entries: Vec<LogEntry> = entries
         .iter()
-        .filter(|entry| {
+        .enumerate()
+        .map(|(i, entry)| LogEntry {
+            entry,
+            session: owners.get(i).map(String::as_str),
+        })
+        .collect();
+
+    // Followed before the time filter so earlier values still seed derivations
+    let entries: Vec<LogEntry> = match args.follow {
+        Some(ref key) => follow(entries, key, args.derivations),
+        None => entries,
+    };
+
+    let filtered_entries: Vec<LogEntry> = entries
+        .into_iter()
+        .filter(|LogEntry { entry, .. }| {
             // Filter by since
-            if let Some(ref since_str) = since {
-                if !matches_since(entry.timestamp, since_str) {
-                    return false;
-                }
+            if since.is_some_and(|cutoff| entry.timestamp < cutoff) {
+                return false;
             }
 
             // Filter by grep
-            if let Some(ref pattern) = grep {
+            if let Some(ref pattern) = args.grep {
                 if !entry.key.contains(pattern) {
                     return false;
                 }
@@ -38,79 +75,601 @@ pub fn log(since: Option<String>, grep: Option<String>) -> Result<()> {
         return Ok(());
     }
 
-    for entry in filtered_entries {
-        let action_str = match entry.action {
-            envhist_core::storage::Action::Set => "SET",
-            envhist_core::storage::Action::Unset => "UNSET",
-        };
+    if args.stat {
+        let entries: Vec<&TimelineEntry> = filtered_entries.iter().map(|e| e.entry).collect();
+        print_stat(&entries);
+        return Ok(());
+    }
 
-        let value_str = if let Some(ref v) = entry.value {
-            format!(" = {}", v)
-        } else {
-            String::new()
-        };
+    let items: Vec<TimelineItem<LogEntry>> = if args.detect_renames {
+        detect_renames(
+            filtered_entries,
+            chrono::Duration::seconds(RENAME_WINDOW_SECS),
+        )
+    } else {
+        filtered_entries
+            .into_iter()
+            .map(TimelineItem::Entry)
+            .collect()
+    };
 
-        println!(
-            "[{}] {} {} {}{}",
-            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
-            action_str,
-            entry.key,
-            value_str,
-            if let Some(ref prev) = entry.prev {
-                format!(" (was: {})", prev)
-            } else {
-                String::new()
+    // First pass for --hot: how often each shown variable changes
+    let counts = args
+        .hot
+        .then(|| change_counts(items.iter().flat_map(item_entries).map(|e| e.entry)));
+    let hot = counts.as_ref();
+
+    let width = terminal_width();
+    let catalog = Catalog::load();
+    match args.group_by {
+        Some(GroupBy::Prefix) => {
+            let prefixes = &storage.config().display.group_prefixes;
+            for (group, items) in group_by_prefix(items, |item| item.key(), prefixes) {
+                println!("{}", format!("[{}]", group).bold());
+                print_log_items(&items, args.timestamp, args.seq, hot, 2, width, &catalog);
+                println!();
             }
-        );
+        }
+        None => print_log_items(&items, args.timestamp, args.seq, hot, 0, width, &catalog),
+    }
+
+    Ok(())
+}
+
+/// A timeline entry as `log` passes it along, with the short id of the
+/// session it came from when several sessions are merged.
+#[derive(Clone, Copy)]
+struct LogEntry<'a> {
+    entry: &'a TimelineEntry,
+    session: Option<&'a str>,
+}
+
+impl Borrow<TimelineEntry> for LogEntry<'_> {
+    fn borrow(&self) -> &TimelineEntry {
+        self.entry
+    }
+}
+
+/// The timelines of `sessions` (those tagged `tag`, if given) in time
+/// order, with the short id of each entry's session alongside.
+fn merged_timeline(
+    storage: &dyn StorageBackend,
+    sessions: &[Session],
+    tag: Option<&str>,
+) -> Result<(Vec<TimelineEntry>, Vec<String>)> {
+    let mut entries = Vec::new();
+    for session in sessions {
+        if tag.is_none_or(|tag| session.tags.iter().any(|t| t == tag)) {
+            let short_id = session.short_id();
+            for entry in storage.read_timeline(session)? {
+                entries.push((entry, short_id.clone()));
+            }
+        }
+    }
+    entries.sort_by_key(|(e, _)| (e.timestamp, e.seq));
+    Ok(entries.into_iter().unzip())
+}
+
+/// One aligned row per item, each followed by its note if it has one.
+/// With `hot` change counts, rows lead with a badge colored by how busy
+/// the variable is; entries from a merged timeline show their session after
+/// the timestamp.
+fn print_log_items(
+    items: &[TimelineItem<LogEntry>],
+    timestamp: TimestampFormat,
+    seq: bool,
+    hot: Option<&HashMap<String, usize>>,
+    indent: usize,
+    width: Option<usize>,
+    catalog: &Catalog,
+) {
+    let busiest = hot.and_then(|counts| counts.values().max().copied());
+    let mut table = Table::new().indent(indent);
+    for item in items {
+        let mut row = log_item_row(item, timestamp);
+        let last = *item_entries(item).last().expect("items hold an entry");
+        if let Some(session) = last.session {
+            row.insert(1, session.dimmed().to_string());
+        }
+        if let (Some(counts), Some(busiest)) = (hot, busiest) {
+            let count = counts.get(item.key()).copied().unwrap_or(0);
+            row.insert(0, hot_badge(count, busiest));
+        }
+        if seq {
+            row.insert(0, format!("#{}", item.seq()).dimmed().to_string());
+        }
+        if let Some(last) = row.last_mut() {
+            last.push_str(&description_suffix(catalog, item.key()));
+        }
+        table.push(row);
+    }
+
+    for (line, item) in table.render(width).into_iter().zip(items) {
+        println!("{}", line);
+        if let Some(note) = item_note(item) {
+            print_note(indent, note);
+        }
+    }
+}
+
+/// A note under its entry, indented the same in `log` and `show`.
+fn print_note(indent: usize, note: &str) {
+    println!(
+        "{:width$}{} {}",
+        "",
+        "note:".dimmed(),
+        note,
+        width = indent + 6
+    );
+}
+
+/// Colors cycled through for the lanes of `log --graph`.
+const LANE_COLORS: [Color; 6] = [
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Red,
+];
+
+/// `log --graph`: every session with matching entries is a lane, drawn
+/// from its first shown entry to its last, and entries are plotted on their
+/// session's lane in time order.
+fn print_graph(storage: &Storage, args: &LogArgs, since: Option<DateTime<Utc>>) -> Result<()> {
+    let mut sessions = storage.list_sessions()?;
+    sessions.retain(|session| {
+        args.session_tag
+            .as_ref()
+            .is_none_or(|tag| session.tags.contains(tag))
+    });
+
+    let mut timelines = Vec::new();
+    for session in sessions {
+        let entries: Vec<TimelineEntry> = storage
+            .read_timeline(&session)?
+            .into_iter()
+            .filter(|entry| since.is_none_or(|cutoff| entry.timestamp >= cutoff))
+            .filter(|entry| {
+                args.grep
+                    .as_ref()
+                    .is_none_or(|pattern| entry.key.contains(pattern))
+            })
+            .collect();
+        if let Some(first) = entries.iter().map(|entry| entry.timestamp).min() {
+            timelines.push((first, session, entries));
+        }
+    }
+    // Lanes open left to right in the order sessions first show up
+    timelines.sort_by_key(|(first, session, _)| (*first, session.started_at));
+
+    let mut lanes = Vec::new();
+    let mut rows: Vec<(TimelineEntry, usize)> = Vec::new();
+    for (_, session, entries) in timelines {
+        rows.extend(entries.into_iter().map(|entry| (entry, lanes.len())));
+        lanes.push(session);
+    }
+    rows.sort_by_key(|(entry, lane)| (entry.timestamp, *lane, entry.seq));
+
+    if rows.is_empty() {
+        println!("No timeline entries found.");
+        return Ok(());
+    }
+
+    let last_row: Vec<usize> = (0..lanes.len())
+        .map(|lane| rows.iter().rposition(|(_, l)| *l == lane).unwrap_or(0))
+        .collect();
+    let mut started = vec![false; lanes.len()];
+    // The lane glyphs for one row: `mark` on `lane`, `|` on lanes still open
+    let graph = |started: &[bool], row: usize, lane: usize, mark: &str| -> String {
+        (0..started.len())
+            .map(|l| {
+                if l == lane {
+                    mark.color(LANE_COLORS[l % LANE_COLORS.len()]).to_string()
+                } else if started[l] && row <= last_row[l] {
+                    "|".color(LANE_COLORS[l % LANE_COLORS.len()]).to_string()
+                } else {
+                    " ".to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut table = Table::new();
+    for (row, (entry, lane)) in rows.iter().enumerate() {
+        if !started[*lane] {
+            let session = &lanes[*lane];
+            let mut label = format!("session {} ({})", session.short_id(), session.shell);
+            // Sessions without metadata have no known start
+            if session.started_at != DateTime::<Utc>::MIN_UTC {
+                label.push_str(&format!(
+                    " started {}",
+                    args.timestamp.format(session.started_at)
+                ));
+            }
+            table.push(vec![
+                graph(&started, row, *lane, "+"),
+                label.dimmed().to_string(),
+            ]);
+            started[*lane] = true;
+        }
+
+        let mut cells = vec![graph(&started, row, *lane, "*")];
+        cells.extend(log_entry_row(entry, args.timestamp));
+        table.push(cells);
+    }
+    for line in table.render(terminal_width()) {
+        println!("{}", line);
     }
 
     Ok(())
 }
 
-pub fn show(var_name: String) -> Result<()> {
+/// `×N`, red for the busiest third of variables and yellow for the middle.
+fn hot_badge(count: usize, busiest: usize) -> String {
+    let badge = format!("×{}", count);
+    if count * 3 > busiest * 2 {
+        badge.red().bold().to_string()
+    } else if count * 3 > busiest {
+        badge.yellow().to_string()
+    } else {
+        badge.dimmed().to_string()
+    }
+}
+
+/// The timeline entries an item was built from.
+fn item_entries<E: Copy>(item: &TimelineItem<E>) -> Vec<E> {
+    match item {
+        TimelineItem::Entry(entry) => vec![*entry],
+        TimelineItem::Rename { from, to } => vec![*from, *to],
+    }
+}
+
+/// Variables by change count, busiest first.
+fn print_stat(entries: &[&TimelineEntry]) {
+    let mut counts: Vec<(String, usize)> =
+        change_counts(entries.iter().copied()).into_iter().collect();
+    counts.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+
+    let mut table = Table::new();
+    for (key, count) in &counts {
+        table.push(vec![count.to_string(), key.clone()]);
+    }
+    for line in table.render(terminal_width()) {
+        println!("{}", line);
+    }
+
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    println!();
+    println!("{} change(s) across {} variable(s)", total, counts.len());
+}
+
+/// How far apart an unset and a set may be to count as a rename.
+const RENAME_WINDOW_SECS: i64 = 10;
+
+fn item_note<'a>(item: &TimelineItem<LogEntry<'a>>) -> Option<&'a str> {
+    match item {
+        TimelineItem::Entry(e) => e.entry.note.as_deref(),
+        TimelineItem::Rename { from, to } => {
+            to.entry.note.as_deref().or(from.entry.note.as_deref())
+        }
+    }
+}
+
+/// Columns: timestamp, action, key, value.
+fn log_item_row(item: &TimelineItem<LogEntry>, timestamp: TimestampFormat) -> Vec<String> {
+    match *item {
+        TimelineItem::Entry(e) => log_entry_row(e.entry, timestamp),
+        TimelineItem::Rename { from, to } => {
+            let (from, to) = (from.entry, to.entry);
+            vec![
+                format!("[{}]", timestamp.format(item.timestamp())),
+                "RENAME".yellow().to_string(),
+                format!("{} -> {}", from.key, to.key),
+                format!(
+                    "= {}",
+                    display_key_value(&to.key, to.value.as_deref().unwrap_or_default())
+                ),
+            ]
+        }
+    }
+}
+
+fn log_entry_row(entry: &TimelineEntry, timestamp: TimestampFormat) -> Vec<String> {
+    let time = format!("[{}]", timestamp.format(entry.timestamp));
+    let action = match entry.action {
+        envhist_core::storage::Action::Set => "SET".green(),
+        envhist_core::storage::Action::Unset => "UNSET".red(),
+        envhist_core::storage::Action::Chdir => {
+            return vec![
+                time,
+                "CD".dimmed().to_string(),
+                format!(
+                    "entered {}",
+                    display_dir(entry.value.as_deref().unwrap_or_default())
+                ),
+            ];
+        }
+        envhist_core::storage::Action::Baseline => {
+            return vec![
+                time,
+                "BASELINE".dimmed().to_string(),
+                "session baseline captured".to_string(),
+            ];
+        }
+    };
+
+    let (shown, shown_prev) = shown_values(entry);
+    let value = [
+        shown.map(|v| format!("= {}", v)),
+        shown_prev.map(|prev| format!("(was: {})", prev)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" ");
+
+    vec![time, action.to_string(), entry.key.clone(), value]
+}
+
+/// An entry's value and previous value for display, masked per
+/// `display.mask_patterns`.
+fn shown_values(entry: &TimelineEntry) -> (Option<String>, Option<String>) {
+    match (&entry.value, &entry.prev) {
+        (Some(value), Some(prev)) => {
+            let (prev, value) = display_change(&entry.key, prev, value);
+            (Some(value), Some(prev))
+        }
+        (value, prev) => (
+            value
+                .as_deref()
+                .map(|v| display_key_value(&entry.key, v).into_owned()),
+            prev.as_deref()
+                .map(|v| display_key_value(&entry.key, v).into_owned()),
+        ),
+    }
+}
+
+/// Abbreviate the home directory as `~`.
+fn display_dir(dir: &str) -> String {
+    match dirs::home_dir() {
+        Some(home) => match std::path::Path::new(dir).strip_prefix(&home) {
+            Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+            Ok(rest) => format!("~/{}", rest.display()),
+            Err(_) => dir.to_string(),
+        },
+        None => dir.to_string(),
+    }
+}
+
+pub fn show(
+    var_name: String,
+    timestamp: TimestampFormat,
+    merge_sessions: bool,
+    seq: bool,
+    at: Option<String>,
+) -> Result<()> {
     let storage = Storage::new()?;
-    let pid = process::id();
 
-    let session = get_session_for_pid(pid)?;
-    let entries = storage.read_timeline(&session)?;
+    let sessions = if merge_sessions {
+        storage.list_sessions()?
+    } else {
+        vec![get_session_for_pid(process::id())?]
+    };
 
-    let var_entries: Vec<&TimelineEntry> = entries.iter().filter(|e| e.key == var_name).collect();
+    let var_entries = variable_history(&storage, &sessions, &var_name)?;
+
+    if let Some(at) = at {
+        let at = parse_at(&at, &storage.config().display.timezone, Utc::now())?;
+        match value_at(var_entries.iter().map(|(_, e)| e), &var_name, at) {
+            ValueAt::Set(value) => println!("{}", display_key_value(&var_name, &value)),
+            ValueAt::Unset => println!("(unset)"),
+            ValueAt::NeverSet => println!("(never set)"),
+        }
+        return Ok(());
+    }
 
     if var_entries.is_empty() {
         println!("No history found for variable: {}", var_name);
         return Ok(());
     }
 
-    println!("History for {}:", var_name);
-    for entry in var_entries {
+    let delimiter = storage.config().list_delimiter(&var_name);
+
+    let catalog = Catalog::load();
+    println!(
+        "History for {}:{}",
+        var_name,
+        description_suffix(&catalog, &var_name)
+    );
+    for (session, entry) in &var_entries {
         let action_str = match entry.action {
             envhist_core::storage::Action::Set => "SET",
             envhist_core::storage::Action::Unset => "UNSET",
+            envhist_core::storage::Action::Chdir => "CD",
+            envhist_core::storage::Action::Baseline => "BASELINE",
         };
 
-        let value_str = if let Some(ref v) = entry.value {
-            format!(" = {}", v)
+        let (shown, shown_prev) = shown_values(entry);
+        let value_str = shown.map(|v| format!(" = {}", v)).unwrap_or_default();
+
+        let mut markers = if merge_sessions {
+            format!(" {}", format!("({})", session.short_id()).dimmed())
         } else {
             String::new()
         };
+        if seq {
+            markers.push_str(&format!(" {}", format!("#{}", entry.seq).dimmed()));
+        }
 
         println!(
-            "  [{}] {} {}{}",
-            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            "  [{}]{} {} {}{}",
+            timestamp.format(entry.timestamp),
+            markers,
             action_str,
             value_str,
-            if let Some(ref prev) = entry.prev {
-                format!(" (was: {})", prev)
-            } else {
-                String::new()
+            shown_prev
+                .map(|prev| format!(" (was: {})", prev))
+                .unwrap_or_default()
+        );
+        if let Some(ref note) = entry.note {
+            print_note(0, note);
+        }
+
+        // List variables also show which elements the change touched, unless
+        // the values are masked
+        if let Some(delimiter) = delimiter.filter(|_| !is_masked(&var_name)) {
+            let list_diff = diff_list(
+                entry.prev.as_deref().unwrap_or_default(),
+                entry.value.as_deref().unwrap_or_default(),
+                delimiter,
+            );
+            for element in &list_diff.removed {
+                println!("      - {}", display_value(element));
+            }
+            for element in &list_diff.added {
+                println!("      + {}", display_value(element));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Every entry for `key` across `sessions`, with its session, in time order.
+fn variable_history<'a>(
+    storage: &dyn StorageBackend,
+    sessions: &'a [Session],
+    key: &str,
+) -> Result<Vec<(&'a Session, TimelineEntry)>> {
+    let mut entries = Vec::new();
+    for session in sessions {
+        for entry in storage.read_timeline(session)? {
+            if entry.key == key {
+                entries.push((session, entry));
             }
+        }
+    }
+    entries.sort_by_key(|(_, entry)| (entry.timestamp, entry.seq));
+    Ok(entries)
+}
+
+pub fn note(var_name: String, text: String) -> Result<()> {
+    let storage = Storage::new()?;
+    let session = get_session_for_pid(process::id())?;
+
+    let entry = storage.add_note(&session, &var_name, &text)?;
+    println!(
+        "✓ Noted {} change at {}",
+        var_name,
+        TimestampFormat::Human.format(entry.timestamp)
+    );
+
+    Ok(())
+}
+
+pub fn tag_session(tag: String) -> Result<()> {
+    let session = daemon_client::get_active_session()?
+        .context("No active session; is the daemon running? (envhist daemon start)")?;
+
+    match daemon_client::send_event(EnvEvent::TagSession {
+        pid: session.pid,
+        tag: tag.clone(),
+    })? {
+        Some(EnvResponse::Session { session }) => {
+            println!(
+                "✓ Tagged session {}: {}",
+                session.short_id(),
+                session.tags.join(", ")
+            );
+            Ok(())
+        }
+        Some(EnvResponse::Error { kind, message }) => {
+            anyhow::bail!("Daemon error tagging session ({:?}): {}", kind, message)
+        }
+        _ => anyhow::bail!("Daemon did not confirm tagging session with '{}'", tag),
+    }
+}
+
+pub fn replay(since: Option<String>, session_id: Option<String>) -> Result<()> {
+    let storage = Storage::new()?;
+
+    let session = match session_id {
+        Some(ref id) => storage.resolve_session(id)?,
+        None => get_session_for_pid(process::id())?,
+    };
+    let entries = storage.read_timeline(&session)?;
+    let since = since_cutoff(&storage, since.as_deref())?;
+
+    let (lines, unrecorded) = replay_lines(&entries, since);
+    if lines.is_empty() {
+        eprintln!("No timeline entries to replay.");
+        return Ok(());
+    }
+
+    println!("# envhist replay of session {}", session.id);
+    for line in lines {
+        println!("{}", line);
+    }
+    if !unrecorded.is_empty() {
+        eprintln!(
+            "⚠ Left out {} set(s) whose value was stored hashed or truncated: {}",
+            unrecorded.len(),
+            unrecorded.join(", ")
         );
     }
 
     Ok(())
 }
 
-fn get_session_for_pid(pid: u32) -> Result<Session> {
+/// One shell command per set or unset at or after `since`, commented with
+/// its time, and the variables of sets left out because only a hash or a
+/// truncated value was recorded; those appear as comments instead.
+fn replay_lines(
+    entries: &[TimelineEntry],
+    since: Option<DateTime<Utc>>,
+) -> (Vec<String>, Vec<String>) {
+    let mut unrecorded = Vec::new();
+    let lines = entries
+        .iter()
+        .filter(|entry| since.is_none_or(|cutoff| entry.timestamp >= cutoff))
+        .filter_map(|entry| {
+            let command = match entry.action {
+                envhist_core::storage::Action::Set => {
+                    let value = entry.value.as_deref().unwrap_or_default();
+                    let lost = if secrets::is_hashed(value) {
+                        Some("hashed")
+                    } else if Config::is_truncated(value) {
+                        Some("truncated")
+                    } else {
+                        None
+                    };
+                    match lost {
+                        Some(how) => {
+                            unrecorded.push(entry.key.clone());
+                            format!("# {}: value not recorded ({})", entry.key, how)
+                        }
+                        None => shell::export_line(&entry.key, value),
+                    }
+                }
+                envhist_core::storage::Action::Unset => shell::unset_line(&entry.key),
+                // Markers are context, not env changes
+                envhist_core::storage::Action::Chdir | envhist_core::storage::Action::Baseline => {
+                    return None
+                }
+            };
+            Some(format!(
+                "{}  # {}",
+                command,
+                entry.timestamp.format("%Y-%m-%d %H:%M:%S")
+            ))
+        })
+        .collect();
+    (lines, unrecorded)
+}
+
+pub(crate) fn get_session_for_pid(pid: u32) -> Result<Session> {
     if let Ok(Some(session)) = daemon_client::get_active_session() {
         return Ok(session);
     }
@@ -143,30 +702,178 @@ fn get_session_for_pid(pid: u32) -> Result<Session> {
     Ok(Session::new(pid, shell))
 }
 
-fn matches_since(timestamp: DateTime<Utc>, since: &str) -> bool {
-    // Simple parsing for "1 hour ago", "2 days ago", etc.
-    // For MVP, just check if timestamp is recent
-    let now = Utc::now();
-    let duration = now - timestamp;
+/// Earliest timestamp a `--since` filter keeps, resolved in `display.timezone`.
+fn since_cutoff(storage: &Storage, since: Option<&str>) -> Result<Option<DateTime<Utc>>> {
+    since
+        .map(|since| parse_since(since, &storage.config().display.timezone, Utc::now()))
+        .transpose()
+}
 
-    if since.contains("hour") {
-        if let Some(hours) = parse_number(since) {
-            return duration.num_hours() < hours as i64;
-        }
-    } else if since.contains("day") {
-        if let Some(days) = parse_number(since) {
-            return duration.num_days() < days as i64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use envhist_core::storage::Action;
+    use envhist_core::MemoryBackend;
+
+    #[test]
+    fn test_variable_history_merges_sessions() {
+        let backend = MemoryBackend::new();
+        let sessions = [
+            Session::new(1, "zsh".to_string()),
+            Session::new(2, "bash".to_string()),
+        ];
+        let start = Utc::now();
+        let entry = |key: &str, value: &str, secs: i64| {
+            let mut entry =
+                TimelineEntry::new(Action::Set, key.to_string(), Some(value.to_string()), None);
+            entry.timestamp = start + chrono::Duration::seconds(secs);
+            entry
+        };
+        backend
+            .append_timeline(&sessions[0], &entry("A", "1", 0))
+            .unwrap();
+        backend
+            .append_timeline(&sessions[0], &entry("A", "3", 20))
+            .unwrap();
+        backend
+            .append_timeline(&sessions[1], &entry("A", "2", 10))
+            .unwrap();
+        backend
+            .append_timeline(&sessions[1], &entry("B", "x", 15))
+            .unwrap();
+
+        let history = variable_history(&backend, &sessions, "A").unwrap();
+        let values: Vec<(u32, &str)> = history
+            .iter()
+            .map(|(session, entry)| (session.pid, entry.value.as_deref().unwrap()))
+            .collect();
+        assert_eq!(values, vec![(1, "1"), (2, "2"), (1, "3")]);
+    }
+
+    #[test]
+    fn test_replay_lines() {
+        let start = Utc.with_ymd_and_hms(2025, 11, 7, 10, 0, 0).unwrap();
+        let entry = |action: Action, key: &str, value: Option<&str>, mins: i64| {
+            let mut entry =
+                TimelineEntry::new(action, key.to_string(), value.map(str::to_string), None);
+            entry.timestamp = start + chrono::Duration::minutes(mins);
+            entry
+        };
+        let entries = [
+            entry(Action::Set, "OLD", Some("1"), 0),
+            entry(Action::Chdir, "PWD", Some("/tmp"), 1),
+            entry(Action::Set, "GREETING", Some("say \"hi\""), 2),
+            entry(Action::Unset, "OLD", None, 3),
+        ];
+
+        let (lines, unrecorded) =
+            replay_lines(&entries, Some(start + chrono::Duration::minutes(1)));
+        assert_eq!(
+            lines,
+            [
+                format!(
+                    "{}  # 2025-11-07 10:02:00",
+                    shell::export_line("GREETING", "say \"hi\"")
+                ),
+                "unset OLD  # 2025-11-07 10:03:00".to_string(),
+            ]
+        );
+        assert!(unrecorded.is_empty());
+        assert_eq!(replay_lines(&entries, None).0.len(), 3);
+        assert!(
+            replay_lines(&entries, Some(start + chrono::Duration::hours(1)))
+                .0
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_replay_lines_skips_unrecorded_values() {
+        let mut config = Config::default();
+        config.core.max_value_bytes = 4;
+        let hashed = secrets::hash_value("salt", "hunter2");
+        let truncated = config.limit_value("a long value".to_string());
+        let entries = [
+            TimelineEntry::new(Action::Set, "API_PASS".to_string(), Some(hashed), None),
+            TimelineEntry::new(Action::Set, "BLOB".to_string(), Some(truncated), None),
+            TimelineEntry::new(
+                Action::Set,
+                "MODE".to_string(),
+                Some("dev".to_string()),
+                None,
+            ),
+        ];
+
+        let (lines, unrecorded) = replay_lines(&entries, None);
+        assert!(lines[0].starts_with("# API_PASS: value not recorded (hashed)  # "));
+        assert!(lines[1].starts_with("# BLOB: value not recorded (truncated)  # "));
+        assert!(lines[2].starts_with(&shell::export_line("MODE", "dev")));
+        assert_eq!(unrecorded, ["API_PASS", "BLOB"]);
+    }
+
+    #[test]
+    fn test_merged_timeline_labels_sessions() {
+        let backend = MemoryBackend::new();
+        let mut sessions = [
+            Session::new(1, "zsh".to_string()),
+            Session::new(2, "bash".to_string()),
+        ];
+        sessions[1].tags.push("work".to_string());
+        let start = Utc::now();
+        for (i, session) in sessions.iter().enumerate() {
+            let mut entry =
+                TimelineEntry::new(Action::Set, "A".to_string(), Some(i.to_string()), None);
+            entry.timestamp = start - chrono::Duration::seconds(i as i64);
+            backend.append_timeline(session, &entry).unwrap();
         }
+
+        let (entries, owners) = merged_timeline(&backend, &sessions, None).unwrap();
+        let values: Vec<&str> = entries
+            .iter()
+            .map(|e| e.value.as_deref().unwrap())
+            .collect();
+        assert_eq!(values, ["1", "0"]);
+        assert_eq!(owners, [sessions[1].short_id(), sessions[0].short_id()]);
+
+        let (entries, owners) = merged_timeline(&backend, &sessions, Some("work")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(owners, [sessions[1].short_id()]);
     }
 
-    // Default: show last 24 hours
-    duration.num_hours() < 24
-}
+    #[test]
+    fn test_log_entries_keep_their_session() {
+        let start = Utc::now();
+        let entries = [
+            TimelineEntry::new(Action::Unset, "OLD".to_string(), None, Some("v".into())),
+            TimelineEntry::new(Action::Set, "OTHER".to_string(), Some("x".into()), None),
+            TimelineEntry::new(Action::Set, "NEW".to_string(), Some("v".into()), None),
+        ]
+        .map(|mut entry| {
+            entry.timestamp = start;
+            entry
+        });
+        let owners = ["aaaa", "bbbb", "cccc"];
+        let log_entries: Vec<LogEntry> = entries
+            .iter()
+            .zip(owners)
+            .map(|(entry, owner)| LogEntry {
+                entry,
+                session: Some(owner),
+            })
+            .collect();
 
-fn parse_number(s: &str) -> Option<u32> {
-    s.chars()
-        .take_while(|c| c.is_ascii_digit())
-        .collect::<String>()
-        .parse()
-        .ok()
+        // Rename detection pairs entries out of order; each item's label is
+        // still that of the entry it was built from
+        let items = detect_renames(log_entries.clone(), chrono::Duration::seconds(5));
+        let labels: Vec<Option<&str>> = items
+            .iter()
+            .map(|item| item_entries(item).last().unwrap().session)
+            .collect();
+        assert_eq!(labels, [Some("cccc"), Some("bbbb")]);
+
+        let followed = follow(log_entries, "OTHER", false);
+        assert_eq!(followed.len(), 1);
+        assert_eq!(followed[0].session, Some("bbbb"));
+    }
 }
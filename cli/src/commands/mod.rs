@@ -1,5 +1,45 @@
+pub mod archive;
+pub mod audit;
 pub mod diff;
+pub mod export;
 pub mod init;
 pub mod log;
+pub mod scan;
+pub mod service;
 pub mod snapshot;
 pub mod status;
+pub mod table;
+pub mod verify;
+
+use envhist_core::storage::Failures;
+
+/// Error of a command that has already reported what went wrong and only
+/// needs to exit 1, like `verify` on a mismatch. `main` records it in the
+/// audit log and exits without printing it again.
+#[derive(Debug)]
+pub(crate) struct Failed(pub String);
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// Exit 1 after printing nothing more.
+pub(crate) fn failed(reason: impl Into<String>) -> anyhow::Error {
+    Failed(reason.into()).into()
+}
+
+/// After a `--keep-going` run, list what failed and fail if anything did.
+pub(crate) fn check_failures(failures: &Failures) -> anyhow::Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    eprintln!("✗ {} item(s) failed:", failures.errors.len());
+    for error in &failures.errors {
+        eprintln!("  {:#}", error);
+    }
+    Err(failed(format!("{} item(s) failed", failures.errors.len())))
+}
@@ -0,0 +1,69 @@
+use crate::daemon_client;
+use anyhow::{Context, Result};
+use envhist_core::{envfile, secrets, storage::Snapshot, storage::Storage, Config, Env};
+use std::path::Path;
+
+pub fn scan(target: String) -> Result<()> {
+    let env = load_target(&target)?;
+
+    let mut flagged: Vec<&String> = env
+        .iter()
+        .filter(|(_, value)| secrets::looks_secret(value))
+        .map(|(key, _)| key)
+        .collect();
+    flagged.sort();
+
+    if flagged.is_empty() {
+        println!("✓ No secrets found in {}", target);
+        return Ok(());
+    }
+
+    println!("✗ Possible secrets found in {}:", target);
+    for key in flagged {
+        println!("  {}", key);
+    }
+
+    Err(super::failed(format!(
+        "possible secrets found in {}",
+        target
+    )))
+}
+
+pub fn check_filter(key: String, value: Option<String>) -> Result<()> {
+    let config = Config::load()?;
+    let decision = config.should_track_explained(&key);
+
+    let verdict = if decision.track {
+        "tracked"
+    } else {
+        "not tracked"
+    };
+    println!("{}: {}", key, verdict);
+    println!("  rule: {}", decision.reason);
+
+    if let Some(value) = value {
+        if decision.track && secrets::looks_secret(&value) {
+            println!("  note: the value looks like a secret and would be stored as-is");
+        }
+    }
+
+    Ok(())
+}
+
+fn load_target(target: &str) -> Result<Env> {
+    let path = Path::new(target);
+    if path.is_file() {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+
+        if let Ok(snapshot) = serde_json::from_str::<Snapshot>(&content) {
+            return Ok(snapshot.environment);
+        }
+        return Ok(envfile::parse_env_lines(&content));
+    }
+
+    let storage = Storage::new()?;
+    let session = daemon_client::get_active_session().ok().flatten();
+    let snapshot = storage.load_snapshot(target, session.as_ref())?;
+    Ok(snapshot.environment)
+}
@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use envhist_core::config::ENVHIST_HOME_VAR;
+use std::path::{Path, PathBuf};
+
+const SYSTEMD_UNIT: &str = "envhist.service";
+const LAUNCHD_LABEL: &str = "com.envhist.daemon";
+
+/// Init systems a service definition can be generated for.
+enum InitSystem {
+    Systemd,
+    Launchd,
+}
+
+impl InitSystem {
+    fn detect() -> Result<Self> {
+        if cfg!(target_os = "macos") {
+            return Ok(InitSystem::Launchd);
+        }
+        // The same check sd_booted() makes
+        if Path::new("/run/systemd/system").is_dir() {
+            return Ok(InitSystem::Systemd);
+        }
+        anyhow::bail!("No supported init system found (systemd or launchd); use `envhist daemon start` instead")
+    }
+
+    fn unit_path(&self) -> Result<PathBuf> {
+        match self {
+            InitSystem::Systemd => Ok(dirs::config_dir()
+                .context("Failed to find config directory")?
+                .join("systemd/user")
+                .join(SYSTEMD_UNIT)),
+            InitSystem::Launchd => Ok(dirs::home_dir()
+                .context("Failed to find home directory")?
+                .join("Library/LaunchAgents")
+                .join(format!("{}.plist", LAUNCHD_LABEL))),
+        }
+    }
+}
+
+pub fn install_service() -> Result<()> {
+    let init = InitSystem::detect()?;
+    let exe = std::env::current_exe().context("Failed to locate the envhist binary")?;
+    let envhist_home = std::env::var_os(ENVHIST_HOME_VAR)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from);
+
+    let content = match init {
+        InitSystem::Systemd => systemd_unit(&exe, envhist_home.as_deref()),
+        InitSystem::Launchd => launchd_plist(&exe, envhist_home.as_deref()),
+    };
+
+    let path = init.unit_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    std::fs::write(&path, content)
+        .with_context(|| format!("Failed to write service definition {:?}", path))?;
+    println!("✓ Wrote {}", path.display());
+
+    println!("\nStop any daemon started by envhist itself, then enable the service:");
+    match init {
+        InitSystem::Systemd => {
+            println!("  envhist daemon stop");
+            println!("  systemctl --user daemon-reload");
+            println!("  systemctl --user enable --now {}", SYSTEMD_UNIT);
+        }
+        InitSystem::Launchd => {
+            println!("  envhist daemon stop");
+            println!("  launchctl load -w {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn systemd_unit(exe: &Path, envhist_home: Option<&Path>) -> String {
+    let mut unit = String::from("[Unit]\nDescription=envhist environment history daemon\n\n");
+    unit.push_str("[Service]\n");
+    unit.push_str(&format!(
+        "ExecStart={} daemon run\n",
+        systemd_quote(&exe.to_string_lossy())
+    ));
+    if let Some(dir) = envhist_home {
+        unit.push_str(&format!(
+            "Environment={}\n",
+            systemd_quote(&format!("{}={}", ENVHIST_HOME_VAR, dir.display()))
+        ));
+    }
+    unit.push_str("Restart=on-failure\n\n");
+    unit.push_str("[Install]\nWantedBy=default.target\n");
+    unit
+}
+
+/// Quote a word for a unit file when it holds spaces or quotes, and escape
+/// `%` so systemd doesn't read it as a specifier.
+fn systemd_quote(word: &str) -> String {
+    let word = word.replace('%', "%%");
+    if word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        word
+    }
+}
+
+fn launchd_plist(exe: &Path, envhist_home: Option<&Path>) -> String {
+    let mut plist = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n",
+    );
+    plist.push_str(&format!(
+        "  <key>Label</key>\n  <string>{}</string>\n",
+        LAUNCHD_LABEL
+    ));
+    plist.push_str("  <key>ProgramArguments</key>\n  <array>\n");
+    for arg in [exe.to_string_lossy().as_ref(), "daemon", "run"] {
+        plist.push_str(&format!("    <string>{}</string>\n", xml_escape(arg)));
+    }
+    plist.push_str("  </array>\n");
+    if let Some(dir) = envhist_home {
+        plist.push_str(&format!(
+            "  <key>EnvironmentVariables</key>\n  <dict>\n    <key>{}</key>\n    <string>{}</string>\n  </dict>\n",
+            ENVHIST_HOME_VAR,
+            xml_escape(&dir.to_string_lossy())
+        ));
+    }
+    plist.push_str("  <key>RunAtLoad</key>\n  <true/>\n");
+    plist.push_str("  <key>KeepAlive</key>\n  <true/>\n");
+    plist.push_str("</dict>\n</plist>\n");
+    plist
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systemd_unit() {
+        let unit = systemd_unit(
+            Path::new("/opt/my tools/envhist"),
+            Some(Path::new("/home/me/100%/envhist")),
+        );
+        assert_eq!(
+            unit,
+            "[Unit]\n\
+             Description=envhist environment history daemon\n\
+             \n\
+             [Service]\n\
+             ExecStart=\"/opt/my tools/envhist\" daemon run\n\
+             Environment=ENVHIST_HOME=/home/me/100%%/envhist\n\
+             Restart=on-failure\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n"
+        );
+
+        let unit = systemd_unit(Path::new("/usr/bin/envhist"), None);
+        assert!(unit.contains("\nExecStart=/usr/bin/envhist daemon run\nRestart=on-failure\n"));
+    }
+
+    #[test]
+    fn test_launchd_plist() {
+        let plist = launchd_plist(
+            Path::new("/Apps/R&D/envhist"),
+            Some(Path::new("/Users/me/<envhist>")),
+        );
+        assert_eq!(
+            plist,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n  \
+               <key>Label</key>\n  \
+               <string>com.envhist.daemon</string>\n  \
+               <key>ProgramArguments</key>\n  \
+               <array>\n    \
+                 <string>/Apps/R&amp;D/envhist</string>\n    \
+                 <string>daemon</string>\n    \
+                 <string>run</string>\n  \
+               </array>\n  \
+               <key>EnvironmentVariables</key>\n  \
+               <dict>\n    \
+                 <key>ENVHIST_HOME</key>\n    \
+                 <string>/Users/me/&lt;envhist&gt;</string>\n  \
+               </dict>\n  \
+               <key>RunAtLoad</key>\n  \
+               <true/>\n  \
+               <key>KeepAlive</key>\n  \
+               <true/>\n\
+             </dict>\n\
+             </plist>\n"
+        );
+    }
+}
@@ -1,48 +1,228 @@
+use super::diff;
 use crate::daemon_client;
-use crate::SnapshotArgs;
-use anyhow::Result;
-use chrono::Utc;
-use envhist_core::{storage::Snapshot, storage::Storage};
+use crate::format::{terminal_width, Table, TimestampFormat};
+use crate::hooks::{self, Hook};
+use crate::shell::ShellKind;
+use crate::{DeleteArgs, RestoreArgs, SnapshotArgs};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use colored::*;
+use envhist_core::{
+    config::PROTECTED_VARS,
+    differ::{apply_diff_with, diff_envs, group_similar, merge_list, similarity, EnvDiff},
+    envfile,
+    session::{self, Session},
+    storage::{strip_values, Failures, Snapshot, Storage},
+    Catalog, Config, Env, StorageBackend,
+};
+use std::io::{IsTerminal, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
 
-fn current_session_id() -> Option<uuid::Uuid> {
-    daemon_client::get_active_session()
-        .ok()
-        .flatten()
-        .map(|s| s.id)
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListMode {
+    /// Replace the whole value with the snapshot's
+    #[default]
+    Overwrite,
+    /// Keep current elements and add the snapshot's missing ones after them
+    Append,
+    /// Keep current elements and add the snapshot's missing ones before them
+    Prepend,
 }
 
 pub fn snapshot(args: SnapshotArgs) -> Result<()> {
     let storage = Storage::new()?;
-    let current_env = Storage::get_current_env();
+    let current_env = if args.stdin {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read environment from stdin")?;
+        filter_tracked(
+            envfile::parse_env_lines(&input),
+            storage.config(),
+            args.force,
+        )
+    } else if let Some(ref container) = args.docker {
+        filter_tracked(capture_docker_env(container)?, storage.config(), args.force)
+    } else if let (Some(patch), Some(base)) = (&args.from_diff, &args.base) {
+        let session = daemon_client::get_active_session().ok().flatten();
+        env_from_diff(
+            &storage,
+            session.as_ref(),
+            patch,
+            base,
+            args.allow_conflicts,
+        )?
+    } else {
+        Storage::get_current_env()
+    };
+    let current_env = if args.keys_only {
+        strip_values(&current_env)
+    } else {
+        current_env
+    };
+
+    let session = if args.session {
+        daemon_client::get_active_session().ok().flatten()
+    } else {
+        None
+    };
+
+    if args.if_changed {
+        if let Some(last) = storage.baseline(session.as_ref())? {
+            if last.environment == current_env {
+                println!("No changes since snapshot '{}'; skipped.", last.name);
+                return Ok(());
+            }
+        }
+    }
 
     let snapshot_name = args
         .name
-        .unwrap_or_else(|| format!("snapshot-{}", Utc::now().format("%Y%m%d-%H%M%S")));
+        .unwrap_or_else(|| default_snapshot_name(session.as_ref()));
 
-    let session_id = args.session.then(|| current_session_id()).flatten();
+    let mut tags = Vec::new();
+    if args.tag_from_branch {
+        tags.extend(current_branch().map(|branch| branch_tag(&branch)));
+    }
 
+    let created_at = Utc::now();
     let snapshot = Snapshot {
         name: snapshot_name.clone(),
-        created_at: Utc::now(),
+        created_at,
         description: args.description,
         environment: current_env,
-        tags: Vec::new(),
-        session_id,
-    };
-
-    let session = if args.session {
-        daemon_client::get_active_session().ok().flatten()
-    } else {
-        None
+        tags,
+        session_id: session.as_ref().map(|s| s.id),
+        keys_only: args.keys_only,
+        expires_at: args.expire.map(|age| created_at + age),
     };
 
-    storage.save_snapshot(&snapshot, session.as_ref())?;
+    let compress = args.compress || storage.config().core.compress_snapshots;
+    let path = storage.save_snapshot_with(&snapshot, session.as_ref(), compress)?;
     println!("✓ Saved snapshot: {}", snapshot_name);
+    for tag in &snapshot.tags {
+        println!("  tagged {}", tag);
+    }
+    if args.verbose {
+        println!("  written to {}", path.display());
+    }
+    hooks::run(storage.config(), Hook::Snapshot, &snapshot_name)?;
 
     Ok(())
 }
 
-pub fn list() -> Result<()> {
+/// The `base` snapshot's environment with the diff in `patch` applied.
+/// Conflicts fail unless `allow_conflicts`, which only warns about them.
+fn env_from_diff(
+    storage: &dyn StorageBackend,
+    session: Option<&Session>,
+    patch: &Path,
+    base: &str,
+    allow_conflicts: bool,
+) -> Result<Env> {
+    let content = std::fs::read_to_string(patch)
+        .with_context(|| format!("Failed to read diff from {:?}", patch))?;
+    let diffs =
+        parse_patch(&content).with_context(|| format!("Failed to parse diff {:?}", patch))?;
+
+    let base_env = storage.load_snapshot(base, session)?.environment;
+
+    let (env, conflicts) = apply_diff_with(&base_env, &diffs, allow_conflicts)
+        .with_context(|| format!("Diff does not apply to snapshot '{}'", base))?;
+    for conflict in &conflicts {
+        eprintln!("{} {}", "warning:".yellow(), conflict);
+    }
+
+    Ok(env)
+}
+
+/// A list of diffs, either bare or wrapped as `diff --json` prints them.
+fn parse_patch(content: &str) -> Result<Vec<EnvDiff>> {
+    #[derive(serde::Deserialize)]
+    struct DiffJson {
+        diffs: Vec<EnvDiff>,
+    }
+
+    match serde_json::from_str::<Vec<EnvDiff>>(content) {
+        Ok(diffs) => Ok(diffs),
+        Err(_) => Ok(serde_json::from_str::<DiffJson>(content)?.diffs),
+    }
+}
+
+/// Auto-generated names; session snapshots also say which terminal made them.
+fn default_snapshot_name(session: Option<&Session>) -> String {
+    let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
+    match session {
+        Some(sess) => {
+            let shell = Path::new(&sess.shell)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("shell");
+            format!("session-{}-{}-{}", shell, sess.short_id(), timestamp)
+        }
+        None => format!("snapshot-{}", timestamp),
+    }
+}
+
+/// Drop variables the tracking filters ignore, unless `force` is set.
+pub(crate) fn filter_tracked(env: Env, config: &Config, force: bool) -> Env {
+    if force {
+        return env;
+    }
+    env.into_iter()
+        .filter(|(key, _)| config.should_track(key))
+        .collect()
+}
+
+/// Snapshots taken with `--tag-from-branch` carry this tag.
+fn branch_tag(branch: &str) -> String {
+    format!("branch:{}", branch)
+}
+
+/// The git branch checked out in the working directory; `None` outside a
+/// repository, on a detached HEAD or when git isn't installed.
+fn current_branch() -> Option<String> {
+    let output = Command::new("git")
+        .args(["symbolic-ref", "--quiet", "--short", "HEAD"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let branch = String::from_utf8(output.stdout).ok()?;
+    let branch = branch.trim();
+    (!branch.is_empty()).then(|| branch.to_string())
+}
+
+fn capture_docker_env(container: &str) -> Result<Env> {
+    let output = Command::new("docker")
+        .args(["exec", container, "env"])
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow::anyhow!("docker is not installed or not on PATH")
+            }
+            _ => anyhow::Error::new(e).context("Failed to run docker"),
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "Failed to read environment of container '{}': {}",
+            container,
+            stderr.trim()
+        );
+    }
+
+    let stdout = String::from_utf8(output.stdout).context("Container env is not valid UTF-8")?;
+    Ok(envfile::parse_env_lines(&stdout))
+}
+
+pub fn list(timestamp: TimestampFormat, branch: Option<String>) -> Result<()> {
     let storage = Storage::new()?;
     let session = daemon_client::get_active_session().ok().flatten();
     let mut merged: std::collections::BTreeMap<String, Snapshot> =
@@ -59,7 +239,11 @@ pub fn list() -> Result<()> {
     }
 
     let mut snapshots: Vec<_> = merged.into_values().collect();
-    snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+    if let Some(ref branch) = branch {
+        let tag = branch_tag(branch);
+        snapshots.retain(|snap| snap.tags.contains(&tag));
+    }
 
     if snapshots.is_empty() {
         println!("No snapshots found.");
@@ -67,44 +251,62 @@ pub fn list() -> Result<()> {
     }
 
     println!("Snapshots:");
+    let now = Utc::now();
+    let mut table = Table::new().indent(2);
     for snap in snapshots {
         let session_info = if let Some(sid) = snap.session_id {
             if Some(sid) == session.as_ref().map(|s| s.id) {
-                " (this session)".to_string()
+                "this session".to_string()
             } else {
-                format!(" (session: {})", sid)
+                format!("session: {}", session::short_id(&sid))
             }
         } else {
-            " (global)".to_string()
+            "global".to_string()
         };
+        let mut info = vec![session_info.dimmed().to_string()];
+        if snap.keys_only {
+            info.push("keys only".dimmed().to_string());
+        }
+        if snap.is_expired(now) {
+            info.push("expired".red().to_string());
+        } else if let Some(expires_at) = snap.expires_at {
+            info.push(
+                format!("expires {}", timestamp.format(expires_at))
+                    .dimmed()
+                    .to_string(),
+            );
+        }
 
-        let desc = snap
-            .description
-            .as_ref()
-            .map(|d| format!(" - {}", d))
-            .unwrap_or_default();
-
-        println!(
-            "  {} - {}{}{}",
-            snap.name,
-            snap.created_at.format("%Y-%m-%d %H:%M:%S"),
-            session_info,
-            desc
-        );
+        table.push(vec![
+            snap.name.bold().to_string(),
+            timestamp.format(snap.created_at),
+            info.join(", "),
+            snap.description.unwrap_or_default(),
+        ]);
+    }
+    for line in table.render(terminal_width()) {
+        println!("{}", line);
     }
 
     Ok(())
 }
 
-pub fn restore(name: String, dry_run: bool) -> Result<()> {
+pub fn restore(args: RestoreArgs) -> Result<()> {
+    let name = args.name;
     let storage = Storage::new()?;
     let session = daemon_client::get_active_session().ok().flatten();
     let snapshot = match storage.load_snapshot(&name, None) {
         Ok(global) => global,
         Err(_) => storage.load_snapshot(&name, session.as_ref())?,
     };
+    if snapshot.keys_only {
+        anyhow::bail!(
+            "Snapshot '{}' is keys-only; it has no values to restore",
+            name
+        );
+    }
 
-    if dry_run {
+    if args.dry_run && args.full {
         println!("Would restore snapshot: {}", name);
         println!("Environment variables:");
         for (key, value) in snapshot.environment.iter() {
@@ -113,27 +315,553 @@ pub fn restore(name: String, dry_run: bool) -> Result<()> {
         return Ok(());
     }
 
-    // Restore each variable
-    for (key, value) in snapshot.environment.iter() {
-        std::env::set_var(key, value);
-        println!("export {}=\"{}\"", key, value.replace("\"", "\\\""));
+    let current_env = Storage::get_current_env();
+    let mut exports = Vec::new();
+    let mut added = Vec::new();
+    let mut overwritten = Vec::new();
+    let mut removed = Vec::new();
+
+    let mut keys: Vec<&String> = snapshot.environment.keys().collect();
+    keys.sort();
+    for key in keys {
+        let mut value = snapshot.environment[key].clone();
+        if let (Some(delimiter), Some(current)) =
+            (storage.config().list_delimiter(key), current_env.get(key))
+        {
+            match args.lists {
+                ListMode::Overwrite => {}
+                ListMode::Append => value = merge_list(current, &value, delimiter, false),
+                ListMode::Prepend => value = merge_list(current, &value, delimiter, true),
+            }
+        }
+
+        match current_env.get(key) {
+            Some(current) if *current == value => continue,
+            Some(_) => overwritten.push(key.as_str()),
+            None => added.push(key.as_str()),
+        }
+        exports.push((key.as_str(), value));
+    }
+
+    // Replace mode also drops tracked variables the snapshot doesn't have
+    let mut refused = Vec::new();
+    if args.replace {
+        let config = storage.config();
+        let mut stale: Vec<&String> = current_env
+            .keys()
+            .filter(|key| {
+                !snapshot.environment.contains_key(*key)
+                    && (config.should_track(key)
+                        || (args.include_system && config.filters.ignore_system.contains(key)))
+            })
+            .collect();
+        stale.sort();
+        for key in stale {
+            if PROTECTED_VARS.contains(&key.as_str()) && !args.force {
+                refused.push(key.as_str());
+                continue;
+            }
+            removed.push(key.as_str());
+        }
+    }
+
+    let mode = if args.replace { "replace" } else { "merge" };
+    let mut restored_env = current_env.clone();
+    for (key, value) in &exports {
+        restored_env.insert(key.to_string(), value.clone());
+    }
+    for key in &removed {
+        restored_env.remove(*key);
+    }
+    let diffs = diff_envs(&current_env, &restored_env);
+    let catalog = Catalog::load();
+
+    if args.dry_run {
+        println!("Would restore snapshot: {} ({})", name, mode);
+        println!();
+        print!(
+            "{}",
+            diff::format_diff_colored(&diffs, false, 0, storage.config(), &catalog)
+        );
+        print!("{}", diff::format_summary(&diffs));
+        return Ok(());
     }
 
-    println!("✓ Restored snapshot: {}", name);
-    println!("\nNote: Run the export commands above in your shell to apply changes.");
+    // stdout is usually captured by `eval "$(...)"`, so ask on the terminal
+    let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+    let pending = !exports.is_empty() || !removed.is_empty();
+    if !args.yes && interactive && pending {
+        eprintln!("Restoring snapshot: {} ({})", name, mode);
+        eprintln!();
+        eprint!(
+            "{}",
+            diff::format_diff_colored(&diffs, false, 0, storage.config(), &catalog)
+        );
+        eprint!("{}", diff::format_summary(&diffs));
+        if !confirm("Apply?")? {
+            eprintln!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    let shell = args.shell.unwrap_or(if args.declare {
+        ShellKind::Bash
+    } else {
+        ShellKind::Zsh
+    });
+    let mut script = String::new();
+    for (key, value) in &exports {
+        if args.declare {
+            script.push_str(&shell.declare_line(key, value));
+        } else {
+            script.push_str(&shell.export_line(key, value));
+        }
+        script.push('\n');
+    }
+    for key in &removed {
+        script.push_str(&shell.unset_line(key));
+        script.push('\n');
+    }
+    match &args.write {
+        Some(path) => {
+            write_private(path, &script)?;
+            println!("{}", path.display());
+        }
+        None => print!("{}", script),
+    }
+
+    eprintln!("✓ Restored snapshot: {} ({})", name, mode);
+    for (label, keys) in [
+        ("added", &added),
+        ("overwritten", &overwritten),
+        ("removed", &removed),
+    ] {
+        if !keys.is_empty() {
+            eprintln!("  {}: {}", label, keys.join(", "));
+        }
+    }
+    let unset_protected: Vec<&str> = removed
+        .iter()
+        .copied()
+        .filter(|key| PROTECTED_VARS.contains(key))
+        .collect();
+    if !unset_protected.is_empty() {
+        eprintln!(
+            "{}",
+            format!(
+                "⚠ WARNING: unsetting protected variables: {}",
+                unset_protected.join(", ")
+            )
+            .red()
+            .bold()
+        );
+    }
+    if !refused.is_empty() {
+        eprintln!(
+            "{}",
+            format!(
+                "⚠ WARNING: refusing to unset protected variables: {} (pass --include-system --force to allow)",
+                refused.join(", ")
+            )
+            .yellow()
+            .bold()
+        );
+    }
+    match &args.write {
+        Some(path) => eprintln!("\nNote: Run `source {}` to apply changes.", path.display()),
+        None => eprintln!("\nNote: Run the commands above in your shell to apply changes."),
+    }
+    hooks::run(storage.config(), Hook::Restore, &name)?;
 
     Ok(())
 }
 
-pub fn delete(name: String) -> Result<()> {
+/// Write `content` readable only by the owner, since restored values may
+/// hold secrets.
+fn write_private(path: &Path, content: &str) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    // `mode` only applies to new files
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to set permissions on {:?}", path))?;
+    file.write_all(content.as_bytes())
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+pub fn delete(args: DeleteArgs) -> Result<()> {
     let storage = Storage::new()?;
     let session = daemon_client::get_active_session().ok().flatten();
 
-    if storage.delete_snapshot(&name, None).is_err() {
-        storage.delete_snapshot(&name, session.as_ref())?;
+    if let Some(name) = args.name {
+        if args.dry_run {
+            println!("Would delete snapshot: {}", name);
+            return Ok(());
+        }
+
+        if storage.delete_snapshot(&name, None).is_err() {
+            storage.delete_snapshot(&name, session.as_ref())?;
+        }
+
+        println!("✓ Deleted snapshot: {}", name);
+        hooks::run(storage.config(), Hook::Delete, &name)?;
+        return Ok(());
     }
 
-    println!("✓ Deleted snapshot: {}", name);
+    let matching = delete_candidates(&storage, session.as_ref(), &args, Utc::now())?;
+
+    if matching.is_empty() {
+        println!("No snapshots match.");
+        return Ok(());
+    }
+
+    let verb = if args.dry_run {
+        "Would delete"
+    } else {
+        "Matched"
+    };
+    println!("{} {} snapshot(s):", verb, matching.len());
+    for snap in &matching {
+        println!(
+            "  {} - {}",
+            snap.name,
+            snap.created_at.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    if !args.yes && !confirm("Delete these snapshots?")? {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let mut failures = Failures::new(args.keep_going);
+    let mut deleted = 0;
+    for snap in &matching {
+        // Snapshots are only listed from the global store and this session
+        let owner = snap.session_id.and(session.as_ref());
+        let result = storage
+            .delete_snapshot_in(&snap.name, owner)
+            .with_context(|| format!("Snapshot {}", snap.name));
+        if failures.check(result)?.is_none() {
+            continue;
+        }
+        deleted += 1;
+        let hook = hooks::run(storage.config(), Hook::Delete, &snap.name)
+            .with_context(|| format!("Delete hook for snapshot {}", snap.name));
+        failures.check(hook)?;
+    }
+
+    println!("✓ Deleted {} snapshot(s)", deleted);
+    super::check_failures(&failures)
+}
+
+/// The snapshots visible from `session` that match all of `args`' selectors.
+fn delete_candidates(
+    storage: &dyn StorageBackend,
+    session: Option<&Session>,
+    args: &DeleteArgs,
+    now: DateTime<Utc>,
+) -> Result<Vec<Snapshot>> {
+    let pattern = args
+        .all_matching
+        .as_deref()
+        .map(glob::Pattern::new)
+        .transpose()
+        .context("Invalid --all-matching glob")?;
+    let cutoff = args.older_than.map(|age| now - age);
+
+    Ok(storage
+        .list_snapshots(session)?
+        .into_iter()
+        .filter(|snap| pattern.as_ref().is_none_or(|p| p.matches(&snap.name)))
+        .filter(|snap| args.tag.as_ref().is_none_or(|t| snap.tags.contains(t)))
+        .filter(|snap| cutoff.is_none_or(|c| snap.created_at < c))
+        .filter(|snap| !args.expired || snap.is_expired(now))
+        .collect())
+}
+
+/// Group snapshots that are at least `threshold` percent alike to the newest
+/// of their group, and with `apply` delete all but that newest one.
+pub fn dedup(threshold: u8, apply: bool, yes: bool) -> Result<()> {
+    let storage = Storage::new()?;
+    let session = daemon_client::get_active_session().ok().flatten();
+    // Newest first, so each group's first member is the one kept
+    let snapshots = storage.list_snapshots(session.as_ref())?;
+
+    let groups: Vec<Vec<&Snapshot>> = group_similar(&snapshots, f64::from(threshold), |a, b| {
+        snapshot_similarity(a, b) * 100.0
+    })
+    .into_iter()
+    .map(|group| group.into_iter().map(|i| &snapshots[i]).collect())
+    .collect();
+
+    if groups.is_empty() {
+        println!("No snapshots are at least {}% alike.", threshold);
+        return Ok(());
+    }
+
+    let mut redundant = Vec::new();
+    for (n, group) in groups.iter().enumerate() {
+        let (newest, rest) = group
+            .split_first()
+            .expect("groups have two or more members");
+        println!("Group {} ({} snapshots):", n + 1, group.len());
+        println!(
+            "  keep  {} - {}",
+            newest.name.bold(),
+            newest.created_at.format("%Y-%m-%d %H:%M:%S")
+        );
+        for snap in rest {
+            let alike = snapshot_similarity(newest, snap);
+            println!(
+                "  {}  {} - {}  ({:.0}% alike)",
+                "drop".red(),
+                snap.name,
+                snap.created_at.format("%Y-%m-%d %H:%M:%S"),
+                // Floored so only identical snapshots read 100%
+                (alike * 100.0).floor()
+            );
+        }
+        redundant.extend(rest.iter().copied());
+    }
+
+    if !apply {
+        println!();
+        println!(
+            "Run with --apply to delete the {} redundant snapshot(s).",
+            redundant.len()
+        );
+        return Ok(());
+    }
+
+    if !yes && !confirm("Delete the redundant snapshots?")? {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    for snap in &redundant {
+        // Snapshots are only listed from the global store and this session
+        let owner = snap.session_id.and(session.as_ref());
+        storage
+            .delete_snapshot_in(&snap.name, owner)
+            .with_context(|| format!("Snapshot {}", snap.name))?;
+        hooks::run(storage.config(), Hook::Delete, &snap.name)?;
+    }
+    println!("✓ Deleted {} snapshot(s)", redundant.len());
+
+    Ok(())
+}
+
+/// [`similarity`] of two snapshots, on names alone when either is keys-only.
+fn snapshot_similarity(a: &Snapshot, b: &Snapshot) -> f64 {
+    if a.keys_only || b.keys_only {
+        similarity(&strip_values(&a.environment), &strip_values(&b.environment))
+    } else {
+        similarity(&a.environment, &b.environment)
+    }
+}
+
+pub fn tag_list() -> Result<()> {
+    let storage = Storage::new()?;
+    let session = daemon_client::get_active_session().ok().flatten();
+
+    let mut counts: std::collections::BTreeMap<String, usize> = std::collections::BTreeMap::new();
+    for snap in storage.list_snapshots(session.as_ref())? {
+        for tag in snap.tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+
+    if counts.is_empty() {
+        println!("No tagged snapshots.");
+        return Ok(());
+    }
+
+    for (tag, count) in counts {
+        println!("{} ({})", tag, count);
+    }
+
+    Ok(())
+}
+
+pub fn tag_add(tag: String, matching: String) -> Result<()> {
+    let storage = Storage::new()?;
+    let session = daemon_client::get_active_session().ok().flatten();
+    let pattern = glob::Pattern::new(&matching).context("Invalid --matching glob")?;
+
+    let matched: Vec<Snapshot> = storage
+        .list_snapshots(session.as_ref())?
+        .into_iter()
+        .filter(|snap| pattern.matches(&snap.name))
+        .collect();
+
+    if matched.is_empty() {
+        println!("No snapshots match.");
+        return Ok(());
+    }
+
+    let mut tagged = 0;
+    for snap in &matched {
+        // Snapshots are only listed from the global store and this session
+        let owner = snap.session_id.and(session.as_ref());
+        if storage.tag_snapshot(snap, owner, &tag)? {
+            tagged += 1;
+        }
+    }
+
+    println!(
+        "✓ Tagged {} snapshot(s) with {} ({} already had it)",
+        tagged,
+        tag,
+        matched.len() - tagged
+    );
+
+    Ok(())
+}
+
+pub fn promote(name: String, force: bool) -> Result<()> {
+    let storage = Storage::new()?;
+    let session = daemon_client::get_active_session()?
+        .context("No active session; is the daemon running? (envhist daemon start)")?;
+
+    let path = storage.move_snapshot(&name, Some(&session), None, force)?;
+    println!("✓ Promoted snapshot {} to global", name);
+    println!("  now at {}", path.display());
 
     Ok(())
 }
+
+pub fn demote(name: String, session_id: Option<String>, force: bool) -> Result<()> {
+    let storage = Storage::new()?;
+    let session = match session_id {
+        Some(ref id) => storage.resolve_session(id)?,
+        None => daemon_client::get_active_session()?
+            .context("No active session; pass --session or start the daemon")?,
+    };
+
+    let path = storage.move_snapshot(&name, None, Some(&session), force)?;
+    println!(
+        "✓ Demoted snapshot {} to session {}",
+        name,
+        session.short_id()
+    );
+    println!("  now at {}", path.display());
+
+    Ok(())
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", prompt);
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envhist_core::MemoryBackend;
+
+    fn snapshot(name: &str, age_secs: i64, session: Option<&Session>) -> Snapshot {
+        Snapshot {
+            name: name.to_string(),
+            created_at: Utc::now() - chrono::Duration::seconds(age_secs),
+            description: None,
+            environment: Env::from([("NAME".to_string(), name.to_string())]),
+            tags: Vec::new(),
+            session_id: session.map(|s| s.id),
+            keys_only: false,
+            expires_at: None,
+        }
+    }
+
+    fn delete_args() -> DeleteArgs {
+        DeleteArgs {
+            name: None,
+            all_matching: None,
+            tag: None,
+            older_than: None,
+            expired: false,
+            dry_run: false,
+            yes: true,
+            keep_going: false,
+        }
+    }
+
+    #[test]
+    fn test_delete_candidates() {
+        let backend = MemoryBackend::new();
+        let session = Session::new(1, "zsh".to_string());
+        let mut old = snapshot("old", 3600, None);
+        old.tags.push("scratch".to_string());
+        backend.save_snapshot(&old, None).unwrap();
+        let mine = snapshot("mine", 0, Some(&session));
+        backend.save_snapshot(&mine, Some(&session)).unwrap();
+        let now = Utc::now();
+
+        // Global snapshots are listed once even with a session active
+        let all = DeleteArgs {
+            all_matching: Some("*".to_string()),
+            ..delete_args()
+        };
+        let names: Vec<String> = delete_candidates(&backend, Some(&session), &all, now)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, vec!["mine", "old"]);
+
+        let tagged = DeleteArgs {
+            tag: Some("scratch".to_string()),
+            ..delete_args()
+        };
+        let older = DeleteArgs {
+            older_than: Some(chrono::Duration::minutes(30)),
+            ..delete_args()
+        };
+        for args in [tagged, older] {
+            let matched = delete_candidates(&backend, Some(&session), &args, now).unwrap();
+            assert_eq!(matched.len(), 1);
+            assert_eq!(matched[0].name, "old");
+        }
+    }
+
+    #[test]
+    fn test_env_from_diff() {
+        let backend = MemoryBackend::new();
+        let mut base = snapshot("base", 0, None);
+        base.environment.insert("A".to_string(), "1".to_string());
+        backend.save_snapshot(&base, None).unwrap();
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let patch = temp_dir.path().join("patch.json");
+        let diffs = vec![
+            EnvDiff::Changed {
+                key: "A".to_string(),
+                old_value: "1".to_string(),
+                new_value: "2".to_string(),
+            },
+            EnvDiff::Added {
+                key: "B".to_string(),
+                value: "3".to_string(),
+            },
+        ];
+        std::fs::write(&patch, serde_json::to_string(&diffs).unwrap()).unwrap();
+
+        let env = env_from_diff(&backend, None, &patch, "base", false).unwrap();
+        assert_eq!(env.get("A").map(String::as_str), Some("2"));
+        assert_eq!(env.get("B").map(String::as_str), Some("3"));
+        assert_eq!(env.get("NAME").map(String::as_str), Some("base"));
+        assert!(env_from_diff(&backend, None, &patch, "missing", false).is_err());
+    }
+}
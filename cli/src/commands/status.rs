@@ -1,34 +1,173 @@
-use crate::daemon_client;
+use super::{diff, log};
+use crate::format::{display_change, display_key_value, group_by_prefix, human_bytes, GroupBy};
+use crate::{daemon_client, StatusArgs};
 use anyhow::Result;
-use envhist_core::{differ::diff_envs, storage::Storage};
+use chrono::{Local, Utc};
+use colored::*;
+use envhist_core::{
+    differ::{diff_envs, DiffSummary, EnvDiff},
+    session::Session,
+    storage::{strip_values, Storage},
+    Config, Env,
+};
+use std::io::Write;
+use std::time::Duration;
 
-pub fn status() -> Result<()> {
+pub fn status(args: StatusArgs) -> Result<()> {
     let storage = Storage::new()?;
+    if args.store {
+        return print_store_usage(&storage);
+    }
     let current_env = Storage::get_current_env();
     let session = daemon_client::get_active_session().ok().flatten();
 
-    // Try to get last snapshot
-    let snapshots = storage.list_snapshots(session.as_ref())?;
+    if args.prompt {
+        // Prompts run this constantly; never fail loudly here
+        if let Ok(Some(snapshot)) = storage.baseline(session.as_ref()) {
+            let current_env = if snapshot.keys_only {
+                strip_values(&current_env)
+            } else {
+                current_env
+            };
+            let count = diff_envs(&snapshot.environment, &current_env)
+                .iter()
+                .filter(|d| !matches!(d, EnvDiff::Unchanged { .. }))
+                .count();
+            if count > 0 {
+                println!("±{}", count);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(interval) = args.watch {
+        return watch(&args, &storage, session.as_ref(), interval);
+    }
+
+    print_status(&args, &storage, session.as_ref(), &current_env, false)
+}
+
+/// Redraw the status every `interval` seconds until interrupted. The CLI's
+/// own env never changes, so this follows the env the shell hook last
+/// captured for the session instead.
+fn watch(
+    args: &StatusArgs,
+    storage: &Storage,
+    session: Option<&Session>,
+    interval: u64,
+) -> Result<()> {
+    loop {
+        let shell_session = log::get_session_for_pid(std::process::id())?;
+        let current_env = Session::load_metadata(&shell_session.metadata_path())
+            .map(|metadata| metadata.current_env)
+            .unwrap_or_else(|_| Storage::get_current_env());
+
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "{}",
+            format!("Every {}s: envhist status ({})", interval, clock()).dimmed()
+        );
+        println!();
+        print_status(args, storage, session, &current_env, true)?;
+        std::io::stdout().flush()?;
+
+        std::thread::sleep(Duration::from_secs(interval));
+    }
+}
 
-    if snapshots.is_empty() {
+fn print_store_usage(storage: &Storage) -> Result<()> {
+    let usage = storage.store_usage()?;
+    println!("Store: {}", Config::base_dir().display());
+    println!(
+        "  sessions:  {:>10}  ({} sessions)",
+        human_bytes(usage.sessions_bytes),
+        usage.session_count
+    );
+    println!(
+        "  snapshots: {:>10}  ({} snapshots)",
+        human_bytes(usage.snapshots_bytes),
+        usage.snapshot_count
+    );
+    println!("  total:     {:>10}", human_bytes(usage.total_bytes));
+    Ok(())
+}
+
+/// Wall-clock time for the watch header, in UTC for deterministic output.
+fn clock() -> String {
+    if envhist_core::config::deterministic() {
+        Utc::now().format("%H:%M:%S").to_string()
+    } else {
+        Local::now().format("%H:%M:%S").to_string()
+    }
+}
+
+/// `recorded` says `current_env` is the daemon's copy, whose
+/// `filters.hash_patterns` values are hashed and long values truncated.
+fn print_status(
+    args: &StatusArgs,
+    storage: &Storage,
+    session: Option<&Session>,
+    current_env: &Env,
+    recorded: bool,
+) -> Result<()> {
+    let Some(last_snapshot) = storage.baseline(session)? else {
         println!("No snapshots found. Create one with: envhist snapshot <name>");
         return Ok(());
+    };
+
+    // Snapshots keep raw values; hash and truncate them the same way to
+    // compare; a keys-only snapshot has no values to hash
+    let snapshot_env = &if recorded && !last_snapshot.keys_only {
+        storage.recorded_env(last_snapshot.environment.clone())?
+    } else {
+        last_snapshot.environment.clone()
+    };
+
+    let mut diffs = if last_snapshot.keys_only {
+        diff_envs(snapshot_env, &strip_values(current_env))
+    } else {
+        diff_envs(snapshot_env, current_env)
+    };
+    if let Some(ref path) = args.only_from {
+        let keys = diff::read_key_list(path)?;
+        diffs.retain(|d| keys.contains(d.key()));
     }
 
-    let last_snapshot = &snapshots[0];
-    let snapshot_env = &last_snapshot.environment;
+    if args.json {
+        return print_json(args, &last_snapshot.name, &diffs);
+    }
 
-    let diffs = diff_envs(snapshot_env, &current_env);
+    if args.summary_only {
+        println!("{}", diff::format_summary(&diffs).trim());
+        return Ok(());
+    }
 
     let changes: Vec<_> = diffs
         .iter()
-        .filter(|d| !matches!(d, envhist_core::differ::EnvDiff::Unchanged { .. }))
+        .filter(|d| !matches!(d, EnvDiff::Unchanged { .. }))
         .collect();
 
-    if changes.is_empty() {
+    if args.short {
+        for diff in changes {
+            match diff {
+                EnvDiff::Added { key, .. } => println!("A {}", key),
+                EnvDiff::Removed { key, .. } => println!("D {}", key),
+                EnvDiff::Changed { key, .. } => println!("M {}", key),
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+
+    if changes.is_empty() && !args.show_unchanged {
         println!("No changes since snapshot: {}", last_snapshot.name);
         return Ok(());
     }
+    let listed: Vec<&EnvDiff> = if args.show_unchanged {
+        diffs.iter().collect()
+    } else {
+        changes
+    };
 
     println!(
         "Changes since snapshot: {} ({})",
@@ -37,24 +176,86 @@ pub fn status() -> Result<()> {
     );
     println!();
 
-    for diff in changes {
-        match diff {
-            envhist_core::differ::EnvDiff::Added { key, value } => {
-                println!("+ {}: {}", key, value);
-            }
-            envhist_core::differ::EnvDiff::Removed { key, old_value } => {
-                println!("- {}: {}", key, old_value);
+    match args.group_by {
+        Some(GroupBy::Prefix) => {
+            let prefixes = &storage.config().display.group_prefixes;
+            for (group, group_diffs) in group_by_prefix(listed, |d| d.key(), prefixes) {
+                println!("{}", format!("[{}]", group).bold());
+                for diff in group_diffs {
+                    print_change(diff);
+                }
+                println!();
             }
-            envhist_core::differ::EnvDiff::Changed {
-                key,
-                old_value,
-                new_value,
-            } => {
-                println!("~ {}: {} -> {}", key, old_value, new_value);
+        }
+        None => {
+            for diff in listed {
+                print_change(diff);
             }
-            _ => {}
         }
     }
 
     Ok(())
 }
+
+/// `status --json`: the detailed `diffs`, the same changes as name lists
+/// under `names`, and the `summary` counts.
+fn print_json(args: &StatusArgs, snapshot: &str, diffs: &[EnvDiff]) -> Result<()> {
+    let summary = DiffSummary::from_diffs(diffs);
+    if args.summary_only {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+    for diff in diffs {
+        match diff {
+            EnvDiff::Added { key, .. } => added.push(key),
+            EnvDiff::Removed { key, .. } => removed.push(key),
+            EnvDiff::Changed { key, .. } => changed.push(key),
+            EnvDiff::Unchanged { .. } => {}
+        }
+    }
+    let mut output = serde_json::json!({
+        "snapshot": snapshot,
+        "names": {
+            "added": added,
+            "removed": removed,
+            "changed": changed,
+        },
+        "summary": summary,
+    });
+    if !args.name_only {
+        let listed: Vec<&EnvDiff> = diffs
+            .iter()
+            .filter(|d| args.show_unchanged || !matches!(d, EnvDiff::Unchanged { .. }))
+            .collect();
+        output["diffs"] = serde_json::json!(listed);
+    }
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+fn print_change(diff: &EnvDiff) {
+    match diff {
+        EnvDiff::Added { key, value } => {
+            println!("+ {}: {}", key, display_key_value(key, value));
+        }
+        EnvDiff::Removed { key, old_value } => {
+            println!("- {}: {}", key, display_key_value(key, old_value));
+        }
+        EnvDiff::Changed {
+            key,
+            old_value,
+            new_value,
+        } => {
+            let (old_shown, new_shown) = display_change(key, old_value, new_value);
+            println!("~ {}: {} -> {}", key, old_shown, new_shown);
+        }
+        EnvDiff::Unchanged { key, value } => {
+            println!(
+                "{}",
+                format!("  {}: {}", key, display_key_value(key, value)).dimmed()
+            );
+        }
+    }
+}
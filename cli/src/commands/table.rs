@@ -0,0 +1,69 @@
+use crate::daemon_client;
+use crate::format::{display_key_value, terminal_width, Table};
+use anyhow::Result;
+use colored::*;
+use envhist_core::storage::{strip_values, Storage};
+use envhist_core::Env;
+use std::collections::BTreeSet;
+
+/// A matrix of the union of the snapshots' variables against the snapshots,
+/// with the names of variables that differ highlighted.
+pub fn table(names: Vec<String>, changed: bool) -> Result<()> {
+    let storage = Storage::new()?;
+    let session = daemon_client::get_active_session().ok().flatten();
+    let snapshots = names
+        .iter()
+        .map(|name| storage.load_snapshot(name, session.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    // With a keys-only snapshot in the mix only names can be compared
+    let keys_only = snapshots.iter().any(|snap| snap.keys_only);
+    let envs: Vec<Env> = snapshots
+        .iter()
+        .map(|snap| {
+            if keys_only {
+                strip_values(&snap.environment)
+            } else {
+                snap.environment.clone()
+            }
+        })
+        .collect();
+
+    let keys: BTreeSet<&String> = envs.iter().flat_map(|env| env.keys()).collect();
+
+    let mut table = Table::new();
+    let mut header = vec!["VARIABLE".bold().to_string()];
+    header.extend(names.iter().map(|name| name.bold().to_string()));
+    table.push(header);
+
+    let total = keys.len();
+    let mut differing = 0;
+    for key in keys {
+        let values: Vec<Option<&String>> = envs.iter().map(|env| env.get(key)).collect();
+        let differs = values.iter().any(|value| *value != values[0]);
+        if differs {
+            differing += 1;
+        } else if changed {
+            continue;
+        }
+
+        let mut row = vec![if differs {
+            key.yellow().bold().to_string()
+        } else {
+            key.to_string()
+        }];
+        row.extend(values.iter().map(|value| match value {
+            Some(_) if keys_only => "(set)".dimmed().to_string(),
+            Some(value) => display_key_value(key, value).into_owned(),
+            None => String::new(),
+        }));
+        table.push(row);
+    }
+
+    for line in table.render(terminal_width()) {
+        println!("{}", line);
+    }
+    println!();
+    println!("{} of {} variable(s) differ", differing, total);
+
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use super::diff;
+use crate::daemon_client;
+use anyhow::{Context, Result};
+use colored::*;
+use envhist_core::{
+    differ::{diff_envs, EnvDiff},
+    storage::{strip_values, Snapshot, Storage},
+    Catalog, Config, Env,
+};
+
+/// Which variables `verify` compares.
+struct Scope {
+    ignore: Vec<glob::Pattern>,
+    only: Vec<glob::Pattern>,
+    /// Also compare variables the filters don't track (`PATH`, `HOME`, ...)
+    all_vars: bool,
+}
+
+impl Scope {
+    fn contains(&self, key: &str, config: &Config) -> bool {
+        (self.all_vars || config.should_track(key))
+            && (self.only.is_empty() || self.only.iter().any(|p| p.matches(key)))
+            && !self.ignore.iter().any(|p| p.matches(key))
+    }
+}
+
+/// Exit 1 with the diff unless every variable in scope has the snapshot's
+/// value in the current environment.
+pub fn verify(name: String, ignore: Vec<String>, only: Vec<String>, all_vars: bool) -> Result<()> {
+    let patterns = |globs: &[String], flag: &str| -> Result<Vec<glob::Pattern>> {
+        globs
+            .iter()
+            .map(|g| {
+                glob::Pattern::new(g).with_context(|| format!("Invalid {} glob '{}'", flag, g))
+            })
+            .collect()
+    };
+    let scope = Scope {
+        ignore: patterns(&ignore, "--ignore")?,
+        only: patterns(&only, "--only")?,
+        all_vars,
+    };
+
+    let storage = Storage::new()?;
+    let session = daemon_client::get_active_session().ok().flatten();
+    let snapshot = storage.load_snapshot(&name, session.as_ref())?;
+    let current_env = Storage::get_current_env();
+    let (checked, mismatches) = compare(&snapshot, &current_env, &scope, storage.config());
+
+    if mismatches.is_empty() {
+        println!(
+            "✓ Environment matches snapshot '{}' ({} variable(s) checked)",
+            name, checked
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        format!("✗ Environment differs from snapshot '{}':", name).red()
+    );
+    println!();
+    let catalog = Catalog::load();
+    print!(
+        "{}",
+        diff::format_diff_colored(&mismatches, false, 0, storage.config(), &catalog)
+    );
+    print!("{}", diff::format_summary(&mismatches));
+    Err(super::failed(format!(
+        "environment differs from snapshot '{}'",
+        name
+    )))
+}
+
+/// Number of variables in scope, and those whose value differs from the
+/// snapshot.
+fn compare(
+    snapshot: &Snapshot,
+    current_env: &Env,
+    scope: &Scope,
+    config: &Config,
+) -> (usize, Vec<EnvDiff>) {
+    let mut diffs = if snapshot.keys_only {
+        diff_envs(&snapshot.environment, &strip_values(current_env))
+    } else {
+        diff_envs(&snapshot.environment, current_env)
+    };
+    diffs.retain(|d| scope.contains(d.key(), config));
+
+    let checked = diffs.len();
+    let mismatches = diffs
+        .into_iter()
+        .filter(|d| !matches!(d, EnvDiff::Unchanged { .. }))
+        .collect();
+    (checked, mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn env(pairs: &[(&str, &str)]) -> Env {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn snapshot(environment: Env) -> Snapshot {
+        Snapshot {
+            name: "pinned".to_string(),
+            created_at: Utc::now(),
+            description: None,
+            environment,
+            tags: Vec::new(),
+            session_id: None,
+            keys_only: false,
+            expires_at: None,
+        }
+    }
+
+    fn scope(all_vars: bool) -> Scope {
+        Scope {
+            ignore: Vec::new(),
+            only: Vec::new(),
+            all_vars,
+        }
+    }
+
+    #[test]
+    fn test_compare_skips_untracked() {
+        let config = Config::default();
+        let pinned = snapshot(env(&[("PATH", "/usr/bin"), ("APP_MODE", "prod")]));
+        let current = env(&[("PATH", "/opt/bin"), ("APP_MODE", "prod")]);
+
+        let (checked, mismatches) = compare(&pinned, &current, &scope(false), &config);
+        assert_eq!(checked, 1);
+        assert!(mismatches.is_empty());
+
+        let (checked, mismatches) = compare(&pinned, &current, &scope(true), &config);
+        assert_eq!(checked, 2);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].key(), "PATH");
+    }
+
+    #[test]
+    fn test_compare_reports_mismatches() {
+        let config = Config::default();
+        let pinned = snapshot(env(&[("APP_MODE", "prod"), ("APP_REGION", "eu")]));
+        let current = env(&[("APP_MODE", "dev"), ("APP_DEBUG", "1")]);
+
+        let (checked, mismatches) = compare(&pinned, &current, &scope(false), &config);
+        assert_eq!(checked, 3);
+        let keys: Vec<&str> = mismatches.iter().map(|d| d.key()).collect();
+        assert_eq!(keys, ["APP_DEBUG", "APP_MODE", "APP_REGION"]);
+
+        let scope = Scope {
+            ignore: vec![glob::Pattern::new("APP_DEBUG").unwrap()],
+            only: vec![glob::Pattern::new("APP_*").unwrap()],
+            all_vars: false,
+        };
+        let (checked, mismatches) = compare(&pinned, &current, &scope, &config);
+        assert_eq!(checked, 2);
+        assert_eq!(mismatches.len(), 2);
+    }
+}
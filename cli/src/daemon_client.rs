@@ -1,8 +1,10 @@
 use anyhow::{Context, Result};
-use envhist_core::{session::Session, Config};
+use envhist_core::{config::CoreConfig, differ::env_hash, session::Session, Config, Env};
 use envhist_daemon::{EnvEvent, EnvResponse};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
 use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::OnceLock;
 use std::time::Duration;
 
 fn shell_pid() -> Option<u32> {
@@ -14,45 +16,228 @@ fn shell_pid() -> Option<u32> {
     }
 }
 
-pub fn send_event(event: EnvEvent) -> Result<Option<EnvResponse>> {
-    let socket_path = Config::daemon_socket_path();
+/// How hard the CLI tries to reach the daemon, from `core.client_attempts`
+/// and `core.client_timeout_ms`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: usize,
+    /// Limit on each write and read
+    pub timeout: Duration,
+}
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Reach the daemon as configured in `core` from now on.
+pub fn set_retry_policy(core: &CoreConfig) {
+    let _ = POLICY.set(RetryPolicy::from_config(core));
+}
+
+impl RetryPolicy {
+    fn from_config(core: &CoreConfig) -> Self {
+        Self {
+            attempts: core.client_attempts.max(1),
+            timeout: Duration::from_millis(core.client_timeout_ms),
+        }
+    }
+
+    fn configured() -> Self {
+        *POLICY.get_or_init(|| Self::from_config(&CoreConfig::default()))
+    }
 
-    if !socket_path.exists() {
-        // Daemon not running, silently fail
-        return Ok(None);
+    /// A single try, for callers with their own deadline.
+    pub fn once(timeout: Duration) -> Self {
+        Self {
+            attempts: 1,
+            timeout,
+        }
     }
+}
 
-    let mut stream = UnixStream::connect(&socket_path)
-        .with_context(|| format!("Failed to connect to daemon socket {:?}", socket_path))?;
+/// Pause before the second try; doubled before each one after it.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Why a single try at reaching the daemon failed.
+enum Failure {
+    /// No socket: the daemon isn't running
+    Missing,
+    /// Nobody listens on the socket any more
+    Refused,
+    /// Timed out before the event was sent
+    SlowToAccept,
+    /// Timed out waiting for the reply, so the event may have been handled
+    SlowToReply,
+    Other(anyhow::Error),
+}
 
-    stream.set_write_timeout(Some(Duration::from_millis(100)))?;
-    stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+/// Send `event`, retrying per the configured [`RetryPolicy`]. `Ok(None)`
+/// means the daemon is not running.
+pub fn send_event(event: EnvEvent) -> Result<Option<EnvResponse>> {
+    send_event_with(event, RetryPolicy::configured())
+}
+
+/// [`send_event`] with a single try waiting at most `timeout` for each read
+/// and write.
+pub fn send_event_with_timeout(event: EnvEvent, timeout: Duration) -> Result<Option<EnvResponse>> {
+    send_event_with(event, RetryPolicy::once(timeout))
+}
 
+fn send_event_with(event: EnvEvent, policy: RetryPolicy) -> Result<Option<EnvResponse>> {
+    send_event_to(&Config::daemon_socket_path(), event, policy)
+}
+
+fn send_event_to(
+    socket_path: &Path,
+    event: EnvEvent,
+    policy: RetryPolicy,
+) -> Result<Option<EnvResponse>> {
     let event_json = serde_json::to_string(&event)?;
-    writeln!(stream, "{}", event_json)?;
-    stream.flush()?;
+
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 0..policy.attempts {
+        if attempt > 0 {
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+        let last_attempt = attempt + 1 == policy.attempts;
+        match try_send(socket_path, &event_json, policy.timeout) {
+            // The daemon may have recorded part of a non-idempotent event
+            // before failing, so only idempotent ones are resent
+            Ok(EnvResponse::Error { kind, .. })
+                if kind.is_retryable() && event.is_idempotent() && !last_attempt => {}
+            Ok(response) => return Ok(Some(response)),
+            // Daemon not running, silently fail
+            Err(Failure::Missing) => return Ok(None),
+            // Left behind by a daemon that died uncleanly, or one that is
+            // about to bind it again; `daemon repair` and `restart` clear it
+            Err(Failure::Refused) => return Ok(None),
+            Err(Failure::SlowToReply) if !event.is_idempotent() => {
+                anyhow::bail!(
+                    "Daemon at {:?} did not reply within {}ms; the change may not have been recorded",
+                    socket_path,
+                    policy.timeout.as_millis()
+                )
+            }
+            Err(Failure::Other(e)) => return Err(e),
+            // Busy daemon: try again
+            Err(Failure::SlowToAccept | Failure::SlowToReply) => {}
+        }
+    }
+
+    anyhow::bail!(
+        "Daemon at {:?} is running but did not answer within {}ms in {} attempt(s); it may be overloaded",
+        socket_path,
+        policy.timeout.as_millis(),
+        policy.attempts
+    )
+}
+
+fn try_send(
+    socket_path: &Path,
+    event_json: &str,
+    timeout: Duration,
+) -> Result<EnvResponse, Failure> {
+    let mut stream = match UnixStream::connect(socket_path) {
+        Ok(stream) => stream,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(Failure::Missing),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => return Err(Failure::Refused),
+        Err(e) => {
+            return Err(Failure::Other(anyhow::Error::new(e).context(format!(
+                "Failed to connect to daemon socket {:?}",
+                socket_path
+            ))))
+        }
+    };
+
+    stream
+        .set_write_timeout(Some(timeout))
+        .and_then(|_| stream.set_read_timeout(Some(timeout)))
+        .map_err(|e| Failure::Other(e.into()))?;
+
+    writeln!(stream, "{}", event_json)
+        .and_then(|_| stream.flush())
+        .map_err(|e| io_failure(e, Failure::SlowToAccept, "Failed to send event to daemon"))?;
 
     let mut reader = BufReader::new(&mut stream);
     let mut response_line = String::new();
     reader
         .read_line(&mut response_line)
-        .context("Failed to read daemon response")?;
+        .map_err(|e| io_failure(e, Failure::SlowToReply, "Failed to read daemon response"))?;
 
     if response_line.trim().is_empty() {
-        return Ok(Some(EnvResponse::Ok));
+        return Ok(EnvResponse::Ok);
+    }
+
+    serde_json::from_str(response_line.trim())
+        .context("Failed to parse daemon response")
+        .map_err(Failure::Other)
+}
+
+/// `slow` for a timeout, otherwise the error itself.
+fn io_failure(e: std::io::Error, slow: Failure, context: &'static str) -> Failure {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => slow,
+        _ => Failure::Other(anyhow::Error::new(e).context(context)),
+    }
+}
+
+/// Whether `path` is a socket file nobody is listening on.
+pub fn is_stale_socket(path: &Path) -> bool {
+    path.exists()
+        && matches!(UnixStream::connect(path), Err(e) if e.kind() == ErrorKind::ConnectionRefused)
+}
+
+/// Send a capture for `pid` unless `env` hashes the same as the last capture
+/// sent for that shell.
+pub fn send_capture_debounced(pid: u32, env: Env) -> Result<()> {
+    let state_dir = Config::hook_state_dir();
+    let hash_path = state_dir.join(format!("{}.hash", pid));
+    let hash = env_hash(&env);
+    if std::fs::read_to_string(&hash_path).is_ok_and(|last| last == hash) {
+        return Ok(());
     }
 
-    let response: EnvResponse =
-        serde_json::from_str(response_line.trim()).context("Failed to parse daemon response")?;
+    if let Some(EnvResponse::Ok) = send_event(EnvEvent::Capture { pid, env })? {
+        std::fs::create_dir_all(&state_dir).context("Failed to create hook state directory")?;
+        std::fs::write(&hash_path, hash)
+            .with_context(|| format!("Failed to write capture hash {:?}", hash_path))?;
+        prune_hook_state(&state_dir);
+    }
+    Ok(())
+}
+
+/// Remove the capture hashes of shells that have exited. Best effort: a
+/// file that can't be removed is retried on the next capture.
+fn prune_hook_state(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let pid = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".hash"))
+            .and_then(|pid| pid.parse::<u32>().ok());
+        if pid.is_some_and(|pid| !process_exists(pid)) {
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
 
-    Ok(Some(response))
+fn process_exists(pid: u32) -> bool {
+    // Signal 0 only checks; EPERM means it exists under another user
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
 }
 
 pub fn get_session(pid: u32) -> Result<Option<Session>> {
     match send_event(EnvEvent::GetSession { pid })? {
         Some(EnvResponse::Session { session }) => Ok(Some(session)),
-        Some(EnvResponse::Error { message }) => {
-            anyhow::bail!("Daemon error fetching session: {}", message)
+        Some(EnvResponse::Error { kind, message }) => {
+            anyhow::bail!("Daemon error fetching session ({:?}): {}", kind, message)
         }
         _ => Ok(None),
     }
@@ -67,3 +252,148 @@ pub fn get_active_session() -> Result<Option<Session>> {
 
     get_session(std::process::id())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_try_send_missing_and_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.sock");
+        assert!(matches!(
+            try_send(&path, "{}", TIMEOUT),
+            Err(Failure::Missing)
+        ));
+
+        // The socket file outlives the listener
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(matches!(
+            try_send(&path, "{}", TIMEOUT),
+            Err(Failure::Refused)
+        ));
+        assert!(is_stale_socket(&path));
+    }
+
+    #[test]
+    fn test_try_send_slow_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.sock");
+        // Bound but never accepting: connections queue in the backlog
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        assert!(matches!(
+            try_send(&path, "{}", TIMEOUT),
+            Err(Failure::SlowToReply)
+        ));
+
+        // Larger than the socket buffer, so the write itself blocks
+        let huge = "x".repeat(16 << 20);
+        assert!(matches!(
+            try_send(&path, &huge, TIMEOUT),
+            Err(Failure::SlowToAccept)
+        ));
+    }
+
+    /// Answers every connection with an `Io` error, counting them.
+    fn failing_daemon(path: &Path) -> std::thread::JoinHandle<usize> {
+        let listener = UnixListener::bind(path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        std::thread::spawn(move || {
+            let mut served = 0;
+            let deadline = std::time::Instant::now() + Duration::from_millis(500);
+            while std::time::Instant::now() < deadline {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    std::thread::sleep(Duration::from_millis(5));
+                    continue;
+                };
+                stream.set_nonblocking(false).unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                writeln!(
+                    stream,
+                    r#"{{"Error":{{"kind":"Io","message":"disk full"}}}}"#
+                )
+                .unwrap();
+                served += 1;
+            }
+            served
+        })
+    }
+
+    #[test]
+    fn test_retries_io_errors_only_for_idempotent_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = RetryPolicy {
+            attempts: 3,
+            timeout: Duration::from_secs(5),
+        };
+
+        let path = dir.path().join("ping.sock");
+        let daemon = failing_daemon(&path);
+        let response = send_event_to(&path, EnvEvent::Ping, policy).unwrap();
+        assert!(matches!(response, Some(EnvResponse::Error { .. })));
+        assert_eq!(daemon.join().unwrap(), 3);
+
+        let path = dir.path().join("set.sock");
+        let daemon = failing_daemon(&path);
+        let set = EnvEvent::Set {
+            pid: 1,
+            key: "A".to_string(),
+            value: "1".to_string(),
+        };
+        let response = send_event_to(&path, set, policy).unwrap();
+        assert!(matches!(response, Some(EnvResponse::Error { .. })));
+        assert_eq!(daemon.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_prune_hook_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let exited = child.id();
+        child.wait().unwrap();
+
+        let live = dir.path().join(format!("{}.hash", std::process::id()));
+        let dead = dir.path().join(format!("{}.hash", exited));
+        let other = dir.path().join("notes.txt");
+        for path in [&live, &dead, &other] {
+            std::fs::write(path, "x").unwrap();
+        }
+
+        prune_hook_state(dir.path());
+        assert!(live.exists());
+        assert!(!dead.exists());
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn test_try_send_reads_response() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            writeln!(
+                stream,
+                r#"{{"Error":{{"kind":"Io","message":"disk full"}}}}"#
+            )
+            .unwrap();
+        });
+
+        let response = try_send(&path, r#""Ping""#, Duration::from_secs(5));
+        server.join().unwrap();
+        match response {
+            Ok(EnvResponse::Error { kind, message }) => {
+                assert!(kind.is_retryable());
+                assert_eq!(message, "disk full");
+            }
+            _ => panic!("expected an error response"),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use colored::*;
+use envhist_core::config::{DisplayConfig, MaskRule};
+use envhist_core::Catalog;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// `2025-11-07 10:23:45` (UTC)
+    #[default]
+    Human,
+    /// Seconds since the Unix epoch
+    Epoch,
+    /// RFC 3339 with sub-second precision and offset
+    Rfc3339,
+}
+
+impl TimestampFormat {
+    pub fn format(&self, timestamp: DateTime<Utc>) -> String {
+        match self {
+            TimestampFormat::Human => timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+            TimestampFormat::Epoch => timestamp.timestamp().to_string(),
+            TimestampFormat::Rfc3339 => timestamp.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Group by a configured prefix, or the part of the name before the first `_`
+    Prefix,
+}
+
+/// `  # description` for variables documented in the catalog, else empty.
+pub fn description_suffix(catalog: &Catalog, key: &str) -> String {
+    match catalog.describe(key) {
+        Some(description) => format!("  {}", format!("# {}", description).dimmed()),
+        None => String::new(),
+    }
+}
+
+/// `1.5 MiB`-style size, in powers of 1024.
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+static RAW_VALUES: AtomicBool = AtomicBool::new(false);
+
+/// Print values exactly as stored from now on (`--raw`).
+pub fn set_raw_values(raw: bool) {
+    RAW_VALUES.store(raw, Ordering::Relaxed);
+}
+
+/// `value` made safe to print to a terminal: control characters escaped, or
+/// `<binary N bytes>` when they make up over a quarter of it. Returned as is
+/// under `--raw`.
+pub fn display_value(value: &str) -> Cow<'_, str> {
+    let unprintable = |c: char| c.is_control() || c == char::REPLACEMENT_CHARACTER;
+    if RAW_VALUES.load(Ordering::Relaxed) || !value.chars().any(unprintable) {
+        return Cow::Borrowed(value);
+    }
+
+    let count = value.chars().filter(|&c| unprintable(c)).count();
+    if count * 4 > value.chars().count() {
+        return Cow::Owned(format!("<binary {} bytes>", value.len()));
+    }
+
+    Cow::Owned(
+        value
+            .chars()
+            .map(|c| {
+                if unprintable(c) {
+                    c.escape_default().to_string()
+                } else {
+                    c.to_string()
+                }
+            })
+            .collect(),
+    )
+}
+
+static DISPLAY: OnceLock<DisplayConfig> = OnceLock::new();
+
+/// Mask values as configured in `display.mask_patterns` from now on.
+pub fn set_display_config(display: DisplayConfig) {
+    let _ = DISPLAY.set(display);
+}
+
+/// Whether `key`'s values are partly masked when printed.
+pub fn is_masked(key: &str) -> bool {
+    DISPLAY
+        .get()
+        .is_some_and(|display| display.mask_rule(key).is_some())
+}
+
+/// [`display_value`] for a value of `key`, masked if `display.mask_patterns`
+/// covers it.
+pub fn display_key_value<'a>(key: &str, value: &'a str) -> Cow<'a, str> {
+    match DISPLAY.get().and_then(|display| display.mask_rule(key)) {
+        Some(rule) => Cow::Owned(display_value(&rule.apply(value)).into_owned()),
+        None => display_value(value),
+    }
+}
+
+/// Old and new value of a changed `key` for display. When masking hides the
+/// difference, the new value is cut to its visible head and marked
+/// (`abcd…wxyz -> abcd… differs`) so the line still reads as a change.
+pub fn display_change(key: &str, old: &str, new: &str) -> (String, String) {
+    let old_shown = display_key_value(key, old).into_owned();
+    let new_shown = display_key_value(key, new).into_owned();
+    if old_shown != new_shown || old == new {
+        return (old_shown, new_shown);
+    }
+    let Some(rule) = DISPLAY.get().and_then(|display| display.mask_rule(key)) else {
+        return (old_shown, new_shown);
+    };
+    let head = MaskRule { last: 0, ..*rule }.apply(new);
+    (old_shown, format!("{} differs", display_value(&head)))
+}
+
+const UNGROUPED: &str = "other";
+
+/// Group name for a variable: the longest matching configured prefix, else
+/// the first `_`-separated segment.
+pub fn group_name(key: &str, prefixes: &[String]) -> Option<String> {
+    if let Some(prefix) = prefixes
+        .iter()
+        .filter(|p| key.starts_with(p.as_str()))
+        .max_by_key(|p| p.len())
+    {
+        return Some(prefix.trim_end_matches('_').to_string());
+    }
+
+    match key.split_once('_') {
+        Some((segment, _)) if !segment.is_empty() => Some(segment.to_string()),
+        _ => None,
+    }
+}
+
+/// Split items into groups sorted by name, with variables that have no
+/// prefix collected last. Items keep their relative order within a group.
+pub fn group_by_prefix<T>(
+    items: Vec<T>,
+    key: impl Fn(&T) -> &str,
+    prefixes: &[String],
+) -> Vec<(String, Vec<T>)> {
+    let mut groups: BTreeMap<(bool, String), Vec<T>> = BTreeMap::new();
+
+    for item in items {
+        let group = match group_name(key(&item), prefixes) {
+            Some(name) => (false, name),
+            None => (true, UNGROUPED.to_string()),
+        };
+        groups.entry(group).or_default().push(item);
+    }
+
+    groups
+        .into_iter()
+        .map(|((_, name), items)| (name, items))
+        .collect()
+}
+
+/// Rows of cells printed as left-aligned columns. Widths ignore ANSI color
+/// codes, and lines are cut to `width` when one is given.
+#[derive(Default)]
+pub struct Table {
+    rows: Vec<Vec<String>>,
+    indent: usize,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    /// One line per row, in order. The last cell of a row is never padded.
+    pub fn render(&self, width: Option<usize>) -> Vec<String> {
+        let columns = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(row.len().saturating_sub(1)) {
+                widths[i] = widths[i].max(visible_width(cell));
+            }
+        }
+
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut line = " ".repeat(self.indent);
+                for (i, cell) in row.iter().enumerate() {
+                    if i > 0 {
+                        line.push_str("  ");
+                    }
+                    line.push_str(cell);
+                    if i + 1 < row.len() {
+                        let pad = widths[i] - visible_width(cell);
+                        line.push_str(&" ".repeat(pad));
+                    }
+                }
+                line.truncate(line.trim_end_matches(' ').len());
+                match width {
+                    Some(width) => truncate_visible(&line, width),
+                    None => line,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Columns of the terminal on stdout, or `None` when output is piped or
+/// deterministic output was requested.
+pub fn terminal_width() -> Option<usize> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdout().is_terminal() || envhist_core::config::deterministic() {
+        return None;
+    }
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+/// Printed width of `s`, skipping ANSI escape sequences.
+pub fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            skip_escape(&mut chars);
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Cut `s` to at most `width` printed characters, ending in `…` when cut.
+/// Escape sequences are kept and colors reset after a cut.
+fn truncate_visible(s: &str, width: usize) -> String {
+    if visible_width(s) <= width {
+        return s.to_string();
+    }
+
+    let mut output = String::new();
+    let mut remaining = width.saturating_sub(1);
+    let mut escaped = false;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            output.push(c);
+            output.push_str(&skip_escape(&mut chars));
+            escaped = true;
+        } else if remaining > 0 {
+            output.push(c);
+            remaining -= 1;
+        }
+    }
+    if width > 0 {
+        output.push('…');
+    }
+    if escaped {
+        output.push_str("\x1b[0m");
+    }
+    output
+}
+
+/// Consume the rest of a CSI sequence (`ESC [ ... final`) and return it.
+fn skip_escape(chars: &mut std::str::Chars) -> String {
+    let mut sequence = String::new();
+    if chars.clone().next() != Some('[') {
+        return sequence;
+    }
+    for c in chars.by_ref() {
+        sequence.push(c);
+        if sequence.len() > 1 && ('\x40'..='\x7e').contains(&c) {
+            break;
+        }
+    }
+    sequence
+}
@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use envhist_core::Config;
+use std::process::Command;
+
+/// Operations that can trigger a `post_*` hook.
+#[derive(Clone, Copy, Debug)]
+pub enum Hook {
+    Snapshot,
+    Restore,
+    Delete,
+}
+
+impl Hook {
+    fn name(&self) -> &'static str {
+        match self {
+            Hook::Snapshot => "post_snapshot",
+            Hook::Restore => "post_restore",
+            Hook::Delete => "post_delete",
+        }
+    }
+
+    fn command<'a>(&self, config: &'a Config) -> Option<&'a str> {
+        let hooks = &config.hooks;
+        match self {
+            Hook::Snapshot => hooks.post_snapshot.as_deref(),
+            Hook::Restore => hooks.post_restore.as_deref(),
+            Hook::Delete => hooks.post_delete.as_deref(),
+        }
+    }
+}
+
+/// Run the configured command for `hook`, if any. Failures only warn unless
+/// `hooks.strict` is set. The hook's stdout goes to stderr so it can't end up
+/// in output meant for `eval`.
+pub fn run(config: &Config, hook: Hook, snapshot_name: &str) -> Result<()> {
+    let Some(command) = hook.command(config) else {
+        return Ok(());
+    };
+
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("ENVHIST_HOOK", hook.name())
+        .env("ENVHIST_SNAPSHOT_NAME", snapshot_name)
+        .stdout(std::io::stderr())
+        .status()
+        .with_context(|| format!("Failed to run {} hook", hook.name()))
+        .and_then(|status| {
+            if status.success() {
+                Ok(())
+            } else {
+                anyhow::bail!("{} hook exited with {}", hook.name(), status)
+            }
+        });
+
+    match result {
+        Err(e) if !config.hooks.strict => {
+            eprintln!("Warning: {:#}", e);
+            Ok(())
+        }
+        result => result,
+    }
+}
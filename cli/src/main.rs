@@ -3,12 +3,35 @@ use clap::{Args, Parser, Subcommand};
 
 mod commands;
 mod daemon_client;
+mod format;
+mod hooks;
 mod shell;
 
+use commands::diff::DiffSort;
+use commands::export::ExportFormat;
+use commands::snapshot::ListMode;
+use envhist_core::audit::{self, AuditEntry};
+use format::{GroupBy, TimestampFormat};
+use shell::ShellKind;
+use std::path::PathBuf;
+
 #[derive(Parser)]
 #[command(name = "envhist")]
 #[command(about = "Git for environment variables", long_about = None)]
 struct Cli {
+    /// Disable colored output (also off when stdout is not a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Print values with control characters as is instead of escaped
+    #[arg(long, global = true)]
+    raw: bool,
+
+    /// Layer the config's [profiles.<NAME>] section over its base settings
+    /// (default from ENVHIST_PROFILE)
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -20,42 +43,173 @@ enum Commands {
         /// Check installation status
         #[arg(long)]
         check: bool,
+        /// Print the shell integration to stdout instead of installing it
+        #[arg(long, conflicts_with = "check")]
+        print_hook: bool,
+        /// Shell to print the integration for
+        #[arg(long, value_enum, requires = "print_hook")]
+        shell: Option<ShellKind>,
     },
     /// Save current environment as a snapshot
     Snapshot(SnapshotArgs),
     /// List all snapshots
-    List,
+    List {
+        /// How to print timestamps
+        #[arg(long, value_enum, default_value_t)]
+        timestamp: TimestampFormat,
+        /// Only snapshots taken with --tag-from-branch on this git branch
+        #[arg(long, value_name = "NAME")]
+        branch: Option<String>,
+    },
     /// Restore a snapshot
-    Restore {
+    Restore(RestoreArgs),
+    /// Delete a snapshot, or every snapshot matching the selectors
+    Delete(DeleteArgs),
+    /// Move a snapshot from this session to the global scope
+    Promote {
         /// Snapshot name
         name: String,
-        /// Preview changes without applying
+        /// Overwrite a global snapshot with the same name
         #[arg(long)]
-        dry_run: bool,
+        force: bool,
     },
-    /// Delete a snapshot
-    Delete {
+    /// Move a global snapshot into a session
+    Demote {
         /// Snapshot name
         name: String,
-    },
-    /// Show changes since last snapshot
-    Status,
-    /// Show timeline of environment changes
-    Log {
-        /// Filter by time (e.g., "1 hour ago")
+        /// Target session id or prefix (defaults to this session)
         #[arg(long)]
-        since: Option<String>,
-        /// Filter by variable name pattern
+        session: Option<String>,
+        /// Overwrite a session snapshot with the same name
         #[arg(long)]
-        grep: Option<String>,
+        force: bool,
     },
+    /// Show changes since last snapshot
+    Status(StatusArgs),
+    /// Show timeline of environment changes
+    Log(LogArgs),
     /// Show history of a specific variable
     Show {
         /// Variable name
         name: String,
+        /// How to print timestamps
+        #[arg(long, value_enum, default_value_t)]
+        timestamp: TimestampFormat,
+        /// Include the variable's history from every session, marked by session id
+        #[arg(long)]
+        merge_sessions: bool,
+        /// Show each entry's sequence number within its session
+        #[arg(long, conflicts_with = "at")]
+        seq: bool,
+        /// Print only the value at this moment (`2025-11-07 15:00`, RFC 3339, `2 hours ago`, ...)
+        #[arg(long, value_name = "TIME")]
+        at: Option<String>,
+    },
+    /// Attach a note to the latest timeline entry for a variable
+    Note {
+        /// Variable name
+        name: String,
+        /// Why it changed
+        text: String,
     },
     /// Show differences between environments
     Diff(DiffArgs),
+    /// Print the timeline as a replayable shell script
+    Replay {
+        /// Filter by time: today, yesterday, "this week" (in display.timezone), "1 hour ago" or 90m
+        #[arg(long)]
+        since: Option<String>,
+        /// Replay another session (id or id prefix)
+        #[arg(long)]
+        session: Option<String>,
+    },
+    /// Export a snapshot as a dotenv, shell, JSON or YAML file
+    Export(ExportArgs),
+    /// Import a dotenv, shell or JSON file or a snapshot bundle
+    Import(ImportArgs),
+    /// Move old sessions and snapshots out of the store into a compressed archive
+    Archive {
+        /// Archive sessions and snapshots older than this age (e.g. 90d)
+        #[arg(long, value_name = "AGE", value_parser = envhist_core::duration::parse_duration)]
+        older_than: chrono::Duration,
+        /// Archive file to write (.tar.zst)
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Bring sessions and snapshots back from an archive
+    ImportArchive {
+        /// Archive file made by `envhist archive`
+        file: PathBuf,
+    },
+    /// Explain whether a variable would be tracked, and which filter rule decided
+    CheckFilter {
+        /// Variable name
+        key: String,
+        /// Value to check as well
+        value: Option<String>,
+    },
+    /// Scan a snapshot or env file for secrets (exits 1 if any are found)
+    Scan {
+        /// Snapshot name or path to a snapshot/env file
+        target: String,
+    },
+    /// Check the current environment against a snapshot (exits 1 on any difference)
+    Verify {
+        /// Snapshot the environment must match
+        snapshot: String,
+        /// Leave variables matching this glob out of the check (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        ignore: Vec<String>,
+        /// Only check variables matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        only: Vec<String>,
+        /// Also check variables the filters don't track (PATH, HOME, ...)
+        #[arg(long)]
+        all_vars: bool,
+    },
+    /// Find groups of nearly identical snapshots
+    Dedup {
+        /// Percentage of matching variables for two snapshots to count as duplicates
+        #[arg(long, value_name = "PERCENT", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+        threshold: u8,
+        /// Delete all but the newest snapshot in each group
+        #[arg(long)]
+        apply: bool,
+        /// With --apply, don't ask for confirmation
+        #[arg(short, long, requires = "apply")]
+        yes: bool,
+    },
+    /// Show snapshots side by side, one row per variable
+    Table {
+        /// Snapshots to compare, one column each
+        #[arg(required = true, num_args = 2..)]
+        snapshots: Vec<String>,
+        /// Only variables whose value differs between the snapshots
+        #[arg(long)]
+        changed: bool,
+    },
+    /// Show the log of commands that changed the store
+    Audit {
+        /// Show only the last N entries
+        #[arg(short = 'n', long, value_name = "N")]
+        limit: Option<usize>,
+        /// Print entries as JSON lines
+        #[arg(long)]
+        json: bool,
+        /// How to print timestamps
+        #[arg(long, value_enum, default_value_t)]
+        timestamp: TimestampFormat,
+    },
+    /// Manage snapshot tags
+    Tag {
+        #[command(subcommand)]
+        action: TagCommand,
+    },
+    /// Manage the current shell session
+    Session {
+        #[command(subcommand)]
+        action: SessionCommand,
+    },
     /// Daemon management
     Daemon {
         #[command(subcommand)]
@@ -66,11 +220,63 @@ enum Commands {
         pid: u32,
         key: String,
         value: String,
+        /// Print the event and whether it would be tracked instead of sending it
+        #[arg(long)]
+        echo: bool,
     },
     /// Send unset event to daemon (internal use)
-    SendUnset { pid: u32, key: String },
+    SendUnset {
+        pid: u32,
+        key: String,
+        /// Print the event and whether it would be tracked instead of sending it
+        #[arg(long)]
+        echo: bool,
+    },
     /// Send capture event to daemon (internal use)
-    SendCapture { pid: u32 },
+    SendCapture {
+        pid: u32,
+        /// Print the event and how many variables would be tracked instead of sending it
+        #[arg(long)]
+        echo: bool,
+    },
+    /// Send directory change event to daemon (internal use)
+    SendChdir {
+        pid: u32,
+        dir: String,
+        /// Print the event and how many variables would be tracked instead of sending it
+        #[arg(long)]
+        echo: bool,
+    },
+    /// Send the full environment as the session baseline (internal use)
+    SendSetMany {
+        pid: u32,
+        /// Print the event and how many variables would be tracked instead of sending it
+        #[arg(long)]
+        echo: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionCommand {
+    /// Label the current session so logs can be filtered by it
+    Tag {
+        /// Tag to add
+        tag: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagCommand {
+    /// List tags in use with how many snapshots carry each
+    List,
+    /// Tag every snapshot whose name matches a glob
+    Add {
+        /// Tag to add
+        tag: String,
+        /// Glob the snapshot names must match
+        #[arg(long, value_name = "GLOB")]
+        matching: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -79,8 +285,20 @@ enum DaemonCommand {
     Start,
     /// Stop the daemon
     Stop,
+    /// Stop the daemon if it is running and start a fresh one
+    Restart,
+    /// Clear a stale socket left by a crashed daemon and start a fresh one
+    Repair,
     /// Check daemon status
     Status,
+    /// Write a systemd user unit (or launchd agent on macOS) that runs the daemon
+    InstallService,
+    /// Exit non-zero unless the daemon answers a ping (for supervisors)
+    Healthcheck {
+        /// How long to wait for the reply, in milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        timeout: u64,
+    },
     /// Run the daemon (internal use)
     #[command(hide = true)]
     Run,
@@ -88,26 +306,142 @@ enum DaemonCommand {
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    if cli.no_color || envhist_core::config::deterministic() {
+        colored::control::set_override(false);
+    }
+    format::set_raw_values(cli.raw);
+    if let Some(ref profile) = cli.profile {
+        // Config::load reads the profile from the environment
+        std::env::set_var(envhist_core::config::ENVHIST_PROFILE_VAR, profile);
+    }
+    envhist_core::Config::try_base_dir()?;
+    let config = envhist_core::Config::load()?;
+    daemon_client::set_retry_policy(&config.core);
+    format::set_display_config(config.display);
 
-    match cli.command {
-        Commands::Init { check } => commands::init::init(check),
+    let audited = audited_command(&cli.command);
+    let result = run(cli.command);
+    if let Some(command) = audited {
+        let entry = AuditEntry::new(command, std::env::args().skip(1).collect(), &result);
+        if let Err(e) = audit::append(&envhist_core::Config::audit_log_path(), &entry) {
+            eprintln!("⚠ Failed to record the audit log: {:#}", e);
+        }
+    }
+    match result {
+        // Already reported by the command
+        Err(e) if e.is::<commands::Failed>() => std::process::exit(1),
+        result => result,
+    }
+}
+
+/// Name recorded in the audit log for commands that change the store.
+fn audited_command(command: &Commands) -> Option<&'static str> {
+    Some(match command {
+        Commands::Snapshot(_) => "snapshot",
+        Commands::Restore(_) => "restore",
+        Commands::Delete(_) => "delete",
+        Commands::Dedup { apply: true, .. } => "dedup",
+        Commands::Promote { .. } => "promote",
+        Commands::Demote { .. } => "demote",
+        Commands::Note { .. } => "note",
+        Commands::Import(_) => "import",
+        Commands::Archive { .. } => "archive",
+        Commands::ImportArchive { .. } => "import-archive",
+        Commands::Tag {
+            action: TagCommand::Add { .. },
+        } => "tag add",
+        Commands::Session {
+            action: SessionCommand::Tag { .. },
+        } => "session tag",
+        _ => return None,
+    })
+}
+
+fn run(command: Commands) -> Result<()> {
+    match command {
+        Commands::Init {
+            check,
+            print_hook,
+            shell,
+        } => {
+            if print_hook {
+                commands::init::print_hook(shell.unwrap_or_default())
+            } else {
+                commands::init::init(check)
+            }
+        }
         Commands::Snapshot(args) => commands::snapshot::snapshot(args),
-        Commands::List => commands::snapshot::list(),
-        Commands::Restore { name, dry_run } => commands::snapshot::restore(name, dry_run),
-        Commands::Delete { name } => commands::snapshot::delete(name),
-        Commands::Status => commands::status::status(),
-        Commands::Log { since, grep } => commands::log::log(since, grep),
-        Commands::Show { name } => commands::log::show(name),
+        Commands::List { timestamp, branch } => commands::snapshot::list(timestamp, branch),
+        Commands::Restore(args) => commands::snapshot::restore(args),
+        Commands::Delete(args) => commands::snapshot::delete(args),
+        Commands::Promote { name, force } => commands::snapshot::promote(name, force),
+        Commands::Demote {
+            name,
+            session,
+            force,
+        } => commands::snapshot::demote(name, session, force),
+        Commands::Status(args) => commands::status::status(args),
+        Commands::Log(args) => commands::log::log(args),
+        Commands::Show {
+            name,
+            timestamp,
+            merge_sessions,
+            seq,
+            at,
+        } => commands::log::show(name, timestamp, merge_sessions, seq, at),
+        Commands::Note { name, text } => commands::log::note(name, text),
         Commands::Diff(args) => commands::diff::diff(args),
+        Commands::Verify {
+            snapshot,
+            ignore,
+            only,
+            all_vars,
+        } => commands::verify::verify(snapshot, ignore, only, all_vars),
+        Commands::Dedup {
+            threshold,
+            apply,
+            yes,
+        } => commands::snapshot::dedup(threshold, apply, yes),
+        Commands::Table { snapshots, changed } => commands::table::table(snapshots, changed),
+        Commands::Replay { since, session } => commands::log::replay(since, session),
+        Commands::Export(args) => commands::export::export(args),
+        Commands::Import(args) => commands::export::import(args),
+        Commands::Archive { older_than, output } => commands::archive::archive(older_than, output),
+        Commands::ImportArchive { file } => commands::archive::import_archive(file),
+        Commands::CheckFilter { key, value } => commands::scan::check_filter(key, value),
+        Commands::Scan { target } => commands::scan::scan(target),
+        Commands::Audit {
+            limit,
+            json,
+            timestamp,
+        } => commands::audit::audit(limit, json, timestamp),
+        Commands::Tag { action } => match action {
+            TagCommand::List => commands::snapshot::tag_list(),
+            TagCommand::Add { tag, matching } => commands::snapshot::tag_add(tag, matching),
+        },
+        Commands::Session { action } => match action {
+            SessionCommand::Tag { tag } => commands::log::tag_session(tag),
+        },
         Commands::Daemon { action } => match action {
             DaemonCommand::Start => commands::init::start_daemon(),
             DaemonCommand::Stop => commands::init::stop_daemon(),
+            DaemonCommand::Restart => commands::init::restart_daemon(),
+            DaemonCommand::Repair => commands::init::repair_daemon(),
             DaemonCommand::Status => commands::init::daemon_status(),
+            DaemonCommand::InstallService => commands::service::install_service(),
+            DaemonCommand::Healthcheck { timeout } => commands::init::healthcheck(timeout),
             DaemonCommand::Run => commands::init::run_daemon(),
         },
-        Commands::SendSet { pid, key, value } => commands::init::send_set(pid, key, value),
-        Commands::SendUnset { pid, key } => commands::init::send_unset(pid, key),
-        Commands::SendCapture { pid } => commands::init::send_capture(pid),
+        Commands::SendSet {
+            pid,
+            key,
+            value,
+            echo,
+        } => commands::init::send_set(pid, key, value, echo),
+        Commands::SendUnset { pid, key, echo } => commands::init::send_unset(pid, key, echo),
+        Commands::SendCapture { pid, echo } => commands::init::send_capture(pid, echo),
+        Commands::SendChdir { pid, dir, echo } => commands::init::send_chdir(pid, dir, echo),
+        Commands::SendSetMany { pid, echo } => commands::init::send_set_many(pid, echo),
     }
 }
 
@@ -122,17 +456,311 @@ pub struct SnapshotArgs {
     /// Store snapshot only for the current session
     #[arg(long)]
     pub session: bool,
+    /// Capture the environment of a running Docker container instead
+    #[arg(long, value_name = "CONTAINER")]
+    pub docker: Option<String>,
+    /// Read `KEY=VALUE` lines (e.g. piped from `env`) from stdin instead
+    #[arg(long, conflicts_with = "docker")]
+    pub stdin: bool,
+    /// Build the snapshot by applying a diff (from `diff --json`) to --base
+    #[arg(long, value_name = "FILE", requires = "base", conflicts_with_all = ["docker", "stdin"])]
+    pub from_diff: Option<PathBuf>,
+    /// Snapshot the --from-diff patch applies to
+    #[arg(long, value_name = "SNAPSHOT", requires = "from_diff")]
+    pub base: Option<String>,
+    /// Apply --from-diff entries that don't match --base anyway, with a warning
+    #[arg(long, requires = "from_diff")]
+    pub allow_conflicts: bool,
+    /// Keep variables the tracking filters would drop from --stdin or --docker input
+    #[arg(long)]
+    pub force: bool,
+    /// Skip saving when nothing changed since the most recent snapshot
+    #[arg(long)]
+    pub if_changed: bool,
+    /// Print the file the snapshot was written to
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Store the snapshot gzipped (default from core.compress_snapshots)
+    #[arg(long)]
+    pub compress: bool,
+    /// Record only which variables are set, with every value blanked
+    #[arg(long)]
+    pub keys_only: bool,
+    /// Tag the snapshot `branch:<name>` with the current git branch, if any
+    #[arg(long)]
+    pub tag_from_branch: bool,
+    /// Let the daemon delete the snapshot once it is this old (e.g. 7d, 12h)
+    #[arg(long, value_name = "AGE", value_parser = envhist_core::duration::parse_duration)]
+    pub expire: Option<chrono::Duration>,
+}
+
+#[derive(Args, Clone, Debug)]
+#[command(group(
+    clap::ArgGroup::new("selector")
+        .args(["name", "all_matching", "tag", "older_than", "expired"])
+        .multiple(true)
+        .required(true)
+))]
+pub struct DeleteArgs {
+    /// Snapshot name
+    #[arg(conflicts_with_all = ["all_matching", "tag", "older_than", "expired"])]
+    pub name: Option<String>,
+    /// Delete snapshots whose name matches a glob
+    #[arg(long, value_name = "GLOB")]
+    pub all_matching: Option<String>,
+    /// Delete snapshots carrying this tag
+    #[arg(long)]
+    pub tag: Option<String>,
+    /// Delete snapshots older than this age (e.g. 30d, 12h)
+    #[arg(long, value_name = "AGE", value_parser = envhist_core::duration::parse_duration)]
+    pub older_than: Option<chrono::Duration>,
+    /// Delete snapshots past their --expire time without waiting for the daemon
+    #[arg(long)]
+    pub expired: bool,
+    /// List the snapshots that would be deleted
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Don't ask for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+    /// Carry on past snapshots that fail to delete, then report them and exit 1
+    #[arg(long, conflicts_with = "name")]
+    pub keep_going: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct RestoreArgs {
+    /// Snapshot name
+    pub name: String,
+    /// Show the changes restore would make without applying them
+    #[arg(long, visible_alias = "preview-diff")]
+    pub dry_run: bool,
+    /// With --dry-run, list every variable in the snapshot instead of the diff
+    #[arg(long, requires = "dry_run")]
+    pub full: bool,
+    /// Layer the snapshot over the current env, keeping other variables (default)
+    #[arg(long, conflicts_with = "replace")]
+    pub merge: bool,
+    /// Make the env match the snapshot, unsetting tracked variables it lacks
+    #[arg(long)]
+    pub replace: bool,
+    /// With --replace, also unset ignore_system variables the snapshot lacks
+    #[arg(long, requires = "replace")]
+    pub include_system: bool,
+    /// With --include-system, allow unsetting protected variables like PATH and HOME
+    #[arg(long, requires = "include_system")]
+    pub force: bool,
+    /// How to restore variables configured in display.list_vars
+    #[arg(long, value_enum, default_value_t)]
+    pub lists: ListMode,
+    /// Skip the confirmation prompt
+    #[arg(short, long)]
+    pub yes: bool,
+    /// Write the commands to a file (mode 0600) to `source` instead of printing them
+    #[arg(long, value_name = "PATH")]
+    pub write: Option<PathBuf>,
+    /// Shell to print the commands for (default: POSIX `export`/`unset`)
+    #[arg(long, value_enum)]
+    pub shell: Option<ShellKind>,
+    /// Use `declare -x` (bash, the default here), `typeset -gx` (zsh) or `set -gx` (fish)
+    #[arg(long)]
+    pub declare: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ExportArgs {
+    /// Snapshot name
+    #[arg(required_unless_present = "all", conflicts_with = "all")]
+    pub name: Option<String>,
+    /// Dump the whole store (config, snapshots and sessions) as JSON; with
+    /// --format bundle, every snapshot as one bundle
+    #[arg(long)]
+    pub all: bool,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: ExportFormat,
+    /// Shell syntax for --format shell
+    #[arg(long, value_enum, default_value_t)]
+    pub shell: ShellKind,
+    /// Write to a file instead of stdout (bundles default to
+    /// NAME.envhist.json, or snapshots.envhist.json with --all)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// With --all, leave out sessions and snapshots that fail to read, then
+    /// report them and exit 1
+    #[arg(long, requires = "all")]
+    pub keep_going: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ImportArgs {
+    /// File to import
+    pub file: PathBuf,
+    /// Snapshot name (defaults to the file name)
+    #[arg(long, conflicts_with = "all")]
+    pub name: Option<String>,
+    /// Restore a whole-store dump made by `export --all`
+    #[arg(long)]
+    pub all: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct StatusArgs {
+    /// Print one line per change (A/D/M KEY) without values
+    #[arg(short, long)]
+    pub short: bool,
+    /// Print a compact drift marker (e.g. ±3) for shell prompts, nothing when clean
+    #[arg(long, conflicts_with = "short")]
+    pub prompt: bool,
+    /// Group changes under headers
+    #[arg(long, value_enum)]
+    pub group_by: Option<GroupBy>,
+    /// Print only the summary counts, without the per-change detail
+    #[arg(long, conflicts_with_all = ["short", "prompt", "group_by"])]
+    pub summary_only: bool,
+    /// Redraw every N seconds (default 2) until interrupted with Ctrl-C
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "2",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "prompt"
+    )]
+    pub watch: Option<u64>,
+    /// Only report variables listed in this file (one name per line, `#` comments)
+    #[arg(long, value_name = "FILE", conflicts_with = "prompt")]
+    pub only_from: Option<PathBuf>,
+    /// List unchanged variables too, dimmed
+    #[arg(long, visible_alias = "all", conflicts_with_all = ["short", "prompt", "summary_only"])]
+    pub show_unchanged: bool,
+    /// Print the changes, their names by kind and the summary counts as JSON
+    #[arg(long, conflicts_with_all = ["short", "prompt", "group_by", "watch"])]
+    pub json: bool,
+    /// Leave the per-change `diffs` array out of --json output
+    #[arg(long, requires = "json")]
+    pub name_only: bool,
+    /// Report how much disk space the envhist store uses instead
+    #[arg(long, conflicts_with_all = ["short", "prompt", "group_by", "summary_only", "watch", "only_from", "show_unchanged", "json"])]
+    pub store: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct LogArgs {
+    /// Filter by time: today, yesterday, "this week" (in display.timezone), "1 hour ago" or 90m
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Filter by variable name pattern
+    #[arg(long)]
+    pub grep: Option<String>,
+    /// Group entries under headers
+    #[arg(long, value_enum)]
+    pub group_by: Option<GroupBy>,
+    /// Show an unset of one variable followed by setting another to its value as a rename
+    #[arg(long)]
+    pub detect_renames: bool,
+    /// How to print timestamps
+    #[arg(long, value_enum, default_value_t)]
+    pub timestamp: TimestampFormat,
+    /// Merge the timelines of every session on disk
+    #[arg(long)]
+    pub all_sessions: bool,
+    /// With --all-sessions, only include sessions carrying this tag
+    #[arg(long, value_name = "TAG", requires = "all_sessions")]
+    pub session_tag: Option<String>,
+    /// Print how many times each variable changed instead of the entries
+    #[arg(long, conflicts_with_all = ["group_by", "detect_renames"])]
+    pub stat: bool,
+    /// Show each entry's sequence number within its session
+    #[arg(long, conflicts_with = "stat")]
+    pub seq: bool,
+    /// Only changes to this variable
+    #[arg(long, value_name = "VAR", conflicts_with = "grep")]
+    pub follow: Option<String>,
+    /// With --follow, also changes to variables whose value contains the followed one's
+    #[arg(long, requires = "follow")]
+    pub derivations: bool,
+    /// Lead each entry with how often its variable changes (×N), busiest in red
+    #[arg(long, conflicts_with = "stat")]
+    pub hot: bool,
+    /// Plot every session's changes as a lane in one time-ordered graph
+    #[arg(long, conflicts_with_all = ["stat", "group_by", "detect_renames", "follow", "hot"])]
+    pub graph: bool,
 }
 
 #[derive(Args, Clone, Debug)]
 pub struct DiffArgs {
-    /// First snapshot (defaults to latest)
+    /// First snapshot (defaults to latest), or `-` to read `KEY=VALUE` lines from stdin
     #[arg()]
     pub snapshot1: Option<String>,
-    /// Second snapshot (defaults to current env)
+    /// Second snapshot (defaults to current env), or `-` to read from stdin
     #[arg()]
     pub snapshot2: Option<String>,
     /// Print ready-to-run commands to restore snapshot values
     #[arg(long)]
     pub exports: bool,
+    /// Compare against the shell's environment as captured by the daemon
+    #[arg(long, conflicts_with = "snapshot2")]
+    pub against_session: bool,
+    /// Number of unchanged variables, or lines of a multiline value, to show
+    /// around each change (defaults to display.diff_context)
+    #[arg(long, value_name = "N")]
+    pub context: Option<usize>,
+    /// Common ancestor snapshot for a three-way diff
+    #[arg(long, requires_all = ["ours", "theirs"], conflicts_with_all = ["snapshot1", "snapshot2"])]
+    pub base: Option<String>,
+    /// First side of a three-way diff
+    #[arg(long, requires = "base")]
+    pub ours: Option<String>,
+    /// Second side of a three-way diff
+    #[arg(long, requires = "base")]
+    pub theirs: Option<String>,
+    /// Group changes under headers
+    #[arg(long, value_enum)]
+    pub group_by: Option<GroupBy>,
+    /// Order of the listed variables
+    #[arg(long, value_enum, default_value_t, conflicts_with_all = ["base", "recent"])]
+    pub sort: DiffSort,
+    /// Only compare variables listed in this file (one name per line, `#` comments)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["base", "recent"])]
+    pub only_from: Option<PathBuf>,
+    /// List unchanged variables too, dimmed
+    #[arg(
+        long,
+        visible_alias = "all",
+        conflicts_with_all = ["base", "json", "only_secrets", "summary_only", "grouped"]
+    )]
+    pub show_unchanged: bool,
+    /// List added, removed and changed variables in separate sections
+    #[arg(long, conflicts_with_all = ["base", "group_by", "only_secrets", "summary_only", "recent"])]
+    pub grouped: bool,
+    /// Print the diff and summary counts as JSON
+    #[arg(long, conflicts_with_all = ["base", "exports", "group_by", "grouped"])]
+    pub json: bool,
+    /// Only show changes to secret-looking variables, with values masked
+    /// (with --json, only together with --summary-only)
+    #[arg(long, conflicts_with_all = ["base", "exports", "group_by"])]
+    pub only_secrets: bool,
+    /// Compare against each of the N most recent snapshots in turn
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["snapshot1", "base", "exports", "group_by", "json", "only_secrets"]
+    )]
+    pub recent: Option<usize>,
+    /// Treat values that differ only in case as unchanged
+    #[arg(long)]
+    pub ignore_case: bool,
+    /// Treat values that differ only in surrounding or repeated whitespace as unchanged
+    #[arg(long)]
+    pub ignore_whitespace: bool,
+    /// Print only the summary counts, without the per-variable detail
+    #[arg(long, conflicts_with_all = ["base", "exports", "group_by"])]
+    pub summary_only: bool,
+    /// Exit with 1 when the environments differ and 0 when they match
+    #[arg(long, conflicts_with = "recent")]
+    pub exit_code: bool,
+    /// Print nothing; implies --exit-code
+    #[arg(short, long, conflicts_with = "recent")]
+    pub quiet: bool,
 }
@@ -0,0 +1,43 @@
+use super::posix;
+
+/// With `capture_on_cd`, also records directory changes.
+pub fn generate_init_script(capture_on_cd: bool) -> String {
+    if capture_on_cd {
+        format!(
+            "{}{}{}{}",
+            posix::WRAPPERS,
+            posix::CHPWD,
+            BASH_CD_HOOKS,
+            posix::SESSION_START
+        )
+    } else {
+        format!("{}{}{}", posix::WRAPPERS, BASH_HOOKS, posix::SESSION_START)
+    }
+}
+
+const BASH_HOOKS: &str = r#"# Run the hook before each prompt (bash)
+_envhist_prompt_command() {
+    _envhist_precmd
+}
+
+if [ -n "$BASH_VERSION" ]; then
+    PROMPT_COMMAND="_envhist_prompt_command${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+
+"#;
+
+/// bash has no precmd or chpwd hooks, so both run from PROMPT_COMMAND.
+const BASH_CD_HOOKS: &str = r#"# Run the hooks before each prompt (bash)
+_envhist_prompt_command() {
+    _envhist_precmd
+    if [ "$PWD" != "$_envhist_last_pwd" ]; then
+        [ -n "$_envhist_last_pwd" ] && _envhist_chpwd
+        _envhist_last_pwd="$PWD"
+    fi
+}
+
+if [ -n "$BASH_VERSION" ]; then
+    PROMPT_COMMAND="_envhist_prompt_command${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+
+"#;
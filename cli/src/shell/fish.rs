@@ -0,0 +1,52 @@
+/// With `capture_on_cd`, also watches `PWD` to record directory changes.
+pub fn generate_init_script(capture_on_cd: bool) -> String {
+    if capture_on_cd {
+        format!("{}{}{}", FISH_SCRIPT, FISH_CHPWD, FISH_SESSION_START)
+    } else {
+        format!("{}{}", FISH_SCRIPT, FISH_SESSION_START)
+    }
+}
+
+/// fish can't alias `export`/`unset`, so this replaces fish's own `export`
+/// function and hooks prompts and `PWD` through fish events.
+const FISH_SCRIPT: &str = r#"# envhist shell integration
+# This file is automatically generated by envhist init
+
+function export --description 'Set an environment variable and record it with envhist'
+    for arg in $argv
+        set -l parts (string split -m 1 = -- $arg)
+        if not set -q parts[2]
+            set -gx $parts[1] $$parts[1]
+            continue
+        end
+
+        envhist send-set $fish_pid $parts[1] $parts[2] 2>/dev/null
+        if contains -- $parts[1] PATH CDPATH MANPATH
+            set -gx $parts[1] (string split : -- $parts[2])
+        else
+            set -gx $parts[1] $parts[2]
+        end
+    end
+end
+
+function _envhist_precmd --on-event fish_prompt
+    # Capture env state before every 10th prompt to reduce overhead
+    set -q _envhist_counter; or set -g _envhist_counter 0
+    set -g _envhist_counter (math $_envhist_counter + 1)
+    if test (math $_envhist_counter % 10) -eq 0
+        envhist send-capture $fish_pid 2>/dev/null
+    end
+end
+
+"#;
+
+const FISH_CHPWD: &str = r#"function _envhist_chpwd --on-variable PWD
+    # Marks the directory change in the timeline
+    envhist send-chdir $fish_pid "$PWD" 2>/dev/null
+end
+
+"#;
+
+const FISH_SESSION_START: &str = r#"# Register the starting environment as this session's baseline
+envhist send-set-many $fish_pid 2>/dev/null
+"#;
@@ -1 +1,78 @@
+use clap::ValueEnum;
+use envhist_core::envfile;
+
+pub mod bash;
+pub mod fish;
+mod posix;
 pub mod zsh;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShellKind {
+    #[default]
+    Zsh,
+    Bash,
+    Fish,
+}
+
+impl ShellKind {
+    /// The integration snippet `envhist init` installs for this shell. The
+    /// directory hook is only included with `capture_on_cd`.
+    pub fn init_script(&self, capture_on_cd: bool) -> String {
+        match self {
+            ShellKind::Zsh => zsh::generate_init_script(capture_on_cd),
+            ShellKind::Bash => bash::generate_init_script(capture_on_cd),
+            ShellKind::Fish => fish::generate_init_script(capture_on_cd),
+        }
+    }
+
+    pub fn export_line(&self, key: &str, value: &str) -> String {
+        match self {
+            ShellKind::Zsh | ShellKind::Bash => export_line(key, value),
+            ShellKind::Fish => envfile::fish_export_line(key, value),
+        }
+    }
+
+    /// The shell's declaration-builtin form of [`Self::export_line`].
+    pub fn declare_line(&self, key: &str, value: &str) -> String {
+        match self {
+            ShellKind::Zsh => envfile::zsh_typeset_line(key, value),
+            ShellKind::Bash => envfile::bash_declare_line(key, value),
+            ShellKind::Fish => envfile::fish_export_line(key, value),
+        }
+    }
+
+    pub fn unset_line(&self, key: &str) -> String {
+        match self {
+            ShellKind::Zsh | ShellKind::Bash => unset_line(key),
+            ShellKind::Fish => format!("set -e {}", key),
+        }
+    }
+}
+
+pub fn export_line(key: &str, value: &str) -> String {
+    envfile::posix_export_line(key, value)
+}
+
+pub fn unset_line(key: &str) -> String {
+    format!("unset {}", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chdir_hook_follows_capture_on_cd() {
+        for shell in [ShellKind::Zsh, ShellKind::Bash, ShellKind::Fish] {
+            let script = shell.init_script(false);
+            assert!(!script.contains("send-chdir"), "{:?}", shell);
+            assert!(!script.contains("_envhist_chpwd"), "{:?}", shell);
+            assert!(script.contains("send-capture"), "{:?}", shell);
+            assert!(script.contains("send-set-many"), "{:?}", shell);
+
+            let script = shell.init_script(true);
+            assert!(script.contains("send-chdir"), "{:?}", shell);
+            assert!(script.contains("send-set-many"), "{:?}", shell);
+        }
+    }
+}
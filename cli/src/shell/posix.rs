@@ -0,0 +1,85 @@
+//! Parts of the init script shared by zsh and bash.
+
+/// Header and the `export`/`unset` wrappers plus the prompt hook.
+pub const WRAPPERS: &str = r#"# envhist shell integration
+# This file is automatically generated by envhist init
+
+_envhist_export() {
+    # Parse arguments - handle both "export KEY=value" and "export KEY value"
+    local key
+    local value
+    
+    if [[ "$1" == *"="* ]]; then
+        # Format: export KEY=value
+        key="${1%%=*}"
+        value="${1#*=}"
+        shift
+    else
+        # Format: export KEY value
+        key="$1"
+        value="$2"
+        shift 2
+    fi
+    
+    # Send to daemon
+    envhist send-set $$ "$key" "$value" 2>/dev/null || true
+    
+    # Actually do the export
+    if [ $# -eq 0 ]; then
+        builtin export "$key"="$value"
+    else
+        builtin export "$key"="$value" "$@"
+    fi
+}
+
+# Wrap export command
+alias export='_envhist_export'
+
+_envhist_unset() {
+    local key="$1"
+    
+    # Send to daemon
+    envhist send-unset $$ "$key" 2>/dev/null || true
+    
+    # Actually do the unset
+    builtin unset "$key"
+}
+
+# Wrap unset command  
+alias unset='_envhist_unset'
+
+_envhist_precmd() {
+    # Capture env state before prompt (throttled to avoid overhead)
+    # Only capture every 10th prompt to reduce overhead
+    if [ -z "$_envhist_counter" ]; then
+        _envhist_counter=0
+    fi
+    _envhist_counter=$((_envhist_counter + 1))
+    
+    if [ $((_envhist_counter % 10)) -eq 0 ]; then
+        envhist send-capture $$ 2>/dev/null || true
+    fi
+}
+
+"#;
+
+/// Directory hook, included when `core.capture_on_cd` is set.
+pub const CHPWD: &str = r#"_envhist_chpwd() {
+    # Marks the directory change in the timeline
+    envhist send-chdir $$ "$PWD" 2>/dev/null || true
+}
+
+"#;
+
+/// Registers the session baseline; goes after the shell's hook setup.
+pub const SESSION_START: &str = r#"# Register the starting environment as this session's baseline
+envhist send-set-many $$ 2>/dev/null || true
+
+# Cleanup on exit
+_envhist_cleanup() {
+    # Session cleanup is handled by daemon detecting process termination
+    :
+}
+
+trap _envhist_cleanup EXIT
+"#;
@@ -0,0 +1,59 @@
+//! Runs the `envhist` binary against a throwaway store with deterministic
+//! output and compares what it prints with the expected text.
+
+use envhist_core::config::{deterministic_env, ENVHIST_HOME_VAR};
+use std::path::Path;
+use std::process::Command;
+
+fn envhist(home: &Path, vars: &[(&str, &str)], args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_envhist"))
+        .env_clear()
+        .env(ENVHIST_HOME_VAR, home)
+        .envs(deterministic_env())
+        .envs(vars.iter().copied())
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "envhist {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_diff_golden() {
+    let home = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(home.path().join("global/snapshots")).unwrap();
+
+    envhist(
+        home.path(),
+        &[("APP_MODE", "dev"), ("APP_PORT", "80")],
+        &["snapshot", "before"],
+    );
+    envhist(
+        home.path(),
+        &[("APP_MODE", "prod"), ("APP_DEBUG", "1")],
+        &["snapshot", "after"],
+    );
+
+    assert_eq!(
+        envhist(
+            home.path(),
+            &[],
+            &["diff", "before", "after", "--context", "0"]
+        ),
+        "--- before ---\n\
+         +++ after +++\n\
+         \n\
+         + APP_DEBUG: 1\n\
+         ~ APP_MODE:\n  \
+           - dev\n  \
+           + prod\n\
+         - APP_PORT: 80\n\
+         \n\
+         1 changed, 1 added, 1 removed\n"
+    );
+}
@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// One envhist command that changed the store, as recorded in `audit.jsonl`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub command: String,
+    /// The command line after the program name
+    pub args: Vec<String>,
+    pub outcome: AuditOutcome,
+    /// Why the command failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Ok,
+    Error,
+}
+
+impl AuditEntry {
+    pub fn new(command: &str, args: Vec<String>, result: &Result<()>) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            command: command.to_string(),
+            args,
+            outcome: if result.is_ok() {
+                AuditOutcome::Ok
+            } else {
+                AuditOutcome::Error
+            },
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        }
+    }
+}
+
+pub fn append(path: &Path, entry: &AuditEntry) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    // Arguments can hold values, so keep the log private
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to open audit log {:?}", path))?;
+    // `mode` only applies to new files
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to set permissions on {:?}", path))?;
+    let line = serde_json::to_string(entry).context("Failed to serialize audit entry")?;
+    writeln!(file, "{}", line).with_context(|| format!("Failed to write audit log {:?}", path))
+}
+
+/// Every recorded entry, oldest first.
+pub fn read(path: &Path) -> Result<Vec<AuditEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open audit log {:?}", path))?;
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context("Failed to read audit log line")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Failed to parse audit entry: {}", line))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_append_and_read() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        assert!(read(&path).unwrap().is_empty());
+
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect();
+        let ok = AuditEntry::new("snapshot", args(&["snapshot", "dev"]), &Ok(()));
+        let failed = AuditEntry::new(
+            "delete",
+            args(&["delete", "gone"]),
+            &Err(anyhow::anyhow!("Snapshot 'gone' not found")),
+        );
+        append(&path, &ok).unwrap();
+        append(&path, &failed).unwrap();
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries, vec![ok, failed]);
+        assert_eq!(entries[1].outcome, AuditOutcome::Error);
+        assert_eq!(
+            entries[1].error.as_deref(),
+            Some("Snapshot 'gone' not found")
+        );
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{
+    session::{Session, SessionMetadata},
+    storage::{Snapshot, Storage, TimelineEntry},
+    Env,
+};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// The timeline and snapshot operations commands need, so their logic can
+/// run against [`MemoryBackend`] in tests instead of the store on disk.
+pub trait StorageBackend {
+    fn append_timeline(&self, session: &Session, entry: &TimelineEntry) -> Result<()>;
+    fn read_timeline(&self, session: &Session) -> Result<Vec<TimelineEntry>>;
+    /// Save into `session`'s snapshots, or the global ones.
+    fn save_snapshot(&self, snapshot: &Snapshot, session: Option<&Session>) -> Result<()>;
+    /// Look in `session` first, then globally; without a session, globally
+    /// and then in every session.
+    fn load_snapshot(&self, name: &str, session: Option<&Session>) -> Result<Snapshot>;
+    /// `session`'s snapshots and the global ones, newest first.
+    fn list_snapshots(&self, session: Option<&Session>) -> Result<Vec<Snapshot>>;
+    fn delete_snapshot(&self, name: &str, session: Option<&Session>) -> Result<()>;
+    /// The env last captured for `session`.
+    fn load_metadata(&self, session: &Session) -> Result<SessionMetadata>;
+    fn save_metadata(&self, session: &Session, env: &Env) -> Result<()>;
+}
+
+impl StorageBackend for Storage {
+    fn append_timeline(&self, session: &Session, entry: &TimelineEntry) -> Result<()> {
+        Storage::append_timeline(self, session, entry)
+    }
+
+    fn read_timeline(&self, session: &Session) -> Result<Vec<TimelineEntry>> {
+        Storage::read_timeline(self, session)
+    }
+
+    fn save_snapshot(&self, snapshot: &Snapshot, session: Option<&Session>) -> Result<()> {
+        Storage::save_snapshot(self, snapshot, session).map(|_| ())
+    }
+
+    fn load_snapshot(&self, name: &str, session: Option<&Session>) -> Result<Snapshot> {
+        Storage::load_snapshot(self, name, session)
+    }
+
+    fn list_snapshots(&self, session: Option<&Session>) -> Result<Vec<Snapshot>> {
+        Storage::list_snapshots(self, session)
+    }
+
+    fn delete_snapshot(&self, name: &str, session: Option<&Session>) -> Result<()> {
+        Storage::delete_snapshot(self, name, session)
+    }
+
+    fn load_metadata(&self, session: &Session) -> Result<SessionMetadata> {
+        Session::load_metadata(&session.metadata_path())
+    }
+
+    fn save_metadata(&self, session: &Session, env: &Env) -> Result<()> {
+        session.save_metadata(env)
+    }
+}
+
+/// Snapshots keyed by owning session (`None` is global), then name.
+type SnapshotScopes = HashMap<Option<Uuid>, HashMap<String, Snapshot>>;
+
+/// A [`StorageBackend`] that keeps everything in memory, for tests.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    timelines: Mutex<HashMap<Uuid, Vec<TimelineEntry>>>,
+    snapshots: Mutex<SnapshotScopes>,
+    metadata: Mutex<HashMap<Uuid, SessionMetadata>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn append_timeline(&self, session: &Session, entry: &TimelineEntry) -> Result<()> {
+        self.timelines
+            .lock()
+            .unwrap()
+            .entry(session.id)
+            .or_default()
+            .push(entry.clone());
+        Ok(())
+    }
+
+    fn read_timeline(&self, session: &Session) -> Result<Vec<TimelineEntry>> {
+        Ok(self
+            .timelines
+            .lock()
+            .unwrap()
+            .get(&session.id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn save_snapshot(&self, snapshot: &Snapshot, session: Option<&Session>) -> Result<()> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .entry(session.map(|s| s.id))
+            .or_default()
+            .insert(snapshot.name.clone(), snapshot.clone());
+        Ok(())
+    }
+
+    fn load_snapshot(&self, name: &str, session: Option<&Session>) -> Result<Snapshot> {
+        let scopes = self.snapshots.lock().unwrap();
+        let found = match session {
+            Some(sess) => [Some(sess.id), None]
+                .iter()
+                .find_map(|scope| scopes.get(scope)?.get(name)),
+            None => scopes
+                .get(&None)
+                .and_then(|global| global.get(name))
+                .or_else(|| scopes.values().find_map(|scope| scope.get(name))),
+        };
+        found
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Snapshot '{}' not found", name))
+    }
+
+    fn list_snapshots(&self, session: Option<&Session>) -> Result<Vec<Snapshot>> {
+        let scopes = self.snapshots.lock().unwrap();
+        let mut owners = vec![None];
+        if let Some(sess) = session {
+            owners.push(Some(sess.id));
+        }
+        let mut snapshots: Vec<Snapshot> = owners
+            .iter()
+            .filter_map(|owner| scopes.get(owner))
+            .flat_map(|scope| scope.values().cloned())
+            .collect();
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+        Ok(snapshots)
+    }
+
+    fn delete_snapshot(&self, name: &str, session: Option<&Session>) -> Result<()> {
+        let mut scopes = self.snapshots.lock().unwrap();
+        let mut owners = vec![session.map(|s| s.id), None];
+        owners.extend(scopes.keys().copied());
+        for owner in owners {
+            if let Some(scope) = scopes.get_mut(&owner) {
+                if scope.remove(name).is_some() {
+                    return Ok(());
+                }
+            }
+        }
+        anyhow::bail!("Snapshot '{}' not found", name)
+    }
+
+    fn load_metadata(&self, session: &Session) -> Result<SessionMetadata> {
+        self.metadata
+            .lock()
+            .unwrap()
+            .get(&session.id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No metadata for session {}", session.id))
+    }
+
+    fn save_metadata(&self, session: &Session, env: &Env) -> Result<()> {
+        self.metadata.lock().unwrap().insert(
+            session.id,
+            SessionMetadata {
+                session: session.clone(),
+                current_env: env.clone(),
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Action;
+    use chrono::{Duration, Utc};
+
+    fn snapshot(name: &str, age_secs: i64, session: Option<&Session>) -> Snapshot {
+        Snapshot {
+            name: name.to_string(),
+            created_at: Utc::now() - Duration::seconds(age_secs),
+            description: None,
+            environment: Env::from([("NAME".to_string(), name.to_string())]),
+            tags: Vec::new(),
+            session_id: session.map(|s| s.id),
+            keys_only: false,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_memory_timeline() {
+        let backend = MemoryBackend::new();
+        let session = Session::new(1, "zsh".to_string());
+        let other = Session::new(2, "zsh".to_string());
+
+        let entry = TimelineEntry::new(Action::Set, "A".to_string(), Some("1".to_string()), None);
+        backend.append_timeline(&session, &entry).unwrap();
+
+        let entries = backend.read_timeline(&session).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "A");
+        assert!(backend.read_timeline(&other).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_memory_snapshot_scopes() {
+        let backend = MemoryBackend::new();
+        let session = Session::new(1, "zsh".to_string());
+        let other = Session::new(2, "zsh".to_string());
+
+        backend
+            .save_snapshot(&snapshot("old", 20, None), None)
+            .unwrap();
+        let mine = snapshot("mine", 10, Some(&session));
+        backend.save_snapshot(&mine, Some(&session)).unwrap();
+        let theirs = snapshot("theirs", 0, Some(&other));
+        backend.save_snapshot(&theirs, Some(&other)).unwrap();
+
+        // Newest first, this session plus global only
+        let names: Vec<String> = backend
+            .list_snapshots(Some(&session))
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, vec!["mine", "old"]);
+
+        // Without a session, other sessions' snapshots still load by name
+        assert_eq!(
+            backend.load_snapshot("theirs", None).unwrap().name,
+            "theirs"
+        );
+        assert!(backend.load_snapshot("theirs", Some(&session)).is_err());
+
+        backend.delete_snapshot("mine", None).unwrap();
+        assert!(backend.load_snapshot("mine", Some(&session)).is_err());
+        assert!(backend.delete_snapshot("mine", None).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Descriptions of what variables mean, from `~/.envhist/catalog.toml`:
+///
+/// ```toml
+/// DATABASE_URL = "Primary Postgres connection string"
+/// AWS_PROFILE = "Profile used by the aws CLI"
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    descriptions: BTreeMap<String, String>,
+}
+
+impl Catalog {
+    pub fn path() -> PathBuf {
+        Config::base_dir().join("catalog.toml")
+    }
+
+    /// Load the catalog; a missing file is an empty catalog. Descriptions
+    /// are only decoration, so an unreadable or malformed file is reported
+    /// and treated as empty rather than failing the command.
+    pub fn load() -> Self {
+        Self::load_from(&Self::path()).unwrap_or_else(|e| {
+            eprintln!("⚠ Ignoring the variable catalog: {:#}", e);
+            Self::default()
+        })
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read catalog from {:?}", path))?;
+        Self::parse(&content).with_context(|| format!("Failed to parse catalog from {:?}", path))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let descriptions = toml::from_str(content)?;
+        Ok(Self { descriptions })
+    }
+
+    pub fn describe(&self, key: &str) -> Option<&str> {
+        self.descriptions.get(key).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_describe() {
+        let catalog = Catalog::parse(
+            r#"
+DATABASE_URL = "Primary Postgres connection string"
+"AWS_PROFILE" = "Profile used by the aws CLI"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            catalog.describe("DATABASE_URL"),
+            Some("Primary Postgres connection string")
+        );
+        assert_eq!(
+            catalog.describe("AWS_PROFILE"),
+            Some("Profile used by the aws CLI")
+        );
+        assert_eq!(catalog.describe("PATH"), None);
+    }
+
+    #[test]
+    fn test_missing_file_is_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let catalog = Catalog::load_from(&temp_dir.path().join("catalog.toml")).unwrap();
+        assert_eq!(catalog.describe("DATABASE_URL"), None);
+    }
+
+    #[test]
+    fn test_rejects_non_string_descriptions() {
+        assert!(Catalog::parse("PORT = 8080").is_err());
+    }
+}
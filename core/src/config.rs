@@ -1,9 +1,38 @@
 use anyhow::{Context, Result};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Overrides the store location, which defaults to `~/.envhist`.
+pub const ENVHIST_HOME_VAR: &str = "ENVHIST_HOME";
+
+/// Selects a `[profiles.<name>]` section to layer over the config.
+pub const ENVHIST_PROFILE_VAR: &str = "ENVHIST_PROFILE";
+
+/// Set to `1` for output that doesn't depend on the user's timezone or
+/// terminal: UTC times, no color and tables that are never fitted to a width.
+pub const ENVHIST_DETERMINISTIC_VAR: &str = "ENVHIST_DETERMINISTIC";
+
+/// Whether deterministic output was requested via [`ENVHIST_DETERMINISTIC_VAR`].
+pub fn deterministic() -> bool {
+    is_enabled(std::env::var_os(ENVHIST_DETERMINISTIC_VAR))
+}
+
+/// Environment for running `envhist` with deterministic output, for
+/// golden-file tests and scripts: `Command::new(bin).envs(deterministic_env())`.
+pub fn deterministic_env() -> [(&'static str, &'static str); 2] {
+    [(ENVHIST_DETERMINISTIC_VAR, "1"), ("TZ", "UTC")]
+}
+
+fn is_enabled(value: Option<OsString>) -> bool {
+    value.is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub core: CoreConfig,
@@ -11,6 +40,12 @@ pub struct Config {
     pub filters: FiltersConfig,
     #[serde(default)]
     pub display: DisplayConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Named overrides of the settings above, e.g. `[profiles.work.filters]`;
+    /// tables merge key by key and other values replace the base ones
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, toml::Table>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +58,51 @@ pub struct CoreConfig {
     pub max_timeline_size: usize,
     #[serde(default = "default_true")]
     pub daemon_enabled: bool,
+    /// Drop daemon clients that send nothing for this long
+    #[serde(default = "default_5000")]
+    pub daemon_idle_timeout_ms: u64,
+    /// Connections served at once; further clients wait to be accepted
+    #[serde(default = "default_64")]
+    pub daemon_max_connections: usize,
+    /// Record directory changes in the timeline and capture the env on `cd`
+    #[serde(default)]
+    pub capture_on_cd: bool,
+    /// Skip prompt-time captures when the env is unchanged since the last one
+    #[serde(default = "default_true")]
+    pub debounce_captures: bool,
+    /// Store new snapshots gzipped (`.json.gz`)
+    #[serde(default)]
+    pub compress_snapshots: bool,
+    /// Seconds a shell's captures are ignored after one is saved (0 saves
+    /// every changed capture)
+    #[serde(default)]
+    pub capture_min_interval: u64,
+    /// Times the CLI tries to reach the daemon before giving up, backing
+    /// off exponentially between tries
+    #[serde(default = "default_3")]
+    pub client_attempts: usize,
+    /// How long each try waits to connect, send or read the reply
+    #[serde(default = "default_100")]
+    pub client_timeout_ms: u64,
+    /// Longest value the daemon records; longer ones are cut and marked
+    /// `[truncated N bytes]` (0 records values whole)
+    #[serde(default = "default_65536")]
+    pub max_value_bytes: usize,
+    /// How the daemon writes new timeline entries
+    #[serde(default)]
+    pub timeline_format: TimelineFormat,
+}
+
+/// On-disk format of session timelines. Both are always readable, so the
+/// setting can change without converting existing sessions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelineFormat {
+    /// One JSON object per line in `timeline.jsonl`
+    #[default]
+    Jsonl,
+    /// Length-prefixed bincode records in `timeline.bin`
+    Bincode,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +113,20 @@ pub struct FiltersConfig {
     pub force_track: Vec<String>,
     #[serde(default = "default_ignore_system")]
     pub ignore_system: Vec<String>,
+    /// When non-empty, only variables starting with one of these prefixes
+    /// are tracked (`force_track` still wins)
+    #[serde(default)]
+    pub track_prefixes: Vec<String>,
+    /// Variables whose values are recorded only as salted hashes
+    #[serde(default)]
+    pub hash_patterns: Vec<String>,
+    /// Variables treated as secrets by `diff --only-secrets`
+    #[serde(default = "default_secret_patterns")]
+    pub secret_patterns: Vec<String>,
+    /// Pattern to the minimum number of seconds between recorded changes of
+    /// a matching variable (e.g. `"^COLUMNS$" = 60`)
+    #[serde(default)]
+    pub sample_patterns: BTreeMap<String, u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,18 +137,70 @@ pub struct DisplayConfig {
     pub color: bool,
     #[serde(default = "default_local")]
     pub timezone: String,
+    /// Prefixes used by `--group-by prefix` before falling back to the first
+    /// `_`-separated segment
+    #[serde(default)]
+    pub group_prefixes: Vec<String>,
+    /// Variables holding delimiter-joined lists, as full-name regex to
+    /// delimiter (e.g. `PATH = ":"`); these diff and restore per element
+    #[serde(default)]
+    pub list_vars: BTreeMap<String, String>,
+    /// Variables shown only partly, as full-name regex to masking rule
+    /// (e.g. `DATABASE_URL = { first = 4, last = 4 }`)
+    #[serde(default)]
+    pub mask_patterns: BTreeMap<String, MaskRule>,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            core: CoreConfig::default(),
-            filters: FiltersConfig::default(),
-            display: DisplayConfig::default(),
+impl DisplayConfig {
+    /// Masking rule of a variable configured in `display.mask_patterns`.
+    pub fn mask_rule(&self, key: &str) -> Option<&MaskRule> {
+        self.mask_patterns
+            .iter()
+            .find(|(pattern, _)| pattern_matches_whole(pattern, key))
+            .map(|(_, rule)| rule)
+    }
+}
+
+/// How much of a masked value stays visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaskRule {
+    /// Leading characters shown
+    #[serde(default = "default_4")]
+    pub first: usize,
+    /// Trailing characters shown
+    #[serde(default = "default_4")]
+    pub last: usize,
+}
+
+impl MaskRule {
+    /// `value` with everything but its first and last characters replaced
+    /// by `…`; values too short to hide anything are masked whole.
+    pub fn apply(&self, value: &str) -> String {
+        let chars: Vec<char> = value.chars().collect();
+        if chars.len() <= self.first + self.last {
+            return "…".to_string();
         }
+        let head: String = chars[..self.first].iter().collect();
+        let tail: String = chars[chars.len() - self.last..].iter().collect();
+        format!("{}…{}", head, tail)
     }
 }
 
+/// Shell commands run after snapshot operations. They run through `sh -c`
+/// with `ENVHIST_SNAPSHOT_NAME` and `ENVHIST_HOOK` set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HooksConfig {
+    #[serde(default)]
+    pub post_snapshot: Option<String>,
+    #[serde(default)]
+    pub post_restore: Option<String>,
+    #[serde(default)]
+    pub post_delete: Option<String>,
+    /// Fail the command when a hook fails instead of only warning
+    #[serde(default)]
+    pub strict: bool,
+}
+
 impl Default for CoreConfig {
     fn default() -> Self {
         Self {
@@ -62,6 +208,16 @@ impl Default for CoreConfig {
             auto_snapshot_interval: 3600,
             max_timeline_size: 10000,
             daemon_enabled: true,
+            daemon_idle_timeout_ms: 5000,
+            daemon_max_connections: 64,
+            capture_on_cd: false,
+            debounce_captures: true,
+            compress_snapshots: false,
+            capture_min_interval: 0,
+            client_attempts: 3,
+            client_timeout_ms: 100,
+            max_value_bytes: 65536,
+            timeline_format: TimelineFormat::Jsonl,
         }
     }
 }
@@ -72,6 +228,10 @@ impl Default for FiltersConfig {
             ignore_patterns: default_ignore_patterns(),
             force_track: Vec::new(),
             ignore_system: default_ignore_system(),
+            track_prefixes: Vec::new(),
+            hash_patterns: Vec::new(),
+            secret_patterns: default_secret_patterns(),
+            sample_patterns: BTreeMap::new(),
         }
     }
 }
@@ -82,6 +242,9 @@ impl Default for DisplayConfig {
             diff_context: 3,
             color: true,
             timezone: "local".to_string(),
+            group_prefixes: Vec::new(),
+            list_vars: BTreeMap::new(),
+            mask_patterns: BTreeMap::new(),
         }
     }
 }
@@ -98,10 +261,30 @@ fn default_10000() -> usize {
     10000
 }
 
+fn default_5000() -> u64 {
+    5000
+}
+
+fn default_64() -> usize {
+    64
+}
+
 fn default_3() -> usize {
     3
 }
 
+fn default_100() -> u64 {
+    100
+}
+
+fn default_4() -> usize {
+    4
+}
+
+fn default_65536() -> usize {
+    65536
+}
+
 fn default_local() -> String {
     "local".to_string()
 }
@@ -116,6 +299,22 @@ fn default_ignore_patterns() -> Vec<String> {
     ]
 }
 
+fn default_secret_patterns() -> Vec<String> {
+    vec![
+        ".*PASSWORD.*".to_string(),
+        ".*PASSWD.*".to_string(),
+        ".*SECRET.*".to_string(),
+        ".*TOKEN.*".to_string(),
+        ".*API_?KEY.*".to_string(),
+        ".*PRIVATE_KEY.*".to_string(),
+        ".*CREDENTIAL.*".to_string(),
+    ]
+}
+
+/// Variables a shell can't work without; `restore --replace` won't unset
+/// these without an explicit override.
+pub const PROTECTED_VARS: &[&str] = &["PATH", "HOME", "SHELL", "USER", "PWD"];
+
 fn default_ignore_system() -> Vec<String> {
     vec![
         "PATH".to_string(),
@@ -133,17 +332,58 @@ fn default_ignore_system() -> Vec<String> {
 
 impl Config {
     pub fn load() -> Result<Self> {
+        Self::try_base_dir()?;
         let config_path = Self::config_path();
         if !config_path.exists() {
             let config = Config::default();
             config.save()?;
-            return Ok(config);
+            return Ok(config.with_env_overrides());
         }
+        Self::load_from(&config_path)
+    }
 
-        let content = std::fs::read_to_string(&config_path)
-            .with_context(|| format!("Failed to read config from {:?}", config_path))?;
-        let config: Config = toml::from_str(&content)
-            .with_context(|| format!("Failed to parse config from {:?}", config_path))?;
+    /// Read the config file at `path`, with the profile selected by
+    /// `ENVHIST_PROFILE` merged in.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config from {:?}", path))?;
+        let profile = std::env::var(ENVHIST_PROFILE_VAR)
+            .ok()
+            .filter(|name| !name.is_empty());
+        let config = Self::parse(&content, profile.as_deref())
+            .with_context(|| format!("Failed to parse config from {:?}", path))?;
+        Ok(config.with_env_overrides())
+    }
+
+    fn with_env_overrides(mut self) -> Self {
+        if deterministic() {
+            self.display.timezone = "UTC".to_string();
+        }
+        self
+    }
+
+    /// Parse config TOML, with `profile`'s overrides merged over the base
+    /// settings when given.
+    pub fn parse(content: &str, profile: Option<&str>) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(content)?;
+        if let Some(name) = profile {
+            let overrides = table
+                .get("profiles")
+                .and_then(|profiles| profiles.get(name))
+                .and_then(toml::Value::as_table)
+                .cloned()
+                .with_context(|| format!("Profile '{}' is not defined under [profiles]", name))?;
+            merge_tables(&mut table, overrides);
+        }
+        let config: Config = toml::Value::Table(table).try_into()?;
+        if let Some((pattern, _)) = config
+            .display
+            .list_vars
+            .iter()
+            .find(|(_, delimiter)| delimiter.is_empty())
+        {
+            anyhow::bail!("display.list_vars: '{}' has an empty delimiter", pattern);
+        }
         Ok(config)
     }
 
@@ -161,16 +401,21 @@ impl Config {
     }
 
     pub fn config_path() -> PathBuf {
-        dirs::home_dir()
-            .expect("Failed to find home directory")
-            .join(".envhist")
-            .join("config.toml")
+        Self::base_dir().join("config.toml")
     }
 
+    /// The store directory: `$ENVHIST_HOME` if set, else `~/.envhist`.
+    /// Fails when neither can be determined, e.g. in a container without
+    /// `$HOME`.
+    pub fn try_base_dir() -> Result<PathBuf> {
+        resolve_base_dir(std::env::var_os(ENVHIST_HOME_VAR), dirs::home_dir())
+    }
+
+    /// Like [`Config::try_base_dir`], but falls back to a directory under the
+    /// system temp dir instead of failing. Entry points call
+    /// `try_base_dir` first so users see the error rather than the fallback.
     pub fn base_dir() -> PathBuf {
-        dirs::home_dir()
-            .expect("Failed to find home directory")
-            .join(".envhist")
+        Self::try_base_dir().unwrap_or_else(|_| std::env::temp_dir().join("envhist"))
     }
 
     pub fn sessions_dir() -> PathBuf {
@@ -181,35 +426,268 @@ impl Config {
         Self::base_dir().join("global").join("snapshots")
     }
 
+    /// Log of the commands that changed the store, for `envhist audit`.
+    pub fn audit_log_path() -> PathBuf {
+        Self::base_dir().join("audit.jsonl")
+    }
+
+    pub fn baseline_path() -> PathBuf {
+        Self::base_dir().join("baseline.json")
+    }
+
+    /// Per-shell state kept by the shell hook, such as the last capture hash.
+    pub fn hook_state_dir() -> PathBuf {
+        Self::base_dir().join("hooks")
+    }
+
+    /// Salt for values hashed by `filters.hash_patterns`, created on first use.
+    pub fn salt_path() -> PathBuf {
+        Self::base_dir().join("salt")
+    }
+
     pub fn daemon_socket_path() -> PathBuf {
         Self::base_dir().join("daemon.sock")
     }
 
     pub fn should_track(&self, key: &str) -> bool {
+        self.should_track_explained(key).track
+    }
+
+    /// Delimiter of a variable configured in `display.list_vars`.
+    pub fn list_delimiter(&self, key: &str) -> Option<&str> {
+        self.display
+            .list_vars
+            .iter()
+            .find(|(pattern, _)| pattern_matches_whole(pattern, key))
+            .map(|(_, delimiter)| delimiter.as_str())
+    }
+
+    /// Whether a variable name matches `filters.secret_patterns`.
+    pub fn is_secret_key(&self, key: &str) -> bool {
+        first_match(&self.filters.secret_patterns, key).is_some()
+    }
+
+    /// Whether a variable matches `filters.hash_patterns`, so its values are
+    /// recorded as hashes.
+    pub fn should_hash(&self, key: &str) -> bool {
+        first_match(&self.filters.hash_patterns, key).is_some()
+    }
+
+    /// `value` cut to `core.max_value_bytes` (on a character boundary), with
+    /// a marker saying how much was dropped.
+    pub fn limit_value(&self, value: String) -> String {
+        let max = self.core.max_value_bytes;
+        if max == 0 || value.len() <= max {
+            return value;
+        }
+        let mut end = max;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}[truncated {} bytes]", &value[..end], value.len() - end)
+    }
+
+    /// Whether `value` was cut short by [`Config::limit_value`].
+    pub fn is_truncated(value: &str) -> bool {
+        value
+            .rsplit_once("[truncated ")
+            .and_then(|(_, rest)| rest.strip_suffix(" bytes]"))
+            .is_some_and(|bytes| !bytes.is_empty() && bytes.bytes().all(|b| b.is_ascii_digit()))
+    }
+
+    /// [`Config::limit_value`] applied to every value in `env`.
+    pub fn limit_env(&self, env: crate::Env) -> crate::Env {
+        env.into_iter()
+            .map(|(key, value)| (key, self.limit_value(value)))
+            .collect()
+    }
+
+    /// Minimum time between recorded changes of a variable, from the first
+    /// matching `filters.sample_patterns` entry.
+    pub fn sample_interval(&self, key: &str) -> Option<chrono::Duration> {
+        self.filters
+            .sample_patterns
+            .iter()
+            .find(|(pattern, _)| pattern_matches(pattern, key))
+            .map(|(_, secs)| chrono::Duration::seconds(*secs as i64))
+    }
+
+    /// Like [`Config::should_track`], but also reports which rule decided.
+    pub fn should_track_explained(&self, key: &str) -> TrackDecision {
         // Check force_track first (highest priority)
-        if self.filters.force_track.iter().any(|pattern| {
-            Regex::new(pattern)
-                .map(|re| re.is_match(key))
-                .unwrap_or(false)
-        }) {
-            return true;
+        if let Some(pattern) = first_match(&self.filters.force_track, key) {
+            return TrackDecision {
+                track: true,
+                reason: TrackReason::ForceTrack(pattern),
+            };
+        }
+
+        // Check the track_prefixes allowlist
+        let prefixes = &self.filters.track_prefixes;
+        if !prefixes.is_empty()
+            && !prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix.as_str()))
+        {
+            return TrackDecision {
+                track: false,
+                reason: TrackReason::OutsideTrackPrefixes,
+            };
         }
 
         // Check ignore_system
         if self.filters.ignore_system.contains(&key.to_string()) {
-            return false;
+            return TrackDecision {
+                track: false,
+                reason: TrackReason::IgnoreSystem,
+            };
         }
 
         // Check ignore_patterns
-        if self.filters.ignore_patterns.iter().any(|pattern| {
-            Regex::new(pattern)
-                .map(|re| re.is_match(key))
-                .unwrap_or(false)
-        }) {
-            return false;
+        if let Some(pattern) = first_match(&self.filters.ignore_patterns, key) {
+            return TrackDecision {
+                track: false,
+                reason: TrackReason::IgnorePattern(pattern),
+            };
+        }
+
+        TrackDecision {
+            track: true,
+            reason: TrackReason::Default,
+        }
+    }
+}
+
+/// Outcome of the tracking filters for one variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackDecision {
+    pub track: bool,
+    pub reason: TrackReason,
+}
+
+/// The filter rule that decided a [`TrackDecision`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackReason {
+    /// Matched this `filters.force_track` pattern
+    ForceTrack(String),
+    /// `filters.track_prefixes` is set and no prefix matched
+    OutsideTrackPrefixes,
+    /// Listed in `filters.ignore_system`
+    IgnoreSystem,
+    /// Matched this `filters.ignore_patterns` pattern
+    IgnorePattern(String),
+    /// No rule matched, so the variable is tracked
+    Default,
+}
+
+impl std::fmt::Display for TrackReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrackReason::ForceTrack(pattern) => write!(f, "force_track pattern '{}'", pattern),
+            TrackReason::OutsideTrackPrefixes => write!(f, "no track_prefixes prefix matched"),
+            TrackReason::IgnoreSystem => write!(f, "ignore_system list"),
+            TrackReason::IgnorePattern(pattern) => {
+                write!(f, "ignore_patterns pattern '{}'", pattern)
+            }
+            TrackReason::Default => write!(f, "no rule matched (default)"),
+        }
+    }
+}
+
+/// Layer `overlay` onto `base`: nested tables merge, anything else replaces.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// First pattern that matches `key`; invalid patterns never match.
+fn first_match(patterns: &[String], key: &str) -> Option<String> {
+    patterns
+        .iter()
+        .find(|pattern| pattern_matches(pattern, key))
+        .cloned()
+}
+
+/// Compiled config patterns, so each is compiled once per process rather
+/// than for every variable checked. Invalid patterns are cached as `None`.
+static PATTERNS: LazyLock<Mutex<HashMap<String, Option<Regex>>>> = LazyLock::new(Default::default);
+
+/// Whether config `pattern` matches `key`; invalid patterns never match.
+fn pattern_matches(pattern: &str, key: &str) -> bool {
+    let mut patterns = PATTERNS.lock().unwrap_or_else(|e| e.into_inner());
+    patterns
+        .entry(pattern.to_string())
+        .or_insert_with(|| Regex::new(pattern).ok())
+        .as_ref()
+        .is_some_and(|re| re.is_match(key))
+}
+
+/// [`pattern_matches`] against the whole of `key`.
+fn pattern_matches_whole(pattern: &str, key: &str) -> bool {
+    pattern_matches(&format!("^(?:{})$", pattern), key)
+}
+
+/// Notices edits to `config.toml` by polling its modification time, so a
+/// long-running process can pick them up.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new() -> Self {
+        Self::at(Config::config_path())
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        let modified = modified_time(&path);
+        Self { path, modified }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The reloaded config if the file changed since the last poll.
+    pub fn poll(&mut self) -> Option<Result<Config>> {
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return None;
         }
+        self.modified = modified;
+        Some(Config::load_from(&self.path))
+    }
+}
 
-        true
+impl Default for ConfigWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn resolve_base_dir(envhist_home: Option<OsString>, home: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(dir) = envhist_home.filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    match home {
+        Some(home) => Ok(home.join(".envhist")),
+        None => anyhow::bail!(
+            "Failed to find home directory; set HOME or {} to choose where envhist keeps its data",
+            ENVHIST_HOME_VAR
+        ),
     }
 }
 
@@ -217,6 +695,112 @@ impl Config {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_resolve_base_dir() {
+        let home = Some(PathBuf::from("/home/me"));
+
+        assert_eq!(
+            resolve_base_dir(None, home.clone()).unwrap(),
+            PathBuf::from("/home/me/.envhist")
+        );
+        assert_eq!(
+            resolve_base_dir(Some("/data/envhist".into()), home.clone()).unwrap(),
+            PathBuf::from("/data/envhist")
+        );
+        assert_eq!(
+            resolve_base_dir(Some("".into()), home).unwrap(),
+            PathBuf::from("/home/me/.envhist")
+        );
+
+        let err = resolve_base_dir(None, None).unwrap_err();
+        assert!(err.to_string().contains("ENVHIST_HOME"));
+    }
+
+    #[test]
+    fn test_config_watcher() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[display]\ndiff_context = 3\n").unwrap();
+
+        let mut watcher = ConfigWatcher::at(path.clone());
+        assert!(watcher.poll().is_none());
+
+        std::fs::write(&path, "[display]\ndiff_context = 7\n").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let config = watcher.poll().unwrap().unwrap();
+        assert_eq!(config.display.diff_context, 7);
+        assert!(watcher.poll().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_mask_rule() {
+        let config: Config = toml::from_str(
+            r#"
+            [display.mask_patterns]
+            ".*_URL" = {}
+            "TOKEN" = { first = 2, last = 0 }
+            "#,
+        )
+        .unwrap();
+
+        let url = config.display.mask_rule("DATABASE_URL").unwrap();
+        assert_eq!(url.apply("postgres://user:pw@db.internal/app"), "post…/app");
+        assert_eq!(url.apply("short"), "…");
+        assert_eq!(
+            config
+                .display
+                .mask_rule("TOKEN")
+                .unwrap()
+                .apply("sk-123456"),
+            "sk…"
+        );
+        // Patterns match the whole name
+        assert!(config.display.mask_rule("MY_TOKEN").is_none());
+    }
+
+    #[test]
+    fn test_limit_value() {
+        let mut config = Config::default();
+        config.core.max_value_bytes = 8;
+
+        assert_eq!(config.limit_value("short".to_string()), "short");
+        let huge = "x".repeat(4 * 1024 * 1024);
+        assert_eq!(
+            config.limit_value(huge),
+            format!("xxxxxxxx[truncated {} bytes]", 4 * 1024 * 1024 - 8)
+        );
+        // Never splits a character: 'é' is two bytes
+        assert_eq!(
+            config.limit_value("abcdefgéz".to_string()),
+            "abcdefg[truncated 3 bytes]"
+        );
+
+        config.core.max_value_bytes = 0;
+        assert_eq!(config.limit_value("x".repeat(100)).len(), 100);
+
+        assert!(Config::is_truncated("abcdefg[truncated 3 bytes]"));
+        assert!(!Config::is_truncated("abcdefg"));
+        assert!(!Config::is_truncated("[truncated many bytes]"));
+    }
+
+    #[test]
+    fn test_is_enabled() {
+        assert!(is_enabled(Some("1".into())));
+        assert!(is_enabled(Some("yes".into())));
+        assert!(!is_enabled(Some("0".into())));
+        assert!(!is_enabled(Some("".into())));
+        assert!(!is_enabled(None));
+    }
+
     #[test]
     fn test_should_track() {
         let config = Config::default();
@@ -238,4 +822,139 @@ mod tests {
         config.filters.force_track.push("MY_PASSWORD".to_string());
         assert!(config.should_track("MY_PASSWORD"));
     }
+
+    #[test]
+    fn test_should_track_explained() {
+        let config = Config::default();
+        assert_eq!(
+            config.should_track_explained("PATH").reason,
+            TrackReason::IgnoreSystem
+        );
+        assert_eq!(
+            config.should_track_explained("DB_PASSWORD").reason,
+            TrackReason::IgnorePattern(".*PASSWORD.*".to_string())
+        );
+        assert_eq!(
+            config.should_track_explained("MY_VAR"),
+            TrackDecision {
+                track: true,
+                reason: TrackReason::Default,
+            }
+        );
+
+        let mut config = Config::default();
+        config.filters.force_track.push("PATH".to_string());
+        assert_eq!(
+            config.should_track_explained("PATH").reason,
+            TrackReason::ForceTrack("PATH".to_string())
+        );
+    }
+
+    #[test]
+    fn test_track_prefixes() {
+        let mut config = Config::default();
+        config.filters.track_prefixes = vec!["MYAPP_".to_string(), "DB_".to_string()];
+
+        assert!(config.should_track("MYAPP_PORT"));
+        assert!(config.should_track("DB_HOST"));
+        assert_eq!(
+            config.should_track_explained("EDITOR"),
+            TrackDecision {
+                track: false,
+                reason: TrackReason::OutsideTrackPrefixes,
+            }
+        );
+
+        // Ignore rules still apply inside the allowlist
+        assert_eq!(
+            config.should_track_explained("DB_PASSWORD").reason,
+            TrackReason::IgnorePattern(".*PASSWORD.*".to_string())
+        );
+
+        // force_track takes precedence over the allowlist
+        config.filters.force_track.push("EDITOR".to_string());
+        assert!(config.should_track("EDITOR"));
+    }
+
+    #[test]
+    fn test_parse_with_profile() {
+        let content = r#"
+            [core]
+            max_timeline_size = 500
+
+            [display]
+            diff_context = 1
+            group_prefixes = ["AWS"]
+
+            [profiles.work.display]
+            group_prefixes = ["CORP"]
+
+            [profiles.work.filters]
+            track_prefixes = ["CORP_"]
+        "#;
+
+        let base = Config::parse(content, None).unwrap();
+        assert_eq!(base.display.group_prefixes, vec!["AWS".to_string()]);
+        assert!(base.filters.track_prefixes.is_empty());
+        assert!(base.profiles.contains_key("work"));
+
+        let work = Config::parse(content, Some("work")).unwrap();
+        assert_eq!(work.display.group_prefixes, vec!["CORP".to_string()]);
+        assert_eq!(work.display.diff_context, 1);
+        assert_eq!(work.filters.track_prefixes, vec!["CORP_".to_string()]);
+        assert_eq!(work.core.max_timeline_size, 500);
+
+        let err = Config::parse(content, Some("home")).unwrap_err();
+        assert!(err.to_string().contains("Profile 'home'"));
+    }
+
+    #[test]
+    fn test_parse_rejects_empty_list_delimiter() {
+        let content = "[display.list_vars]\nPATH = \":\"\nFLAGS = \"\"\n";
+        let err = Config::parse(content, None).unwrap_err();
+        assert!(err.to_string().contains("'FLAGS' has an empty delimiter"));
+    }
+
+    #[test]
+    fn test_should_hash() {
+        let mut config = Config::default();
+        assert!(!config.should_hash("DB_HOST"));
+
+        config.filters.hash_patterns.push("^DB_.*".to_string());
+        assert!(config.should_hash("DB_HOST"));
+        assert!(!config.should_hash("MY_DB_HOST"));
+    }
+
+    #[test]
+    fn test_sample_interval() {
+        let mut config = Config::default();
+        assert_eq!(config.sample_interval("COLUMNS"), None);
+
+        config
+            .filters
+            .sample_patterns
+            .insert("^(COLUMNS|LINES)$".to_string(), 60);
+        assert_eq!(
+            config.sample_interval("LINES"),
+            Some(chrono::Duration::seconds(60))
+        );
+        assert_eq!(config.sample_interval("MY_COLUMNS"), None);
+    }
+
+    #[test]
+    fn test_is_secret_key() {
+        let config = Config::default();
+        assert!(config.is_secret_key("GITHUB_TOKEN"));
+        assert!(config.is_secret_key("AWS_SECRET_ACCESS_KEY"));
+        assert!(config.is_secret_key("STRIPE_APIKEY"));
+        // Ignored for tracking, but not secret
+        assert!(!config.is_secret_key("AWS_REGION"));
+        assert!(!config.is_secret_key("SSH_AUTH_SOCK"));
+        assert!(!config.is_secret_key("EDITOR"));
+
+        let mut config = Config::default();
+        config.filters.secret_patterns = vec!["^INTERNAL_".to_string()];
+        assert!(config.is_secret_key("INTERNAL_URL"));
+        assert!(!config.is_secret_key("GITHUB_TOKEN"));
+    }
 }
@@ -1,5 +1,8 @@
 use crate::Env;
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EnvDiff {
@@ -22,7 +25,175 @@ pub enum EnvDiff {
     },
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffSummary {
+    pub changed: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl DiffSummary {
+    pub fn from_diffs(diffs: &[EnvDiff]) -> Self {
+        let mut summary = Self::default();
+        for diff in diffs {
+            match diff {
+                EnvDiff::Added { .. } => summary.added += 1,
+                EnvDiff::Removed { .. } => summary.removed += 1,
+                EnvDiff::Changed { .. } => summary.changed += 1,
+                EnvDiff::Unchanged { .. } => summary.unchanged += 1,
+            }
+        }
+        summary
+    }
+}
+
+impl EnvDiff {
+    pub fn key(&self) -> &str {
+        match self {
+            EnvDiff::Added { key, .. }
+            | EnvDiff::Removed { key, .. }
+            | EnvDiff::Changed { key, .. }
+            | EnvDiff::Unchanged { key, .. } => key,
+        }
+    }
+
+    /// The value after the change, or the removed one.
+    pub fn value(&self) -> &str {
+        match self {
+            EnvDiff::Added { value, .. }
+            | EnvDiff::Removed {
+                old_value: value, ..
+            }
+            | EnvDiff::Changed {
+                new_value: value, ..
+            }
+            | EnvDiff::Unchanged { value, .. } => value,
+        }
+    }
+
+    fn change_size(&self) -> usize {
+        match self {
+            EnvDiff::Added { value, .. } => value.len(),
+            EnvDiff::Removed { old_value, .. } => old_value.len(),
+            EnvDiff::Changed {
+                old_value,
+                new_value,
+                ..
+            } => old_value.len().abs_diff(new_value.len()),
+            EnvDiff::Unchanged { .. } => 0,
+        }
+    }
+
+    fn type_rank(&self) -> u8 {
+        match self {
+            EnvDiff::Added { .. } => 0,
+            EnvDiff::Removed { .. } => 1,
+            EnvDiff::Changed { .. } => 2,
+            EnvDiff::Unchanged { .. } => 3,
+        }
+    }
+}
+
+/// Value normalizations applied before deciding whether a variable changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Compare values case-insensitively
+    pub ignore_case: bool,
+    /// Ignore leading/trailing whitespace and treat whitespace runs as one space
+    pub ignore_whitespace: bool,
+}
+
+impl DiffOptions {
+    pub fn values_equal(&self, a: &str, b: &str) -> bool {
+        a == b || self.normalize(a) == self.normalize(b)
+    }
+
+    fn normalize(&self, value: &str) -> String {
+        let value = if self.ignore_whitespace {
+            value.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            value.to_string()
+        };
+        if self.ignore_case {
+            value.to_lowercase()
+        } else {
+            value
+        }
+    }
+}
+
 pub fn diff_envs(old: &Env, new: &Env) -> Vec<EnvDiff> {
+    diff_envs_with(old, new, &DiffOptions::default())
+}
+
+/// How alike two environments are, from 0.0 to 1.0: the share of variables
+/// across both that are set to the same value in each. Two empty
+/// environments are identical.
+pub fn similarity(a: &Env, b: &Env) -> f64 {
+    let matching = a
+        .iter()
+        .filter(|(key, value)| b.get(*key) == Some(*value))
+        .count();
+    let union = a.len() + b.keys().filter(|key| !a.contains_key(*key)).count();
+    if union == 0 {
+        1.0
+    } else {
+        matching as f64 / union as f64
+    }
+}
+
+/// Hex SHA-256 of `env` in key order, so equal environments hash equal
+/// across runs, builds and hosts.
+pub fn env_hash(env: &Env) -> String {
+    let sorted: BTreeMap<&String, &String> = env.iter().collect();
+    let mut hasher = Sha256::new();
+    for (key, value) in sorted {
+        // Length-prefixed so `A=BC` and `AB=C` differ
+        for part in [key, value] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Group `items` (newest first) whose `alike` score with their group's first,
+/// newest member is at least `threshold`. Each item is compared with the
+/// kept member only, so groups never chain through intermediate items.
+/// Returns groups of two or more, as indices into `items`.
+pub fn group_similar<T>(
+    items: &[T],
+    threshold: f64,
+    alike: impl Fn(&T, &T) -> f64,
+) -> Vec<Vec<usize>> {
+    let mut grouped = vec![false; items.len()];
+    let mut groups = Vec::new();
+    for keep in 0..items.len() {
+        if grouped[keep] {
+            continue;
+        }
+        let mut group = vec![keep];
+        for other in keep + 1..items.len() {
+            if !grouped[other] && alike(&items[keep], &items[other]) >= threshold {
+                grouped[other] = true;
+                group.push(other);
+            }
+        }
+        if group.len() > 1 {
+            groups.push(group);
+        }
+    }
+    groups
+}
+
+/// [`diff_envs`] with values normalized per `options`; pairs that only differ
+/// in ignored ways are reported as unchanged with the new value.
+pub fn diff_envs_with(old: &Env, new: &Env, options: &DiffOptions) -> Vec<EnvDiff> {
     let mut diffs = Vec::new();
 
     // Find added and changed
@@ -32,17 +203,17 @@ pub fn diff_envs(old: &Env, new: &Env) -> Vec<EnvDiff> {
                 key: key.clone(),
                 value: new_val.clone(),
             }),
-            Some(old_val) if old_val != new_val => {
+            Some(old_val) if !options.values_equal(old_val, new_val) => {
                 diffs.push(EnvDiff::Changed {
                     key: key.clone(),
                     old_value: old_val.clone(),
                     new_value: new_val.clone(),
                 });
             }
-            Some(val) => {
+            Some(_) => {
                 diffs.push(EnvDiff::Unchanged {
                     key: key.clone(),
-                    value: val.clone(),
+                    value: new_val.clone(),
                 });
             }
         }
@@ -59,23 +230,268 @@ pub fn diff_envs(old: &Env, new: &Env) -> Vec<EnvDiff> {
     }
 
     // Sort by key for consistent output
-    diffs.sort_by(|a, b| {
-        let key_a = match a {
-            EnvDiff::Added { key, .. }
-            | EnvDiff::Removed { key, .. }
-            | EnvDiff::Changed { key, .. }
-            | EnvDiff::Unchanged { key, .. } => key,
-        };
-        let key_b = match b {
-            EnvDiff::Added { key, .. }
-            | EnvDiff::Removed { key, .. }
-            | EnvDiff::Changed { key, .. }
-            | EnvDiff::Unchanged { key, .. } => key,
-        };
-        key_a.cmp(key_b)
-    });
+    diffs.sort_by(|a, b| a.key().cmp(b.key()));
+
+    diffs
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Diff3Status {
+    /// Neither side touched the variable
+    Unchanged,
+    /// Only ours diverged from base
+    Ours,
+    /// Only theirs diverged from base
+    Theirs,
+    /// Both sides made the same change
+    Both,
+    /// Both sides changed the variable to different values
+    Conflict,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diff3Entry {
+    pub key: String,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+    pub status: Diff3Status,
+}
+
+/// Compare two environments against a common base, sorted by key. `None`
+/// means the variable is absent on that side.
+pub fn diff3_envs(base: &Env, ours: &Env, theirs: &Env) -> Vec<Diff3Entry> {
+    let keys: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+
+    keys.into_iter()
+        .map(|key| {
+            let base_val = base.get(key);
+            let ours_val = ours.get(key);
+            let theirs_val = theirs.get(key);
+
+            let status = match (ours_val != base_val, theirs_val != base_val) {
+                (false, false) => Diff3Status::Unchanged,
+                (true, false) => Diff3Status::Ours,
+                (false, true) => Diff3Status::Theirs,
+                (true, true) if ours_val == theirs_val => Diff3Status::Both,
+                (true, true) => Diff3Status::Conflict,
+            };
+
+            Diff3Entry {
+                key: key.clone(),
+                base: base_val.cloned(),
+                ours: ours_val.cloned(),
+                theirs: theirs_val.cloned(),
+                status,
+            }
+        })
+        .collect()
+}
 
+/// A diff entry whose old state doesn't match the environment it is applied
+/// to. `None` means the variable is absent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffConflict {
+    pub key: String,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// The value a diff entry expects before it and leaves after it.
+fn diff_states(diff: &EnvDiff) -> (Option<&String>, Option<&String>) {
+    match diff {
+        EnvDiff::Added { value, .. } => (None, Some(value)),
+        EnvDiff::Removed { old_value, .. } => (Some(old_value), None),
+        EnvDiff::Changed {
+            old_value,
+            new_value,
+            ..
+        } => (Some(old_value), Some(new_value)),
+        EnvDiff::Unchanged { value, .. } => (Some(value), Some(value)),
+    }
+}
+
+/// Entries of `diffs` whose old state differs from `base`. A variable that
+/// already has the diff's new state is not a conflict.
+pub fn diff_conflicts(base: &Env, diffs: &[EnvDiff]) -> Vec<DiffConflict> {
     diffs
+        .iter()
+        .filter_map(|diff| {
+            let (before, after) = diff_states(diff);
+            let actual = base.get(diff.key());
+            (actual != before && actual != after).then(|| DiffConflict {
+                key: diff.key().to_string(),
+                expected: before.cloned(),
+                actual: actual.cloned(),
+            })
+        })
+        .collect()
+}
+
+impl std::fmt::Display for DiffConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |value: &Option<String>| value.clone().unwrap_or("(unset)".to_string());
+        write!(
+            f,
+            "{}: expected {}, found {}",
+            self.key,
+            describe(&self.expected),
+            describe(&self.actual)
+        )
+    }
+}
+
+/// `base` with every entry of `diffs` applied, failing if any of them
+/// conflicts with `base` (see [`diff_conflicts`]).
+pub fn apply_diff(base: &Env, diffs: &[EnvDiff]) -> Result<Env> {
+    apply_diff_with(base, diffs, false).map(|(env, _)| env)
+}
+
+/// Like [`apply_diff`], but with `force` conflicting entries are applied
+/// anyway (the diff's new state wins) and returned so the caller can warn.
+pub fn apply_diff_with(
+    base: &Env,
+    diffs: &[EnvDiff],
+    force: bool,
+) -> Result<(Env, Vec<DiffConflict>)> {
+    let conflicts = diff_conflicts(base, diffs);
+    if !force && !conflicts.is_empty() {
+        let listed: Vec<String> = conflicts.iter().map(ToString::to_string).collect();
+        anyhow::bail!(
+            "{} conflicting variable(s): {}",
+            conflicts.len(),
+            listed.join("; ")
+        );
+    }
+
+    let mut env = base.clone();
+    for diff in diffs {
+        match diff_states(diff).1 {
+            Some(value) => env.insert(diff.key().to_string(), value.clone()),
+            None => env.remove(diff.key()),
+        };
+    }
+    Ok((env, conflicts))
+}
+
+/// How to order a diff for display.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiffOrder {
+    /// By variable name
+    #[default]
+    Key,
+    /// By the new value (the old one for removals)
+    Value,
+    /// Largest change first: the length delta of a changed value, or the
+    /// whole length of an added or removed one
+    ChangeSize,
+    /// Added, then removed, then changed, then unchanged
+    Type,
+}
+
+/// Reorder `diffs` per `order`, falling back to the key for ties.
+pub fn sort_diffs(diffs: &mut [EnvDiff], order: DiffOrder) {
+    match order {
+        DiffOrder::Key => diffs.sort_by(|a, b| a.key().cmp(b.key())),
+        DiffOrder::Value => {
+            diffs.sort_by(|a, b| a.value().cmp(b.value()).then_with(|| a.key().cmp(b.key())))
+        }
+        DiffOrder::ChangeSize => diffs.sort_by(|a, b| {
+            b.change_size()
+                .cmp(&a.change_size())
+                .then_with(|| a.key().cmp(b.key()))
+        }),
+        DiffOrder::Type => diffs.sort_by(|a, b| {
+            a.type_rank()
+                .cmp(&b.type_rank())
+                .then_with(|| a.key().cmp(b.key()))
+        }),
+    }
+}
+
+/// Decide which entries of a sorted diff to display: every change, plus up to
+/// `context` unchanged entries on either side of it.
+pub fn context_mask(diffs: &[EnvDiff], context: usize) -> Vec<bool> {
+    let mut visible = vec![false; diffs.len()];
+
+    for (i, diff) in diffs.iter().enumerate() {
+        if matches!(diff, EnvDiff::Unchanged { .. }) {
+            continue;
+        }
+        let start = i.saturating_sub(context);
+        let end = (i + context).min(diffs.len() - 1);
+        for flag in &mut visible[start..=end] {
+            *flag = true;
+        }
+    }
+
+    visible
+}
+
+/// Element-level change of a delimiter-joined list variable such as `PATH`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Elements of a list value. Empty elements from doubled or trailing
+/// delimiters are kept, as shells give them meaning (an empty `PATH` entry
+/// is the current directory); only an empty value has no elements. With an
+/// empty delimiter the whole value is one element.
+pub fn split_list<'a>(value: &'a str, delimiter: &str) -> Vec<&'a str> {
+    if value.is_empty() {
+        Vec::new()
+    } else if delimiter.is_empty() {
+        vec![value]
+    } else {
+        value.split(delimiter).collect()
+    }
+}
+
+/// Elements only in `new` (in `new` order) and only in `old` (in `old` order).
+pub fn diff_list(old: &str, new: &str, delimiter: &str) -> ListDiff {
+    let old_elements = split_list(old, delimiter);
+    let new_elements = split_list(new, delimiter);
+
+    ListDiff {
+        added: new_elements
+            .iter()
+            .filter(|e| !old_elements.contains(e))
+            .map(|e| e.to_string())
+            .collect(),
+        removed: old_elements
+            .iter()
+            .filter(|e| !new_elements.contains(e))
+            .map(|e| e.to_string())
+            .collect(),
+    }
+}
+
+/// Add the elements of `incoming` missing from `current`, after the current
+/// elements or (with `prepend`) before them. With an empty delimiter the
+/// values aren't lists, so `incoming` replaces `current`.
+pub fn merge_list(current: &str, incoming: &str, delimiter: &str, prepend: bool) -> String {
+    if delimiter.is_empty() {
+        return incoming.to_string();
+    }
+    let current_elements = split_list(current, delimiter);
+    let missing: Vec<&str> = split_list(incoming, delimiter)
+        .into_iter()
+        .filter(|e| !current_elements.contains(e))
+        .collect();
+
+    let merged: Vec<&str> = if prepend {
+        missing.into_iter().chain(current_elements).collect()
+    } else {
+        current_elements.into_iter().chain(missing).collect()
+    };
+    merged.join(delimiter)
 }
 
 pub fn format_diff(diffs: &[EnvDiff], show_unchanged: bool) -> String {
@@ -126,6 +542,68 @@ pub fn format_diff(diffs: &[EnvDiff], show_unchanged: bool) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_similarity() {
+        let env = |pairs: &[(&str, &str)]| -> Env {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let a = env(&[("A", "1"), ("B", "2"), ("C", "3")]);
+
+        assert_eq!(similarity(&a, &a), 1.0);
+        assert_eq!(similarity(&Env::new(), &Env::new()), 1.0);
+        assert_eq!(similarity(&a, &Env::new()), 0.0);
+        // A matches; B differs; C and D are each on one side only
+        let b = env(&[("A", "1"), ("B", "x"), ("D", "4")]);
+        assert_eq!(similarity(&a, &b), 0.25);
+        assert_eq!(similarity(&b, &a), 0.25);
+    }
+
+    #[test]
+    fn test_env_hash() {
+        let env = |pairs: &[(&str, &str)]| -> Env {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        // Stable across builds: the digest of no input
+        assert_eq!(
+            env_hash(&Env::new()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let a = env(&[("A", "1"), ("B", "2")]);
+        let reordered = env(&[("B", "2"), ("A", "1")]);
+        assert_eq!(env_hash(&a), env_hash(&reordered));
+        assert_ne!(
+            env_hash(&env(&[("A", "BC")])),
+            env_hash(&env(&[("AB", "C")]))
+        );
+    }
+
+    #[test]
+    fn test_group_similar_compares_with_kept() {
+        let env = |pairs: &[(&str, &str)]| -> Env {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        // A~B and B~C at a third each, but A and C share nothing
+        let a = env(&[("K1", "1"), ("K2", "2")]);
+        let b = env(&[("K2", "2"), ("K3", "3")]);
+        let c = env(&[("K3", "3"), ("K4", "4")]);
+        let d = env(&[("K1", "1"), ("K2", "2"), ("K5", "5")]);
+        assert!(similarity(&a, &c) < 0.3);
+
+        let groups = group_similar(&[a, b, c, d], 0.3, similarity);
+        // B joins A's group; C is not pulled in through B
+        assert_eq!(groups, vec![vec![0, 1, 3]]);
+    }
 
     #[test]
     fn test_diff_envs() {
@@ -140,6 +618,282 @@ mod tests {
 
         let diffs = diff_envs(&old, &new);
 
-        assert_eq!(diffs.len(), 4); // 1 unchanged, 1 changed, 1 added, 1 removed (VAR2 from old)
+        // Nothing is removed: VAR2 is in both
+        assert_eq!(diffs.len(), 3);
+        assert!(matches!(&diffs[0], EnvDiff::Unchanged { key, .. } if key == "VAR1"));
+        assert!(matches!(&diffs[1], EnvDiff::Changed { key, .. } if key == "VAR2"));
+        assert!(matches!(&diffs[2], EnvDiff::Added { key, .. } if key == "VAR3"));
+    }
+
+    fn sorted_keys(order: DiffOrder) -> Vec<String> {
+        let old = Env::from([
+            ("A".to_string(), "x".to_string()),
+            ("B".to_string(), "short".to_string()),
+            ("C".to_string(), "gone".to_string()),
+            ("D".to_string(), "same".to_string()),
+        ]);
+        let new = Env::from([
+            ("A".to_string(), "a much longer x".to_string()),
+            ("B".to_string(), "shorter".to_string()),
+            ("D".to_string(), "same".to_string()),
+            ("E".to_string(), "new".to_string()),
+        ]);
+        let mut diffs = diff_envs(&old, &new);
+        sort_diffs(&mut diffs, order);
+        diffs.iter().map(|d| d.key().to_string()).collect()
+    }
+
+    #[test]
+    fn test_sort_diffs_by_key() {
+        assert_eq!(sorted_keys(DiffOrder::Key), ["A", "B", "C", "D", "E"]);
+    }
+
+    #[test]
+    fn test_sort_diffs_by_value() {
+        // "a much longer x" < "gone" < "new" < "same" < "shorter"
+        assert_eq!(sorted_keys(DiffOrder::Value), ["A", "C", "E", "D", "B"]);
+    }
+
+    #[test]
+    fn test_sort_diffs_by_change_size() {
+        // Deltas: A 14, C 4, E 3, B 2, D 0
+        assert_eq!(
+            sorted_keys(DiffOrder::ChangeSize),
+            ["A", "C", "E", "B", "D"]
+        );
+    }
+
+    #[test]
+    fn test_sort_diffs_by_type() {
+        assert_eq!(sorted_keys(DiffOrder::Type), ["E", "C", "A", "B", "D"]);
+    }
+
+    #[test]
+    fn test_apply_diff() {
+        let old = Env::from([
+            ("KEEP".to_string(), "same".to_string()),
+            ("EDIT".to_string(), "before".to_string()),
+            ("DROP".to_string(), "gone".to_string()),
+        ]);
+        let new = Env::from([
+            ("KEEP".to_string(), "same".to_string()),
+            ("EDIT".to_string(), "after".to_string()),
+            ("ADD".to_string(), "new".to_string()),
+        ]);
+        let diffs = diff_envs(&old, &new);
+
+        assert!(diff_conflicts(&old, &diffs).is_empty());
+        assert_eq!(apply_diff(&old, &diffs).unwrap(), new);
+
+        // Already applied is not a conflict
+        assert!(diff_conflicts(&new, &diffs).is_empty());
+    }
+
+    #[test]
+    fn test_diff_conflicts() {
+        let diffs = vec![
+            EnvDiff::Changed {
+                key: "EDIT".to_string(),
+                old_value: "before".to_string(),
+                new_value: "after".to_string(),
+            },
+            EnvDiff::Added {
+                key: "ADD".to_string(),
+                value: "new".to_string(),
+            },
+        ];
+        let base = Env::from([
+            ("EDIT".to_string(), "elsewhere".to_string()),
+            ("ADD".to_string(), "existing".to_string()),
+        ]);
+
+        let conflicts = diff_conflicts(&base, &diffs);
+        assert_eq!(
+            conflicts,
+            vec![
+                DiffConflict {
+                    key: "EDIT".to_string(),
+                    expected: Some("before".to_string()),
+                    actual: Some("elsewhere".to_string()),
+                },
+                DiffConflict {
+                    key: "ADD".to_string(),
+                    expected: None,
+                    actual: Some("existing".to_string()),
+                },
+            ]
+        );
+
+        let err = apply_diff(&base, &diffs).unwrap_err().to_string();
+        assert!(err.contains("EDIT: expected before, found elsewhere"));
+        assert!(err.contains("ADD: expected (unset), found existing"));
+
+        let (applied, _) = apply_diff_with(&base, &diffs, true).unwrap();
+        assert_eq!(applied.get("EDIT"), Some(&"after".to_string()));
+        assert_eq!(applied.get("ADD"), Some(&"new".to_string()));
+    }
+
+    fn single_diff(old: &str, new: &str, options: DiffOptions) -> EnvDiff {
+        let old_env = Env::from([("VAR".to_string(), old.to_string())]);
+        let new_env = Env::from([("VAR".to_string(), new.to_string())]);
+        diff_envs_with(&old_env, &new_env, &options).remove(0)
+    }
+
+    #[test]
+    fn test_diff_ignore_case() {
+        let options = DiffOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            single_diff("Debug", "DEBUG", options),
+            EnvDiff::Unchanged { .. }
+        ));
+        assert!(matches!(
+            single_diff("debug ", "DEBUG", options),
+            EnvDiff::Changed { .. }
+        ));
+        assert!(matches!(
+            single_diff("Debug", "DEBUG", DiffOptions::default()),
+            EnvDiff::Changed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_diff_ignore_whitespace() {
+        let options = DiffOptions {
+            ignore_whitespace: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            single_diff("-O2  -g\n", " -O2 -g", options),
+            EnvDiff::Unchanged { .. }
+        ));
+        assert!(matches!(
+            single_diff("-O2 -g", "-O2-g", options),
+            EnvDiff::Changed { .. }
+        ));
+        assert!(matches!(
+            single_diff(
+                "A b",
+                "a  B",
+                DiffOptions {
+                    ignore_case: true,
+                    ignore_whitespace: true
+                }
+            ),
+            EnvDiff::Unchanged { .. }
+        ));
+    }
+
+    #[test]
+    fn test_context_mask() {
+        let mut old = Env::new();
+        let mut new = Env::new();
+        for key in ["A", "B", "C", "D", "E", "F", "G"] {
+            old.insert(key.to_string(), "same".to_string());
+            new.insert(key.to_string(), "same".to_string());
+        }
+        new.insert("D".to_string(), "changed".to_string());
+
+        let diffs = diff_envs(&old, &new);
+
+        assert_eq!(
+            context_mask(&diffs, 0),
+            vec![false, false, false, true, false, false, false]
+        );
+        assert_eq!(
+            context_mask(&diffs, 2),
+            vec![false, true, true, true, true, true, false]
+        );
+        assert!(context_mask(&diffs, 10).iter().all(|v| *v));
+    }
+
+    #[test]
+    fn test_diff3_envs() {
+        let env = |pairs: &[(&str, &str)]| -> Env {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let base = env(&[
+            ("SAME", "1"),
+            ("OURS", "1"),
+            ("THEIRS", "1"),
+            ("BOTH", "1"),
+            ("CONFLICT", "1"),
+        ]);
+        let ours = env(&[
+            ("SAME", "1"),
+            ("OURS", "2"),
+            ("THEIRS", "1"),
+            ("BOTH", "2"),
+            ("CONFLICT", "2"),
+        ]);
+        let theirs = env(&[
+            ("SAME", "1"),
+            ("OURS", "1"),
+            ("BOTH", "2"),
+            ("CONFLICT", "3"),
+            ("NEW", "1"),
+        ]);
+
+        let result = diff3_envs(&base, &ours, &theirs);
+        let status_of = |key: &str| {
+            result
+                .iter()
+                .find(|e| e.key == key)
+                .map(|e| e.status.clone())
+                .unwrap()
+        };
+
+        assert_eq!(result.len(), 6);
+        assert_eq!(status_of("SAME"), Diff3Status::Unchanged);
+        assert_eq!(status_of("OURS"), Diff3Status::Ours);
+        assert_eq!(status_of("THEIRS"), Diff3Status::Theirs);
+        assert_eq!(status_of("BOTH"), Diff3Status::Both);
+        assert_eq!(status_of("CONFLICT"), Diff3Status::Conflict);
+        assert_eq!(status_of("NEW"), Diff3Status::Theirs);
+    }
+
+    #[test]
+    fn test_diff_list() {
+        let diff = diff_list("/usr/bin:/bin:/opt/a", "/opt/b:/usr/bin:/bin", ":");
+        assert_eq!(diff.added, vec!["/opt/b"]);
+        assert_eq!(diff.removed, vec!["/opt/a"]);
+
+        assert_eq!(split_list("a::b:", ":"), vec!["a", "", "b", ""]);
+        assert_eq!(split_list("", ":"), Vec::<&str>::new());
+        assert_eq!(split_list("a:b", ""), vec!["a:b"]);
+
+        let diff = diff_list("/bin", "/bin:", ":");
+        assert_eq!(diff.added, vec![""]);
+    }
+
+    #[test]
+    fn test_merge_list() {
+        assert_eq!(merge_list("a:b", "b:c", ":", false), "a:b:c");
+        assert_eq!(merge_list("a:b", "b:c", ":", true), "c:a:b");
+        assert_eq!(merge_list("", "x y", " ", false), "x y");
+        // The empty (current directory) entry survives the merge
+        assert_eq!(merge_list("a::b", "c", ":", false), "a::b:c");
+        assert_eq!(merge_list("a", ":b", ":", true), ":b:a");
+        assert_eq!(merge_list("a:b", "c", "", false), "c");
+    }
+
+    fn arb_env() -> impl Strategy<Value = Env> {
+        // A small key and value space so pairs share keys and values
+        prop::collection::hash_map("[A-E]", "[ab]{0,2}", 0..6)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_apply_diff_round_trip(old in arb_env(), new in arb_env()) {
+            let diffs = diff_envs(&old, &new);
+            prop_assert!(diff_conflicts(&old, &diffs).is_empty());
+            prop_assert_eq!(apply_diff(&old, &diffs).unwrap(), new);
+        }
     }
 }
@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Parse a compact age like `90d`, `12h`, `30m`, `45s` or `2w`.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .with_context(|| format!("Missing unit in duration '{}' (use s, m, h, d or w)", input))?;
+    let (number, unit) = input.split_at(split);
+    let amount: i64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", input))?;
+
+    let duration = match unit {
+        "s" => Duration::seconds(amount),
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => anyhow::bail!(
+            "Unknown unit '{}' in duration '{}' (use s, m, h, d or w)",
+            unit,
+            input
+        ),
+    };
+    Ok(duration)
+}
+
+/// Resolve a `--since` filter to the earliest timestamp it includes.
+///
+/// Accepts `today`, `yesterday` and `this week` (from Monday), which start at
+/// midnight in `timezone` (`local`, `UTC` or an IANA name such as
+/// `Europe/Berlin`), as well as `2 hours ago`-style phrases and compact
+/// durations like `90m`.
+pub fn parse_since(input: &str, timezone: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let input = input.trim().to_lowercase();
+    let days_back = match input.as_str() {
+        "today" => Some(0),
+        "yesterday" => Some(1),
+        "this week" => None,
+        _ => return Ok(now - parse_relative(&input)?),
+    };
+
+    let start = match zone(timezone)? {
+        None => day_start(now.with_timezone(&Local), days_back),
+        Some(tz) => day_start(now.with_timezone(&tz), days_back),
+    };
+    start.with_context(|| format!("Cannot resolve '{}' in timezone {}", input, timezone))
+}
+
+/// Resolve an `--at` moment to a timestamp.
+///
+/// Accepts RFC 3339 timestamps, `YYYY-MM-DD HH:MM[:SS]` or a bare date
+/// (midnight) in `timezone`, and anything [`parse_since`] understands.
+pub fn parse_at(input: &str, timezone: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(at) = DateTime::parse_from_rfc3339(input) {
+        return Ok(at.with_timezone(&Utc));
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        });
+    let Some(naive) = naive else {
+        return parse_since(input, timezone, now).map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid time '{}' (try '2025-11-07 15:00', an RFC 3339 timestamp or '2 hours ago')",
+                input
+            )
+        });
+    };
+
+    let at = match zone(timezone)? {
+        None => Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|at| at.with_timezone(&Utc)),
+        Some(tz) => tz
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|at| at.with_timezone(&Utc)),
+    };
+    at.with_context(|| format!("'{}' does not exist in timezone {}", input, timezone))
+}
+
+/// The configured display zone, or `None` for the system's local zone.
+fn zone(timezone: &str) -> Result<Option<Tz>> {
+    if timezone.eq_ignore_ascii_case("local") {
+        return Ok(None);
+    }
+    if timezone.eq_ignore_ascii_case("utc") {
+        return Ok(Some(Tz::UTC));
+    }
+    timezone
+        .parse()
+        .map(Some)
+        .map_err(|_| anyhow::anyhow!("Unknown timezone '{}' in display.timezone", timezone))
+}
+
+/// Midnight `days_back` days before `now`'s calendar day in its own zone, or
+/// the Monday of its week when `days_back` is `None`.
+fn day_start<T: TimeZone>(now: DateTime<T>, days_back: Option<u32>) -> Option<DateTime<Utc>> {
+    let days_back = days_back.unwrap_or_else(|| now.weekday().num_days_from_monday());
+    let date = now.date_naive() - Duration::days(days_back.into());
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    // Zones that skip midnight for DST start the day at the first valid time
+    let start = now
+        .timezone()
+        .from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| {
+            now.timezone()
+                .from_local_datetime(&(midnight + Duration::hours(1)))
+                .earliest()
+        })?;
+    Some(start.with_timezone(&Utc))
+}
+
+/// `N <unit>(s) ago` or a compact duration.
+fn parse_relative(input: &str) -> Result<Duration> {
+    let Some(phrase) = input.strip_suffix(" ago") else {
+        return parse_duration(input).with_context(|| {
+            format!(
+                "Invalid --since '{}' (try today, yesterday, this week, '2 hours ago' or 90m)",
+                input
+            )
+        });
+    };
+
+    let (number, unit) = phrase
+        .split_once(' ')
+        .with_context(|| format!("Invalid --since '{}'", input))?;
+    let amount: i64 = number
+        .parse()
+        .with_context(|| format!("Invalid number in --since '{}'", input))?;
+    let duration = match unit.trim_end_matches('s') {
+        "second" => Duration::seconds(amount),
+        "minute" => Duration::minutes(amount),
+        "hour" => Duration::hours(amount),
+        "day" => Duration::days(amount),
+        "week" => Duration::weeks(amount),
+        _ => anyhow::bail!("Unknown unit '{}' in --since '{}'", unit, input),
+    };
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90d").unwrap(), Duration::days(90));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::weeks(2));
+        assert!(parse_duration("90").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
+
+    #[test]
+    fn test_parse_since_calendar_days() {
+        // Saturday 2025-06-14 22:00 in New York (EDT, UTC-4)
+        let now = Utc.with_ymd_and_hms(2025, 6, 15, 2, 0, 0).unwrap();
+
+        let at = |y, m, d, h| Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap();
+        assert_eq!(
+            parse_since("today", "America/New_York", now).unwrap(),
+            at(2025, 6, 14, 4)
+        );
+        assert_eq!(
+            parse_since("yesterday", "America/New_York", now).unwrap(),
+            at(2025, 6, 13, 4)
+        );
+        assert_eq!(
+            parse_since("this week", "America/New_York", now).unwrap(),
+            at(2025, 6, 9, 4)
+        );
+
+        // Already Sunday in UTC
+        assert_eq!(
+            parse_since("today", "UTC", now).unwrap(),
+            at(2025, 6, 15, 0)
+        );
+        assert_eq!(
+            parse_since("This Week", "utc", now).unwrap(),
+            at(2025, 6, 9, 0)
+        );
+
+        assert!(parse_since("today", "Mars/Olympus", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_since_relative() {
+        let now = Utc.with_ymd_and_hms(2025, 6, 15, 2, 0, 0).unwrap();
+
+        assert_eq!(
+            parse_since("2 hours ago", "local", now).unwrap(),
+            now - Duration::hours(2)
+        );
+        assert_eq!(
+            parse_since("1 day ago", "local", now).unwrap(),
+            now - Duration::days(1)
+        );
+        assert_eq!(
+            parse_since("90m", "local", now).unwrap(),
+            now - Duration::minutes(90)
+        );
+        assert!(parse_since("last tuesday", "local", now).is_err());
+        assert!(parse_since("2 fortnights ago", "local", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_at() {
+        let now = Utc.with_ymd_and_hms(2025, 6, 15, 2, 0, 0).unwrap();
+
+        assert_eq!(
+            parse_at("2025-06-10 15:30", "America/New_York", now).unwrap(),
+            Utc.with_ymd_and_hms(2025, 6, 10, 19, 30, 0).unwrap()
+        );
+        assert_eq!(
+            parse_at("2025-06-10", "UTC", now).unwrap(),
+            Utc.with_ymd_and_hms(2025, 6, 10, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_at("2025-06-10T12:00:00+02:00", "local", now).unwrap(),
+            Utc.with_ymd_and_hms(2025, 6, 10, 10, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_at("2 hours ago", "local", now).unwrap(),
+            now - Duration::hours(2)
+        );
+        assert!(parse_at("2025-13-40", "UTC", now).is_err());
+    }
+}
@@ -0,0 +1,475 @@
+use crate::Env;
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Parse `KEY=VALUE` lines as printed by `env`. Each line holds exactly one
+/// variable; blank lines and lines without `=` are skipped.
+pub fn parse_env_lines(content: &str) -> Env {
+    let mut env = Env::new();
+
+    for line in content.lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            if is_valid_key(key) {
+                env.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    env
+}
+
+/// Parse a list of variable names, one per line. Blank lines and lines
+/// starting with `#` are skipped; anything else must be a valid name.
+pub fn parse_key_list(content: &str) -> Result<BTreeSet<String>> {
+    let mut keys = BTreeSet::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !is_valid_key(line) {
+            anyhow::bail!("Line {}: '{}' is not a variable name", number + 1, line);
+        }
+        keys.insert(line.to_string());
+    }
+    Ok(keys)
+}
+
+pub fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Canonical, key-ordered form of an environment: keys are trimmed and
+/// entries whose key is not a valid variable name are dropped. Values are
+/// kept verbatim since whitespace in them is significant.
+pub fn normalize(env: &Env) -> BTreeMap<String, String> {
+    env.iter()
+        .map(|(key, value)| (key.trim().to_string(), value.clone()))
+        .filter(|(key, _)| is_valid_key(key))
+        .collect()
+}
+
+fn is_plain(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,@%+=".contains(c))
+}
+
+/// Quote a value so it survives a round trip through a POSIX shell.
+pub fn posix_quote(value: &str) -> String {
+    if is_plain(value) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// Quote a value for fish, where single quotes only honor `\\` and `\'`.
+pub fn fish_quote(value: &str) -> String {
+    if is_plain(value) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+}
+
+/// Quote a value for a `.env` file. Control characters are escaped so every
+/// variable stays on one line.
+pub fn dotenv_quote(value: &str) -> String {
+    if is_plain(value) {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str("\\$"),
+            '`' => quoted.push_str("\\`"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn to_dotenv(env: &Env) -> String {
+    normalize(env)
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, dotenv_quote(value)))
+        .collect()
+}
+
+pub fn posix_export_line(key: &str, value: &str) -> String {
+    format!("export {}={}", key, posix_quote(value))
+}
+
+pub fn fish_export_line(key: &str, value: &str) -> String {
+    format!("set -gx {} {}", key, fish_quote(value))
+}
+
+/// bash's `declare -x` form of [`posix_export_line`].
+pub fn bash_declare_line(key: &str, value: &str) -> String {
+    format!("declare -x {}={}", key, posix_quote(value))
+}
+
+/// zsh's `typeset -gx`, which stays global even when sourced from a function.
+pub fn zsh_typeset_line(key: &str, value: &str) -> String {
+    format!("typeset -gx {}={}", key, posix_quote(value))
+}
+
+/// Parse a `.env` file or a script of `export KEY=...` (POSIX),
+/// `declare -x`/`typeset -gx KEY=...` (bash/zsh) or `set -gx KEY ...` (fish)
+/// lines back into an environment.
+///
+/// Quoted values may span lines. Inside double quotes `\n`, `\r` and `\t`
+/// are read as control characters, matching what [`dotenv_quote`] writes.
+pub fn parse_env_file(content: &str) -> Result<Env> {
+    let mut parser = Parser {
+        chars: content.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let mut env = Env::new();
+
+    loop {
+        parser.skip_while(char::is_whitespace);
+        match parser.peek() {
+            None => break,
+            Some('#') => {
+                parser.skip_while(|c| c != '\n');
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        let line = parser.line;
+        let head = parser.take_while(|c| !c.is_whitespace() && c != '=');
+
+        let (key, value) = match head.as_str() {
+            "export" | "declare" | "typeset" => {
+                // Skip attribute flags such as `-x` or `-gx`
+                let key = loop {
+                    parser.skip_while(|c| c == ' ' || c == '\t');
+                    let word = parser.take_while(|c| !c.is_whitespace() && c != '=');
+                    if !word.starts_with('-') {
+                        break word;
+                    }
+                };
+                parser.expect_assignment(&key, line)?;
+                (key, parser.read_value()?)
+            }
+            "set" => {
+                // fish: set [-g] [-x] [-gx] KEY VALUE
+                let key = loop {
+                    parser.skip_while(|c| c == ' ' || c == '\t');
+                    let word = parser.take_while(|c| !c.is_whitespace());
+                    if !word.starts_with('-') {
+                        break word;
+                    }
+                };
+                parser.skip_while(|c| c == ' ' || c == '\t');
+                let value = parser.read_fish_word()?;
+                parser.skip_while(|c| c != '\n');
+                (key, value)
+            }
+            _ => {
+                parser.expect_assignment(&head, line)?;
+                (head, parser.read_value()?)
+            }
+        };
+
+        if !is_valid_key(&key) {
+            anyhow::bail!("Invalid variable name '{}' on line {}", key, line);
+        }
+        env.insert(key, value);
+    }
+
+    Ok(env)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_while(&mut self, pred: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&pred) {
+            self.next();
+        }
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.peek().filter(|c| pred(*c)) {
+            taken.push(c);
+            self.next();
+        }
+        taken
+    }
+
+    fn expect_assignment(&mut self, key: &str, line: usize) -> Result<()> {
+        if self.next() != Some('=') {
+            anyhow::bail!("Expected '=' after '{}' on line {}", key, line);
+        }
+        Ok(())
+    }
+
+    fn unterminated(&self, line: usize) -> anyhow::Error {
+        anyhow::anyhow!("Unterminated quote starting on line {}", line)
+    }
+
+    /// Read a POSIX/dotenv value up to the end of the line or a ` #` comment.
+    /// Unquoted inner whitespace is kept, trailing whitespace is dropped.
+    fn read_value(&mut self) -> Result<String> {
+        let mut value = String::new();
+        let mut pending_space = String::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => break,
+                ' ' | '\t' => {
+                    self.next();
+                    pending_space.push(c);
+                    continue;
+                }
+                '#' if !pending_space.is_empty() => {
+                    self.skip_while(|c| c != '\n');
+                    break;
+                }
+                _ => {}
+            }
+            value.push_str(&pending_space);
+            pending_space.clear();
+
+            let start = self.line;
+            self.next();
+            match c {
+                '\'' => loop {
+                    match self.next() {
+                        Some('\'') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(self.unterminated(start)),
+                    }
+                },
+                '"' => self.read_double_quoted(&mut value, start)?,
+                '\\' => match self.next() {
+                    Some('\n') | None => {}
+                    Some(c) => value.push(c),
+                },
+                _ => value.push(c),
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn read_double_quoted(&mut self, value: &mut String, start: usize) -> Result<()> {
+        loop {
+            match self.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some('\n') => {}
+                    Some(c @ ('\\' | '"' | '$' | '`')) => value.push(c),
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => return Err(self.unterminated(start)),
+                },
+                Some(c) => value.push(c),
+                None => return Err(self.unterminated(start)),
+            }
+        }
+    }
+
+    /// Read a single fish word, honoring fish's single-quote escapes.
+    fn read_fish_word(&mut self) -> Result<String> {
+        let mut value = String::new();
+
+        while let Some(c) = self.peek().filter(|c| !c.is_whitespace()) {
+            let start = self.line;
+            self.next();
+            match c {
+                '\'' => loop {
+                    match self.next() {
+                        Some('\'') => break,
+                        Some('\\') if matches!(self.peek(), Some('\\' | '\'')) => {
+                            value.extend(self.next());
+                        }
+                        Some(c) => value.push(c),
+                        None => return Err(self.unterminated(start)),
+                    }
+                },
+                '"' => self.read_double_quoted(&mut value, start)?,
+                '\\' => value.extend(self.next()),
+                _ => value.push(c),
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn tricky_env() -> Env {
+        [
+            ("PLAIN", "value"),
+            ("EMPTY", ""),
+            ("SPACES", "  padded value  "),
+            ("QUOTES", r#"it's a "quote""#),
+            ("EQUALS", "a=b=c"),
+            ("NEWLINES", "line1\nline2\r\n"),
+            ("SHELLY", "$HOME `id` \\ ; | & * ~"),
+            ("HASH", "value # not a comment"),
+            ("UNICODE", "héllo wörld ✓"),
+            ("TAB", "a\tb"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_parse_env_lines() {
+        let env = parse_env_lines("FOO=bar\nURL=http://x?a=b\n\nnot a var\nEMPTY=\n");
+
+        assert_eq!(env.len(), 3);
+        assert_eq!(env.get("FOO"), Some(&"bar".to_string()));
+        assert_eq!(env.get("URL"), Some(&"http://x?a=b".to_string()));
+        assert_eq!(env.get("EMPTY"), Some(&String::new()));
+    }
+
+    #[test]
+    fn test_parse_key_list() {
+        let keys = parse_key_list("# CI vars\nDATABASE_URL\n\n  API_KEY  \n").unwrap();
+        assert_eq!(
+            keys.into_iter().collect::<Vec<_>>(),
+            vec!["API_KEY", "DATABASE_URL"]
+        );
+
+        let err = parse_key_list("OK\nNOT OK\n").unwrap_err();
+        assert!(err.to_string().contains("Line 2"));
+    }
+
+    #[test]
+    fn test_round_trip_formats() {
+        let env = tricky_env();
+
+        let script = |line: fn(&str, &str) -> String| -> String {
+            env.iter().map(|(k, v)| line(k, v) + "\n").collect()
+        };
+
+        assert_eq!(parse_env_file(&to_dotenv(&env)).unwrap(), env);
+        assert_eq!(parse_env_file(&script(posix_export_line)).unwrap(), env);
+        assert_eq!(parse_env_file(&script(fish_export_line)).unwrap(), env);
+        assert_eq!(parse_env_file(&script(bash_declare_line)).unwrap(), env);
+        assert_eq!(parse_env_file(&script(zsh_typeset_line)).unwrap(), env);
+    }
+
+    #[test]
+    fn test_declare_lines() {
+        assert_eq!(
+            bash_declare_line("GREETING", "it's here"),
+            r#"declare -x GREETING='it'\''s here'"#
+        );
+        assert_eq!(
+            zsh_typeset_line("GREETING", "it's here"),
+            r#"typeset -gx GREETING='it'\''s here'"#
+        );
+        assert_eq!(
+            fish_export_line("GREETING", "it's here"),
+            r#"set -gx GREETING 'it\'s here'"#
+        );
+    }
+
+    #[test]
+    fn test_parse_handwritten_dotenv() {
+        let content = "# database\nexport DB_HOST=localhost\nDB_NAME = app\nGREETING=hello world # trailing\nMULTI=\"a\nb\"\n";
+        let env = parse_env_file(content);
+
+        // Spaces around '=' are not valid
+        assert!(env.is_err());
+
+        let content = "# database\nexport DB_HOST=localhost\nGREETING=hello world # trailing\nMULTI=\"a\nb\"\n";
+        let env = parse_env_file(content).unwrap();
+        assert_eq!(env.get("DB_HOST"), Some(&"localhost".to_string()));
+        assert_eq!(env.get("GREETING"), Some(&"hello world".to_string()));
+        assert_eq!(env.get("MULTI"), Some(&"a\nb".to_string()));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut env = Env::new();
+        env.insert(" PADDED ".to_string(), " kept ".to_string());
+        env.insert("1INVALID".to_string(), "x".to_string());
+        env.insert("A".to_string(), "a".to_string());
+
+        let normalized: Vec<_> = normalize(&env).into_iter().collect();
+        assert_eq!(
+            normalized,
+            vec![
+                ("A".to_string(), "a".to_string()),
+                ("PADDED".to_string(), " kept ".to_string()),
+            ]
+        );
+    }
+
+    fn arb_env() -> impl Strategy<Value = Env> {
+        let value = prop_oneof![
+            Just(String::new()),
+            "[a-z=]{1,8}",
+            "[ \t\n\r'\"\\$`#=]{1,8}",
+            any::<String>(),
+        ];
+        prop::collection::hash_map("[A-Za-z_][A-Za-z0-9_]{0,15}", value, 0..12)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_export_import_round_trip(env in arb_env()) {
+            let script = |line: fn(&str, &str) -> String| -> String {
+                env.iter().map(|(k, v)| line(k, v) + "\n").collect()
+            };
+
+            prop_assert_eq!(&parse_env_file(&to_dotenv(&env)).unwrap(), &env);
+            prop_assert_eq!(&parse_env_file(&script(posix_export_line)).unwrap(), &env);
+            prop_assert_eq!(&parse_env_file(&script(fish_export_line)).unwrap(), &env);
+
+            let json = serde_json::to_string(&normalize(&env)).unwrap();
+            prop_assert_eq!(&serde_json::from_str::<Env>(&json).unwrap(), &env);
+        }
+    }
+}
@@ -1,8 +1,17 @@
+pub mod audit;
+pub mod backend;
+pub mod catalog;
 pub mod config;
 pub mod differ;
+pub mod duration;
+pub mod envfile;
+pub mod secrets;
 pub mod session;
 pub mod storage;
+pub mod timeline;
 
+pub use backend::{MemoryBackend, StorageBackend};
+pub use catalog::Catalog;
 pub use config::Config;
 pub use differ::{diff_envs, EnvDiff};
 pub use session::{Session, SessionMetadata};
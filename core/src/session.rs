@@ -5,6 +5,24 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use uuid::Uuid;
 
+/// Sidecar next to `timeline.jsonl` holding notes on timeline entries, so
+/// annotating never rewrites the timeline the daemon appends to.
+pub const NOTES_FILE: &str = "notes.jsonl";
+
+/// Timeline written when `core.timeline_format = "bincode"`, next to
+/// `timeline.jsonl`.
+pub const BINARY_TIMELINE_FILE: &str = "timeline.bin";
+
+/// How many leading characters of a session id `short_id` keeps.
+pub const SHORT_ID_LEN: usize = 8;
+
+/// Leading characters of `id`, used wherever a session is shown or named.
+pub fn short_id(id: &Uuid) -> String {
+    id.to_string()[..SHORT_ID_LEN].to_string()
+}
+
+/// Sessions compare and hash by id alone; the rest is state that changes
+/// as the session runs.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     pub id: Uuid,
@@ -12,6 +30,9 @@ pub struct Session {
     pub shell: String,
     pub started_at: DateTime<Utc>,
     pub last_updated: DateTime<Utc>,
+    /// Labels set with `envhist session tag`
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +41,20 @@ pub struct SessionMetadata {
     pub current_env: Env,
 }
 
+impl PartialEq for Session {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Session {}
+
+impl std::hash::Hash for Session {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 impl Session {
     pub fn new(pid: u32, shell: String) -> Self {
         let now = Utc::now();
@@ -29,9 +64,14 @@ impl Session {
             shell,
             started_at: now,
             last_updated: now,
+            tags: Vec::new(),
         }
     }
 
+    pub fn short_id(&self) -> String {
+        short_id(&self.id)
+    }
+
     pub fn update_timestamp(&mut self) {
         self.last_updated = Utc::now();
     }
@@ -44,6 +84,14 @@ impl Session {
         self.session_dir().join("timeline.jsonl")
     }
 
+    pub fn binary_timeline_path(&self) -> PathBuf {
+        self.session_dir().join(BINARY_TIMELINE_FILE)
+    }
+
+    pub fn notes_path(&self) -> PathBuf {
+        self.session_dir().join(NOTES_FILE)
+    }
+
     pub fn metadata_path(&self) -> PathBuf {
         self.session_dir().join("metadata.json")
     }
@@ -1,20 +1,286 @@
-use crate::{config::Config, session::Session, Env};
+use crate::{
+    config::{Config, TimelineFormat},
+    secrets,
+    session::{Session, SessionMetadata},
+    Env,
+};
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::OpenOptions,
-    io::{BufRead, BufReader, Write},
-    path::PathBuf,
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Mutex, OnceLock},
 };
 
+/// Format version written into new timeline entries. Entries from before
+/// versioning was introduced deserialize with `v == 0`.
+pub const TIMELINE_VERSION: u32 = 1;
+
+// Fields added after the initial format must carry `#[serde(default)]` so
+// that existing timeline files keep parsing.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimelineEntry {
+    #[serde(default)]
+    pub v: u32,
     pub timestamp: DateTime<Utc>,
     pub action: Action,
     pub key: String,
+    #[serde(default)]
     pub value: Option<String>,
+    #[serde(default)]
     pub prev: Option<String>,
+    /// Annotation from `envhist note`; filled in from the notes sidecar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Position in the session's timeline, assigned by the daemon from 1;
+    /// orders entries that share a timestamp. 0 for entries written before
+    /// it existed.
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// A [`TimelineEntry`] as stored in binary timelines. Bincode isn't
+/// self-describing, so every field is always written; notes stay in the
+/// sidecar.
+#[derive(Serialize, Deserialize)]
+struct BinaryEntry {
+    v: u32,
+    timestamp: DateTime<Utc>,
+    action: Action,
+    key: String,
+    value: Option<String>,
+    prev: Option<String>,
+    seq: u64,
+}
+
+impl From<&TimelineEntry> for BinaryEntry {
+    fn from(entry: &TimelineEntry) -> Self {
+        Self {
+            v: entry.v,
+            timestamp: entry.timestamp,
+            action: entry.action.clone(),
+            key: entry.key.clone(),
+            value: entry.value.clone(),
+            prev: entry.prev.clone(),
+            seq: entry.seq,
+        }
+    }
+}
+
+impl From<BinaryEntry> for TimelineEntry {
+    fn from(entry: BinaryEntry) -> Self {
+        Self {
+            v: entry.v,
+            timestamp: entry.timestamp,
+            action: entry.action,
+            key: entry.key,
+            value: entry.value,
+            prev: entry.prev,
+            note: None,
+            seq: entry.seq,
+        }
+    }
+}
+
+/// `entry` framed for a binary timeline: its length as a little-endian
+/// `u32`, then the bincode payload.
+fn binary_record(entry: &TimelineEntry) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(&BinaryEntry::from(entry))
+        .context("Failed to serialize timeline entry")?;
+    let len = u32::try_from(payload.len()).context("Timeline entry is too large")?;
+    let mut record = len.to_le_bytes().to_vec();
+    record.extend(payload);
+    Ok(record)
+}
+
+/// Bytes taken by the complete records at the start of a binary timeline of
+/// `size` bytes. Anything after them is a record cut short by an
+/// interrupted write.
+fn complete_records_len(reader: &mut (impl Read + Seek), size: u64) -> std::io::Result<u64> {
+    let mut pos = 0;
+    let mut len_bytes = [0; 4];
+    while size - pos >= 4 {
+        reader.read_exact(&mut len_bytes)?;
+        let len = u64::from(u32::from_le_bytes(len_bytes));
+        if size - pos - 4 < len {
+            break;
+        }
+        reader.seek_relative(len as i64)?;
+        pos += 4 + len;
+    }
+    Ok(pos)
+}
+
+fn warn_partial_record(path: &Path, pos: u64) {
+    eprintln!(
+        "⚠ Timeline {:?} ends in a partial record at byte {} (interrupted write?); ignoring it",
+        path, pos
+    );
+}
+
+/// Where this process last finished appending to each binary timeline.
+/// Records before that offset are known to be whole, so appends only check
+/// what was written since.
+fn appended_ends() -> &'static Mutex<HashMap<PathBuf, u64>> {
+    static ENDS: OnceLock<Mutex<HashMap<PathBuf, u64>>> = OnceLock::new();
+    ENDS.get_or_init(Default::default)
+}
+
+/// Drop a partial record at the end of the binary timeline `file`, so the
+/// next record starts on a frame boundary. Only the bytes after `start`, a
+/// known frame boundary, are checked. Returns the resulting length.
+fn truncate_partial_record(mut file: &std::fs::File, path: &Path, start: u64) -> Result<u64> {
+    let size = file
+        .metadata()
+        .with_context(|| format!("Failed to stat timeline file {:?}", path))?
+        .len();
+    // The file shrank since we last wrote it, so check it from the start
+    let start = if start <= size { start } else { 0 };
+    if start == size {
+        return Ok(size);
+    }
+    file.seek(SeekFrom::Start(start))
+        .with_context(|| format!("Failed to read timeline file {:?}", path))?;
+    let complete = start
+        + complete_records_len(&mut BufReader::new(file), size - start)
+            .with_context(|| format!("Failed to read timeline file {:?}", path))?;
+    if complete < size {
+        warn_partial_record(path, complete);
+        file.set_len(complete)
+            .with_context(|| format!("Failed to truncate timeline file {:?}", path))?;
+    }
+    Ok(complete)
+}
+
+/// Append `entry` to the timeline file at `timeline_path`, written in
+/// `format`.
+fn append_timeline_at(
+    timeline_path: &Path,
+    format: TimelineFormat,
+    entry: &TimelineEntry,
+) -> Result<()> {
+    if let Some(parent) = timeline_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create timeline directory {:?}", parent))?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(timeline_path)
+        .with_context(|| format!("Failed to open timeline file {:?}", timeline_path))?;
+
+    match format {
+        TimelineFormat::Jsonl => {
+            let mut line =
+                serde_json::to_string(entry).context("Failed to serialize timeline entry")?;
+            line.push('\n');
+            // One write per entry, so concurrent appends don't interleave
+            file.write_all(line.as_bytes())
+                .with_context(|| format!("Failed to write to timeline file {:?}", timeline_path))?;
+        }
+        TimelineFormat::Bincode => {
+            append_binary_record(&mut file, timeline_path, &binary_record(entry)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Append `record` to the binary timeline `file`, first dropping a partial
+/// record left at its end by an interrupted write.
+fn append_binary_record(file: &mut std::fs::File, path: &Path, record: &[u8]) -> Result<()> {
+    // Held until `file` is closed, so no other writer appends between the
+    // check and the write
+    file.lock()
+        .with_context(|| format!("Failed to lock timeline file {:?}", path))?;
+    let mut ends = appended_ends().lock().unwrap_or_else(|e| e.into_inner());
+    // Forgotten until the write succeeds, so a failed write is checked again
+    let start = ends.remove(path).unwrap_or(0);
+    let end = truncate_partial_record(file, path, start)?;
+    file.write_all(record)
+        .with_context(|| format!("Failed to write to timeline file {:?}", path))?;
+    ends.insert(path.to_path_buf(), end + record.len() as u64);
+    Ok(())
+}
+
+/// The entries of a binary timeline, up to a partial record left at its end.
+fn read_binary_timeline(path: &Path) -> Result<Vec<TimelineEntry>> {
+    let data =
+        std::fs::read(path).with_context(|| format!("Failed to read timeline file {:?}", path))?;
+    let complete = complete_records_len(&mut Cursor::new(&data), data.len() as u64)
+        .with_context(|| format!("Failed to read timeline file {:?}", path))?
+        as usize;
+    if complete < data.len() {
+        warn_partial_record(path, complete as u64);
+    }
+
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < complete {
+        let len_bytes = &data[pos..pos + 4];
+        let len = u32::from_le_bytes(len_bytes.try_into().expect("four bytes")) as usize;
+        let payload = &data[pos + 4..pos + 4 + len];
+        let entry: BinaryEntry = bincode::deserialize(payload).with_context(|| {
+            format!(
+                "Failed to parse timeline record at byte {} of {:?}",
+                pos, path
+            )
+        })?;
+        entries.push(entry.into());
+        pos += 4 + len;
+    }
+    Ok(entries)
+}
+
+fn read_jsonl_timeline(path: &Path) -> Result<Vec<TimelineEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open timeline file {:?}", path))?;
+    let reader = BufReader::new(file);
+
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read timeline line")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: TimelineEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Failed to parse timeline entry: {}", line))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// A line of the notes sidecar, matched to its entry by timestamp and key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TimelineNote {
+    timestamp: DateTime<Utc>,
+    key: String,
+    note: String,
+}
+
+impl TimelineEntry {
+    pub fn new(action: Action, key: String, value: Option<String>, prev: Option<String>) -> Self {
+        Self {
+            v: TIMELINE_VERSION,
+            timestamp: Utc::now(),
+            action,
+            key,
+            value,
+            prev,
+            note: None,
+            seq: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +288,12 @@ pub struct TimelineEntry {
 pub enum Action {
     Set,
     Unset,
+    /// The shell changed directory: `key` is `PWD`, `value` the new directory
+    /// and `prev` the one it left
+    Chdir,
+    /// The shell registered its full environment as the session baseline;
+    /// `key` is empty
+    Baseline,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,22 +304,82 @@ pub struct Snapshot {
     pub environment: Env,
     pub tags: Vec<String>,
     pub session_id: Option<uuid::Uuid>,
+    /// Saved by `snapshot --keys-only`: every value in `environment` is
+    /// blank, so only which variables were set is known.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keys_only: bool,
+    /// Set by `snapshot --expire`; past it, the daemon deletes the snapshot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Snapshot {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// `env` with every value blanked, the way keys-only snapshots store it.
+pub fn strip_values(env: &Env) -> Env {
+    env.keys().map(|key| (key.clone(), String::new())).collect()
 }
 
 #[derive(Clone)]
 pub struct Storage {
-    #[allow(dead_code)]
     config: Config,
+    salt: OnceLock<String>,
 }
 
 impl Storage {
     pub fn new() -> Result<Self> {
         let config = Config::load()?;
-        Ok(Self { config })
+        Ok(Self::with_config(config))
     }
 
     pub fn with_config(config: Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            salt: OnceLock::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// `value` as it may be recorded: a salted hash for variables matching
+    /// `filters.hash_patterns`, otherwise unchanged. Already hashed values
+    /// are kept as they are.
+    pub fn protect_value(&self, key: &str, value: String) -> Result<String> {
+        if !self.config.should_hash(key) || secrets::is_hashed(&value) {
+            return Ok(value);
+        }
+        Ok(secrets::hash_value(self.salt()?, &value))
+    }
+
+    /// [`Storage::protect_value`] applied to every variable in `env`.
+    pub fn protect_env(&self, env: Env) -> Result<Env> {
+        env.into_iter()
+            .map(|(key, value)| {
+                let value = self.protect_value(&key, value)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    /// `env` as the daemon records it: [`Storage::protect_env`], then values
+    /// cut to `core.max_value_bytes`. Used to compare raw snapshots against
+    /// the daemon's copy.
+    pub fn recorded_env(&self, env: Env) -> Result<Env> {
+        Ok(self.config.limit_env(self.protect_env(env)?))
+    }
+
+    fn salt(&self) -> Result<&str> {
+        if let Some(salt) = self.salt.get() {
+            return Ok(salt);
+        }
+        let salt = load_or_create_salt(&Config::salt_path())?;
+        Ok(self.salt.get_or_init(|| salt))
     }
 
     pub fn ensure_directories(&self) -> Result<()> {
@@ -60,98 +392,216 @@ impl Storage {
     }
 
     pub fn append_timeline(&self, session: &Session, entry: &TimelineEntry) -> Result<()> {
-        let timeline_path = session.timeline_path();
-        if let Some(parent) = timeline_path.parent() {
-            std::fs::create_dir_all(parent)
-                .with_context(|| format!("Failed to create timeline directory {:?}", parent))?;
+        let format = self.config.core.timeline_format;
+        let timeline_path = match format {
+            TimelineFormat::Jsonl => session.timeline_path(),
+            TimelineFormat::Bincode => session.binary_timeline_path(),
+        };
+        append_timeline_at(&timeline_path, format, entry)
+    }
+
+    pub fn read_timeline(&self, session: &Session) -> Result<Vec<TimelineEntry>> {
+        Self::read_timeline_file(&session.timeline_path())
+    }
+
+    /// The entries of `timeline.jsonl` at `timeline_path` and of the binary
+    /// timeline beside it, with their notes. A session that changed format
+    /// has both, merged in time order.
+    pub fn read_timeline_file(timeline_path: &Path) -> Result<Vec<TimelineEntry>> {
+        let mut entries = read_jsonl_timeline(timeline_path)?;
+        let binary_path = timeline_path.with_file_name(crate::session::BINARY_TIMELINE_FILE);
+        if binary_path.exists() {
+            let binary = read_binary_timeline(&binary_path)?;
+            let mixed = !entries.is_empty() && !binary.is_empty();
+            entries.extend(binary);
+            if mixed {
+                entries.sort_by_key(|e| (e.timestamp, e.seq));
+            }
         }
 
+        let notes_path = timeline_path.with_file_name(crate::session::NOTES_FILE);
+        if notes_path.exists() {
+            let content = std::fs::read_to_string(&notes_path)
+                .with_context(|| format!("Failed to read notes file {:?}", notes_path))?;
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                let note: TimelineNote = serde_json::from_str(line)
+                    .with_context(|| format!("Failed to parse note: {}", line))?;
+                if let Some(entry) = entries
+                    .iter_mut()
+                    .find(|e| e.timestamp == note.timestamp && e.key == note.key)
+                {
+                    entry.note = Some(note.note);
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Attach a note to the most recent timeline entry for `key`.
+    pub fn add_note(&self, session: &Session, key: &str, note: &str) -> Result<TimelineEntry> {
+        let entries = self.read_timeline(session)?;
+        let Some(mut entry) = entries.into_iter().rev().find(|e| e.key == key) else {
+            anyhow::bail!("No timeline entries for {}", key);
+        };
+
+        let notes_path = session.notes_path();
         let mut file = OpenOptions::new()
             .create(true)
             .append(true)
-            .open(&timeline_path)
-            .with_context(|| format!("Failed to open timeline file {:?}", timeline_path))?;
-
-        let line = serde_json::to_string(entry).context("Failed to serialize timeline entry")?;
+            .open(&notes_path)
+            .with_context(|| format!("Failed to open notes file {:?}", notes_path))?;
+        let line = serde_json::to_string(&TimelineNote {
+            timestamp: entry.timestamp,
+            key: entry.key.clone(),
+            note: note.to_string(),
+        })
+        .context("Failed to serialize note")?;
         writeln!(file, "{}", line)
-            .with_context(|| format!("Failed to write to timeline file {:?}", timeline_path))?;
-        Ok(())
+            .with_context(|| format!("Failed to write to notes file {:?}", notes_path))?;
+
+        entry.note = Some(note.to_string());
+        Ok(entry)
     }
 
-    pub fn read_timeline(&self, session: &Session) -> Result<Vec<TimelineEntry>> {
-        let timeline_path = session.timeline_path();
-        if !timeline_path.exists() {
-            return Ok(Vec::new());
+    /// Every session on disk, oldest first. Sessions that never captured
+    /// their env have no metadata and come back with placeholder details.
+    pub fn list_sessions(&self) -> Result<Vec<Session>> {
+        let mut sessions = Vec::new();
+        let sessions_dir = Config::sessions_dir();
+        if !sessions_dir.exists() {
+            return Ok(sessions);
         }
 
-        let file = std::fs::File::open(&timeline_path)
-            .with_context(|| format!("Failed to open timeline file {:?}", timeline_path))?;
-        let reader = BufReader::new(file);
-
-        let mut entries = Vec::new();
-        for line in reader.lines() {
-            let line = line.context("Failed to read timeline line")?;
-            if line.trim().is_empty() {
+        for entry in std::fs::read_dir(&sessions_dir)
+            .with_context(|| format!("Failed to read sessions directory {:?}", sessions_dir))?
+        {
+            let path = entry
+                .context("Failed to read session directory entry")?
+                .path();
+            if !path.is_dir() {
                 continue;
             }
-            let entry: TimelineEntry = serde_json::from_str(&line)
-                .with_context(|| format!("Failed to parse timeline entry: {}", line))?;
-            entries.push(entry);
+
+            if let Ok(metadata) = Session::load_metadata(&path.join("metadata.json")) {
+                sessions.push(metadata.session);
+            } else if let Some(id) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse().ok())
+            {
+                let mut session = Session::new(0, "unknown".to_string());
+                session.id = id;
+                session.started_at = DateTime::<Utc>::MIN_UTC;
+                sessions.push(session);
+            }
         }
 
-        Ok(entries)
+        sessions.sort_by_key(|s| s.started_at);
+        Ok(sessions)
     }
 
-    pub fn save_snapshot(&self, snapshot: &Snapshot, session: Option<&Session>) -> Result<()> {
-        let snapshot_path = if let Some(sess) = session {
-            let snapshots_dir = sess.snapshots_dir();
-            std::fs::create_dir_all(&snapshots_dir)
+    /// The session whose id is or starts with `id`, such as a short id.
+    pub fn resolve_session(&self, id: &str) -> Result<Session> {
+        pick_session(self.list_sessions()?, id)
+    }
+
+    /// Write `snapshot` into `session`'s snapshots, or the global ones, and
+    /// return the file it was written to. Compressed when
+    /// `core.compress_snapshots` is set.
+    pub fn save_snapshot(&self, snapshot: &Snapshot, session: Option<&Session>) -> Result<PathBuf> {
+        let compress = self.config.core.compress_snapshots;
+        self.save_snapshot_with(snapshot, session, compress)
+    }
+
+    /// [`Storage::save_snapshot`] with an explicit choice of gzip compression.
+    pub fn save_snapshot_with(
+        &self,
+        snapshot: &Snapshot,
+        session: Option<&Session>,
+        compress: bool,
+    ) -> Result<PathBuf> {
+        if let Some(sess) = session {
+            std::fs::create_dir_all(sess.snapshots_dir())
                 .context("Failed to create session snapshots directory")?;
-            snapshots_dir.join(format!("{}.json", snapshot.name))
-        } else {
-            Config::global_snapshots_dir().join(format!("{}.json", snapshot.name))
-        };
+        }
+        let path = snapshot_path(&snapshot.name, session, compress);
 
         let content =
             serde_json::to_string_pretty(snapshot).context("Failed to serialize snapshot")?;
-        std::fs::write(&snapshot_path, content)
-            .with_context(|| format!("Failed to write snapshot to {:?}", snapshot_path))?;
-        Ok(())
+        let bytes = if compress {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(content.as_bytes())
+                .and_then(|_| encoder.finish())
+                .context("Failed to compress snapshot")?
+        } else {
+            content.into_bytes()
+        };
+        write_atomic(&path, &bytes)
+            .with_context(|| format!("Failed to write snapshot to {:?}", path))?;
+
+        // Overwriting in the other format must not leave a stale copy behind
+        let other_path = snapshot_path(&snapshot.name, session, !compress);
+        if other_path.exists() {
+            std::fs::remove_file(&other_path)
+                .with_context(|| format!("Failed to remove old snapshot {:?}", other_path))?;
+        }
+
+        update_baseline_at(&Config::base_dir(), snapshot)?;
+        Ok(path)
+    }
+
+    /// Add `tag` to `snapshot`, stored in `session` or globally, rewriting
+    /// its file in the same format. Returns `false` if it already had the tag.
+    pub fn tag_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        session: Option<&Session>,
+        tag: &str,
+    ) -> Result<bool> {
+        if snapshot.tags.iter().any(|t| t == tag) {
+            return Ok(false);
+        }
+
+        let dir = session.map_or_else(Config::global_snapshots_dir, Session::snapshots_dir);
+        let path = find_snapshot_file(&dir, &snapshot.name)
+            .with_context(|| format!("Snapshot '{}' not found", snapshot.name))?;
+        let compressed = path.extension().and_then(|s| s.to_str()) == Some("gz");
+
+        let mut tagged = snapshot.clone();
+        tagged.tags.push(tag.to_string());
+        self.save_snapshot_with(&tagged, session, compressed)?;
+        Ok(true)
     }
 
     pub fn load_snapshot(&self, name: &str, session: Option<&Session>) -> Result<Snapshot> {
         // Try session snapshot first, then global
-        let snapshot_path = if let Some(sess) = session {
-            sess.snapshots_dir().join(format!("{}.json", name))
+        let snapshots_dir = if let Some(sess) = session {
+            sess.snapshots_dir()
         } else {
-            Config::global_snapshots_dir().join(format!("{}.json", name))
+            Config::global_snapshots_dir()
         };
 
-        if !snapshot_path.exists() {
+        let Some(snapshot_path) = find_snapshot_file(&snapshots_dir, name) else {
             // Try the other location
-            let alt_path = if session.is_some() {
-                Config::global_snapshots_dir().join(format!("{}.json", name))
-            } else {
+            if session.is_none() {
                 // Search in all session directories
                 return self.find_snapshot_in_sessions(name);
-            };
+            }
 
-            if alt_path.exists() {
+            if let Some(alt_path) = find_snapshot_file(&Config::global_snapshots_dir(), name) {
                 return self.load_snapshot_from_path(&alt_path);
             }
 
             anyhow::bail!("Snapshot '{}' not found", name);
-        }
+        };
 
         self.load_snapshot_from_path(&snapshot_path)
     }
 
-    fn load_snapshot_from_path(&self, path: &PathBuf) -> Result<Snapshot> {
-        let content = std::fs::read_to_string(path)
-            .with_context(|| format!("Failed to read snapshot from {:?}", path))?;
-        let snapshot: Snapshot = serde_json::from_str(&content)
-            .with_context(|| format!("Failed to parse snapshot from {:?}", path))?;
-        Ok(snapshot)
+    fn load_snapshot_from_path(&self, path: &Path) -> Result<Snapshot> {
+        read_snapshot_file(path)
     }
 
     fn find_snapshot_in_sessions(&self, name: &str) -> Result<Snapshot> {
@@ -166,12 +616,8 @@ impl Storage {
             let entry = entry.context("Failed to read session directory entry")?;
             let path = entry.path();
             if path.is_dir() {
-                let snapshots_dir = path.join("snapshots");
-                if snapshots_dir.exists() {
-                    let snapshot_path = snapshots_dir.join(format!("{}.json", name));
-                    if snapshot_path.exists() {
-                        return self.load_snapshot_from_path(&snapshot_path);
-                    }
+                if let Some(snapshot_path) = find_snapshot_file(&path.join("snapshots"), name) {
+                    return self.load_snapshot_from_path(&snapshot_path);
                 }
             }
         }
@@ -191,7 +637,7 @@ impl Storage {
                 {
                     let entry = entry.context("Failed to read snapshot entry")?;
                     let path = entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                    if snapshot_file_name(&path).is_some() {
                         if let Ok(snapshot) = self.load_snapshot_from_path(&path) {
                             snapshots.push(snapshot);
                         }
@@ -208,7 +654,7 @@ impl Storage {
             {
                 let entry = entry.context("Failed to read snapshot entry")?;
                 let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                if snapshot_file_name(&path).is_some() {
                     if let Ok(snapshot) = self.load_snapshot_from_path(&path) {
                         snapshots.push(snapshot);
                     }
@@ -217,15 +663,99 @@ impl Storage {
         }
 
         // Sort by created_at
-        snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
         Ok(snapshots)
     }
 
+    /// The snapshot `status` compares against: the cached most recent
+    /// snapshot when it is visible from `session`, otherwise the newest one
+    /// found by a full scan.
+    pub fn baseline(&self, session: Option<&Session>) -> Result<Option<Snapshot>> {
+        let baseline_path = Config::baseline_path();
+        if let Ok(cached) = self.load_snapshot_from_path(&baseline_path) {
+            let visible = match cached.session_id {
+                None => true,
+                Some(id) => session.is_some_and(|s| s.id == id),
+            };
+            if visible {
+                return Ok(Some(cached));
+            }
+        }
+
+        Ok(self.list_snapshots(session)?.into_iter().next())
+    }
+
+    /// Move snapshot `name` from one scope to another (`None` is global),
+    /// returning the new file. Fails if the target scope already has a
+    /// snapshot of that name, unless `overwrite` is set.
+    pub fn move_snapshot(
+        &self,
+        name: &str,
+        from: Option<&Session>,
+        to: Option<&Session>,
+        overwrite: bool,
+    ) -> Result<PathBuf> {
+        let from_dir = from.map_or_else(Config::global_snapshots_dir, Session::snapshots_dir);
+        let to_dir = to.map_or_else(Config::global_snapshots_dir, Session::snapshots_dir);
+        let scope = |session: Option<&Session>| match session {
+            Some(sess) => format!("session {}", sess.id),
+            None => "the global scope".to_string(),
+        };
+
+        let source = find_snapshot_file(&from_dir, name)
+            .with_context(|| format!("Snapshot '{}' not found in {}", name, scope(from)))?;
+        if !overwrite && find_snapshot_file(&to_dir, name).is_some() {
+            anyhow::bail!(
+                "Snapshot '{}' already exists in {}; pass --force to overwrite it",
+                name,
+                scope(to)
+            );
+        }
+
+        let mut snapshot = self.load_snapshot_from_path(&source)?;
+        snapshot.session_id = to.map(|s| s.id);
+
+        // The cached baseline may still carry the old scope
+        self.invalidate_baseline(name);
+        let path = self.save_snapshot(&snapshot, to)?;
+        std::fs::remove_file(&source)
+            .with_context(|| format!("Failed to remove moved snapshot {:?}", source))?;
+        Ok(path)
+    }
+
+    fn invalidate_baseline(&self, name: &str) {
+        let baseline_path = Config::baseline_path();
+        if let Ok(cached) = self.load_snapshot_from_path(&baseline_path) {
+            if cached.name == name {
+                let _ = std::fs::remove_file(&baseline_path);
+            }
+        }
+    }
+
     pub fn delete_snapshot(&self, name: &str, session: Option<&Session>) -> Result<()> {
+        self.remove_snapshot_file(name, session)?;
+        self.invalidate_baseline(name);
+        Ok(())
+    }
+
+    /// Delete `name` from `session`'s snapshots, or the global ones, without
+    /// looking anywhere else.
+    pub fn delete_snapshot_in(&self, name: &str, session: Option<&Session>) -> Result<()> {
+        let dir = session.map_or_else(Config::global_snapshots_dir, Session::snapshots_dir);
+        let path = find_snapshot_file(&dir, name).with_context(|| match session {
+            Some(sess) => format!("Snapshot '{}' not found in session {}", name, sess.id),
+            None => format!("Snapshot '{}' not found in the global scope", name),
+        })?;
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to delete snapshot {:?}", path))?;
+        self.invalidate_baseline(name);
+        Ok(())
+    }
+
+    fn remove_snapshot_file(&self, name: &str, session: Option<&Session>) -> Result<()> {
         // Try session snapshot first
         if let Some(sess) = session {
-            let snapshot_path = sess.snapshots_dir().join(format!("{}.json", name));
-            if snapshot_path.exists() {
+            if let Some(snapshot_path) = find_snapshot_file(&sess.snapshots_dir(), name) {
                 std::fs::remove_file(&snapshot_path)
                     .with_context(|| format!("Failed to delete snapshot {:?}", snapshot_path))?;
                 return Ok(());
@@ -233,8 +763,7 @@ impl Storage {
         }
 
         // Try global snapshot
-        let snapshot_path = Config::global_snapshots_dir().join(format!("{}.json", name));
-        if snapshot_path.exists() {
+        if let Some(snapshot_path) = find_snapshot_file(&Config::global_snapshots_dir(), name) {
             std::fs::remove_file(&snapshot_path)
                 .with_context(|| format!("Failed to delete snapshot {:?}", snapshot_path))?;
             return Ok(());
@@ -249,8 +778,7 @@ impl Storage {
                 let entry = entry.context("Failed to read session directory entry")?;
                 let path = entry.path();
                 if path.is_dir() {
-                    let snapshot_path = path.join("snapshots").join(format!("{}.json", name));
-                    if snapshot_path.exists() {
+                    if let Some(snapshot_path) = find_snapshot_file(&path.join("snapshots"), name) {
                         std::fs::remove_file(&snapshot_path).with_context(|| {
                             format!("Failed to delete snapshot {:?}", snapshot_path)
                         })?;
@@ -266,6 +794,759 @@ impl Storage {
     pub fn get_current_env() -> Env {
         std::env::vars().collect()
     }
+
+    /// Serialize config, global snapshots and every session directory.
+    pub fn export_all(&self) -> Result<StoreExport> {
+        self.export_all_with(&mut Failures::new(false))
+    }
+
+    /// [`Storage::export_all`], leaving out sessions and snapshots that fail
+    /// to read when `failures` keeps going.
+    pub fn export_all_with(&self, failures: &mut Failures) -> Result<StoreExport> {
+        let mut sessions = Vec::new();
+        let sessions_dir = Config::sessions_dir();
+        if sessions_dir.exists() {
+            for entry in std::fs::read_dir(&sessions_dir)
+                .with_context(|| format!("Failed to read sessions directory {:?}", sessions_dir))?
+            {
+                let path = entry
+                    .context("Failed to read session directory entry")?
+                    .path();
+                let Some(id) = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| n.parse().ok())
+                else {
+                    continue;
+                };
+                if !path.is_dir() {
+                    continue;
+                }
+
+                let timeline = Self::read_timeline_file(&path.join("timeline.jsonl"))
+                    .with_context(|| format!("Session {}", id));
+                let Some(timeline) = failures.check(timeline)? else {
+                    continue;
+                };
+                sessions.push(SessionExport {
+                    id,
+                    metadata: Session::load_metadata(&path.join("metadata.json")).ok(),
+                    timeline,
+                    snapshots: self.snapshots_in(&path.join("snapshots"), failures)?,
+                });
+            }
+        }
+        sessions.sort_by_key(|s| s.id);
+
+        Ok(StoreExport {
+            version: STORE_EXPORT_VERSION,
+            exported_at: Utc::now(),
+            config: self.config.clone(),
+            snapshots: self.snapshots_in(&Config::global_snapshots_dir(), failures)?,
+            sessions,
+        })
+    }
+
+    /// Write an [`StoreExport`] into this store and replace the config.
+    /// Snapshots and sessions that already exist are left alone.
+    pub fn import_all(&self, export: &StoreExport) -> Result<StoreImportSummary> {
+        if export.version > STORE_EXPORT_VERSION {
+            anyhow::bail!(
+                "Store export version {} is newer than supported version {}",
+                export.version,
+                STORE_EXPORT_VERSION
+            );
+        }
+
+        self.ensure_directories()?;
+        export.config.save()?;
+
+        let mut summary = StoreImportSummary::default();
+        for snapshot in &export.snapshots {
+            if find_snapshot_file(&Config::global_snapshots_dir(), &snapshot.name).is_some() {
+                summary.skipped += 1;
+                continue;
+            }
+            self.save_snapshot(snapshot, None)?;
+            summary.snapshots += 1;
+        }
+
+        for exported in &export.sessions {
+            let session = match exported.metadata {
+                Some(ref metadata) => metadata.session.clone(),
+                None => {
+                    let mut session = Session::new(0, "unknown".to_string());
+                    session.id = exported.id;
+                    session
+                }
+            };
+            if session.session_dir().exists() {
+                summary.skipped += 1;
+                continue;
+            }
+
+            std::fs::create_dir_all(session.session_dir())
+                .with_context(|| format!("Failed to create session {}", session.id))?;
+            if let Some(ref metadata) = exported.metadata {
+                session.save_metadata(&metadata.current_env)?;
+            }
+            for entry in &exported.timeline {
+                self.append_timeline(&session, entry)?;
+            }
+            for snapshot in &exported.snapshots {
+                self.save_snapshot(snapshot, Some(&session))?;
+            }
+            summary.sessions += 1;
+        }
+
+        Ok(summary)
+    }
+
+    /// Save a [`SnapshotBundle`]'s snapshots as global snapshots. Names that
+    /// already exist globally are left alone.
+    pub fn import_bundle(&self, bundle: &SnapshotBundle) -> Result<StoreImportSummary> {
+        if bundle.version > SNAPSHOT_BUNDLE_VERSION {
+            anyhow::bail!(
+                "Snapshot bundle version {} is newer than supported version {}",
+                bundle.version,
+                SNAPSHOT_BUNDLE_VERSION
+            );
+        }
+
+        self.ensure_directories()?;
+        let mut summary = StoreImportSummary::default();
+        for snapshot in &bundle.snapshots {
+            if find_snapshot_file(&Config::global_snapshots_dir(), &snapshot.name).is_some() {
+                summary.skipped += 1;
+                continue;
+            }
+            self.save_snapshot(snapshot, None)?;
+            summary.snapshots += 1;
+        }
+        Ok(summary)
+    }
+
+    fn snapshots_in(&self, dir: &Path, failures: &mut Failures) -> Result<Vec<Snapshot>> {
+        let mut snapshots = Vec::new();
+        if !dir.exists() {
+            return Ok(snapshots);
+        }
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))?
+        {
+            let path = entry.context("Failed to read snapshot entry")?.path();
+            if snapshot_file_name(&path).is_some() {
+                if let Some(snapshot) = failures.check(self.load_snapshot_from_path(&path))? {
+                    snapshots.push(snapshot);
+                }
+            }
+        }
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(snapshots)
+    }
+
+    /// Move sessions last written before `cutoff` and global snapshots
+    /// created before it into a zstd-compressed tarball at `output`.
+    pub fn archive(&self, cutoff: DateTime<Utc>, output: &Path) -> Result<ArchiveSummary> {
+        let summary = archive_store(&Config::base_dir(), cutoff, output)?;
+        for name in &summary.snapshot_names {
+            self.invalidate_baseline(name);
+        }
+        Ok(summary)
+    }
+
+    /// Delete every snapshot, global or in any session, whose expiry has
+    /// passed by `now`, returning their names.
+    pub fn delete_expired(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        let names = delete_expired_at(&Config::base_dir(), now)?;
+        for name in &names {
+            self.invalidate_baseline(name);
+        }
+        Ok(names)
+    }
+
+    /// How much disk space the store takes, by walking its directories.
+    pub fn store_usage(&self) -> Result<StoreUsage> {
+        store_usage_at(&Config::base_dir())
+    }
+
+    /// Unpack an archive made by [`Storage::archive`] back into the store.
+    /// Files that already exist in the store are left untouched.
+    pub fn import_archive(&self, archive: &Path) -> Result<ArchiveSummary> {
+        self.ensure_directories()?;
+        let summary = unpack_archive(&Config::base_dir(), archive)?;
+        for name in &summary.snapshot_names {
+            let Some(path) = find_snapshot_file(&Config::global_snapshots_dir(), name) else {
+                continue;
+            };
+            if let Ok(snapshot) = self.load_snapshot_from_path(&path) {
+                update_baseline_at(&Config::base_dir(), &snapshot)?;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Errors a bulk operation set aside to carry on with the remaining items,
+/// for `--keep-going`. Without keep-going the first error stops it.
+#[derive(Debug, Default)]
+pub struct Failures {
+    keep_going: bool,
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl Failures {
+    pub fn new(keep_going: bool) -> Self {
+        Self {
+            keep_going,
+            errors: Vec::new(),
+        }
+    }
+
+    /// `result`'s value; on error, `None` after recording it when keeping
+    /// going, and the error itself otherwise.
+    pub fn check<T>(&mut self, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.keep_going => {
+                self.errors.push(e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Format version of [`StoreExport`] files.
+pub const STORE_EXPORT_VERSION: u32 = 1;
+
+/// The whole store as one portable document, for `export --all`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreExport {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub config: Config,
+    /// Global snapshots
+    pub snapshots: Vec<Snapshot>,
+    pub sessions: Vec<SessionExport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionExport {
+    pub id: uuid::Uuid,
+    /// Absent for sessions that never captured their env
+    pub metadata: Option<SessionMetadata>,
+    pub timeline: Vec<TimelineEntry>,
+    pub snapshots: Vec<Snapshot>,
+}
+
+/// Format version of [`SnapshotBundle`] files.
+pub const SNAPSHOT_BUNDLE_VERSION: u32 = 1;
+
+/// Marks a JSON document as a [`SnapshotBundle`], so store exports with
+/// similar fields are not mistaken for one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BundleKind {
+    #[serde(rename = "snapshot-bundle")]
+    SnapshotBundle,
+}
+
+/// Snapshots with their metadata as one portable document, for
+/// `export --format bundle`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotBundle {
+    pub kind: BundleKind,
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl SnapshotBundle {
+    /// Bundle `snapshots` for another host. Only variables `config` tracks
+    /// are kept, so the secrets and host-specific variables (`PATH`, `HOME`,
+    /// ...) that plain snapshots store stay behind; session links are dropped.
+    pub fn new(snapshots: Vec<Snapshot>, config: &Config) -> Self {
+        let snapshots = snapshots
+            .into_iter()
+            .map(|mut snapshot| {
+                snapshot
+                    .environment
+                    .retain(|key, _| config.should_track(key));
+                snapshot.session_id = None;
+                snapshot
+            })
+            .collect();
+        Self {
+            kind: BundleKind::SnapshotBundle,
+            version: SNAPSHOT_BUNDLE_VERSION,
+            exported_at: Utc::now(),
+            snapshots,
+        }
+    }
+}
+
+/// Counts of what [`Storage::import_all`] and [`Storage::import_bundle`]
+/// wrote; existing items are skipped.
+#[derive(Debug, Default)]
+pub struct StoreImportSummary {
+    pub snapshots: usize,
+    pub sessions: usize,
+    pub skipped: usize,
+}
+
+/// What an archive run moved out of (or back into) the store.
+#[derive(Debug, Default)]
+pub struct ArchiveSummary {
+    pub sessions: usize,
+    pub snapshot_names: Vec<String>,
+}
+
+/// Disk space the store takes, from [`Storage::store_usage`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StoreUsage {
+    /// Session timelines, notes and metadata
+    pub sessions_bytes: u64,
+    /// Global and session snapshot directories
+    pub snapshots_bytes: u64,
+    /// Everything under the store directory, config and baseline included
+    pub total_bytes: u64,
+    pub session_count: usize,
+    /// Global and session snapshots
+    pub snapshot_count: usize,
+}
+
+const SESSIONS_PREFIX: &str = "sessions";
+const GLOBAL_SNAPSHOTS_PREFIX: &str = "global/snapshots";
+const BASELINE_FILE: &str = "baseline.json";
+
+fn archive_store(base: &Path, cutoff: DateTime<Utc>, output: &Path) -> Result<ArchiveSummary> {
+    if output.exists() {
+        anyhow::bail!("Archive {:?} already exists", output);
+    }
+
+    let mut summary = ArchiveSummary::default();
+    let mut session_dirs = Vec::new();
+    let mut snapshot_files = Vec::new();
+
+    let sessions_dir = base.join(SESSIONS_PREFIX);
+    if sessions_dir.exists() {
+        for entry in std::fs::read_dir(&sessions_dir)
+            .with_context(|| format!("Failed to read sessions directory {:?}", sessions_dir))?
+        {
+            let path = entry
+                .context("Failed to read session directory entry")?
+                .path();
+            if path.is_dir() && last_modified(&path)? < cutoff {
+                session_dirs.push(path);
+            }
+        }
+    }
+
+    let snapshots_dir = base.join(GLOBAL_SNAPSHOTS_PREFIX);
+    if snapshots_dir.exists() {
+        for entry in std::fs::read_dir(&snapshots_dir)
+            .with_context(|| format!("Failed to read snapshots directory {:?}", snapshots_dir))?
+        {
+            let path = entry.context("Failed to read snapshot entry")?.path();
+            if snapshot_file_name(&path).is_none() {
+                continue;
+            }
+            let Ok(snapshot) = read_snapshot_file(&path) else {
+                continue;
+            };
+            if snapshot.created_at < cutoff {
+                snapshot_files.push(path);
+                summary.snapshot_names.push(snapshot.name);
+            }
+        }
+    }
+
+    summary.sessions = session_dirs.len();
+    if session_dirs.is_empty() && snapshot_files.is_empty() {
+        return Ok(summary);
+    }
+
+    let file = std::fs::File::create(output)
+        .with_context(|| format!("Failed to create archive {:?}", output))?;
+    let encoder = zstd::Encoder::new(file, 0).context("Failed to start zstd stream")?;
+    let mut builder = tar::Builder::new(encoder);
+    for dir in &session_dirs {
+        let name = Path::new(SESSIONS_PREFIX).join(dir.file_name().unwrap_or_default());
+        builder
+            .append_dir_all(&name, dir)
+            .with_context(|| format!("Failed to archive session {:?}", dir))?;
+    }
+    for path in &snapshot_files {
+        let name = Path::new(GLOBAL_SNAPSHOTS_PREFIX).join(path.file_name().unwrap_or_default());
+        builder
+            .append_path_with_name(path, &name)
+            .with_context(|| format!("Failed to archive snapshot {:?}", path))?;
+    }
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|file| file.sync_all())
+        .with_context(|| format!("Failed to write archive {:?}", output))?;
+
+    // Only remove from the live store once the archive is safely on disk
+    for dir in &session_dirs {
+        std::fs::remove_dir_all(dir)
+            .with_context(|| format!("Failed to remove archived session {:?}", dir))?;
+    }
+    for path in &snapshot_files {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove archived snapshot {:?}", path))?;
+    }
+
+    Ok(summary)
+}
+
+fn unpack_archive(base: &Path, archive: &Path) -> Result<ArchiveSummary> {
+    let file = std::fs::File::open(archive)
+        .with_context(|| format!("Failed to open archive {:?}", archive))?;
+    let decoder = zstd::Decoder::new(file).context("Failed to start zstd stream")?;
+    let mut tarball = tar::Archive::new(decoder);
+
+    let mut summary = ArchiveSummary::default();
+    let mut sessions = std::collections::BTreeSet::new();
+    for entry in tarball
+        .entries()
+        .with_context(|| format!("Failed to read archive {:?}", archive))?
+    {
+        let mut entry = entry.context("Failed to read archive entry")?;
+        let path = entry
+            .path()
+            .context("Invalid path in archive")?
+            .into_owned();
+
+        let in_store =
+            path.starts_with(SESSIONS_PREFIX) || path.starts_with(GLOBAL_SNAPSHOTS_PREFIX);
+        if !in_store || base.join(&path).exists() {
+            continue;
+        }
+
+        // unpack_in refuses paths that would escape `base`
+        if !entry
+            .unpack_in(base)
+            .with_context(|| format!("Failed to unpack {:?}", path))?
+        {
+            continue;
+        }
+
+        if let Ok(rest) = path.strip_prefix(SESSIONS_PREFIX) {
+            if let Some(id) = rest.components().next() {
+                sessions.insert(id.as_os_str().to_owned());
+            }
+        } else if let Some(name) = snapshot_file_name(&path) {
+            summary.snapshot_names.push(name.to_string());
+        }
+    }
+
+    summary.sessions = sessions.len();
+    Ok(summary)
+}
+
+/// The one session in `sessions` whose id is `id` or starts with it.
+fn pick_session(sessions: Vec<Session>, id: &str) -> Result<Session> {
+    if id.is_empty() {
+        anyhow::bail!("Session id must not be empty");
+    }
+
+    let mut matches: Vec<Session> = sessions
+        .into_iter()
+        .filter(|s| s.id.to_string().starts_with(id))
+        .collect();
+    match matches.len() {
+        0 => anyhow::bail!("Session '{}' not found", id),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            let ids: Vec<String> = matches.iter().map(|s| s.id.to_string()).collect();
+            anyhow::bail!(
+                "Session '{}' is ambiguous; it matches {}",
+                id,
+                ids.join(", ")
+            )
+        }
+    }
+}
+
+/// Where a snapshot named `name` is stored for `session`, or globally.
+fn snapshot_path(name: &str, session: Option<&Session>, compressed: bool) -> PathBuf {
+    let dir = match session {
+        Some(sess) => sess.snapshots_dir(),
+        None => Config::global_snapshots_dir(),
+    };
+    let extension = if compressed { "json.gz" } else { "json" };
+    dir.join(format!("{}.{}", name, extension))
+}
+
+/// The snapshot name of a `.json` or `.json.gz` file, or `None` for other files.
+fn snapshot_file_name(path: &Path) -> Option<&str> {
+    let file_name = path.file_name()?.to_str()?;
+    file_name
+        .strip_suffix(".json.gz")
+        .or_else(|| file_name.strip_suffix(".json"))
+}
+
+/// The file holding snapshot `name` in `dir`, plain or compressed.
+fn find_snapshot_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    ["json", "json.gz"]
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", name, extension)))
+        .find(|path| path.exists())
+}
+
+/// Read a snapshot, decompressing `.gz` files.
+fn read_snapshot_file(path: &Path) -> Result<Snapshot> {
+    let mut content = String::new();
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to read snapshot from {:?}", path))?;
+    if path.extension().and_then(|s| s.to_str()) == Some("gz") {
+        GzDecoder::new(file).read_to_string(&mut content)
+    } else {
+        BufReader::new(file).read_to_string(&mut content)
+    }
+    .with_context(|| format!("Failed to read snapshot from {:?}", path))?;
+
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse snapshot from {:?}", path))
+}
+
+/// Read the store's salt, generating it if this is the first use. A
+/// concurrent creator wins and everyone reads its salt.
+fn load_or_create_salt(path: &Path) -> Result<String> {
+    if let Ok(salt) = std::fs::read_to_string(path) {
+        if !salt.trim().is_empty() {
+            return Ok(salt.trim().to_string());
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    let salt = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => {
+            file.write_all(salt.as_bytes())
+                .with_context(|| format!("Failed to write salt to {:?}", path))?;
+            Ok(salt)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            let salt = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read salt from {:?}", path))?;
+            Ok(salt.trim().to_string())
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to create salt {:?}", path)),
+    }
+}
+
+/// Replace the baseline cached in the store at `base` with `snapshot` unless
+/// it already holds a newer one. Concurrent saves are serialized by a lock
+/// file next to the cache. A missing or unreadable cache is rebuilt from the
+/// newest snapshot in the store, so it can't fall back to an older one.
+fn update_baseline_at(base: &Path, snapshot: &Snapshot) -> Result<()> {
+    let baseline_path = &base.join(BASELINE_FILE);
+    let lock_path = baseline_path.with_extension("json.lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file {:?}", lock_path))?;
+    lock.lock()
+        .with_context(|| format!("Failed to lock {:?}", lock_path))?;
+
+    let cached: Option<Snapshot> = std::fs::read_to_string(baseline_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    let snapshot = match cached {
+        Some(cached) if cached.created_at > snapshot.created_at => return Ok(()),
+        Some(_) => snapshot,
+        None => &newest_snapshot_at(base)?
+            .filter(|newest| newest.created_at > snapshot.created_at)
+            .unwrap_or_else(|| snapshot.clone()),
+    };
+
+    let content = serde_json::to_string_pretty(snapshot).context("Failed to serialize baseline")?;
+    write_atomic(baseline_path, &content)
+        .with_context(|| format!("Failed to write baseline to {:?}", baseline_path))
+}
+
+/// Write through a temporary file and rename it into place, so readers see
+/// either the old or the new content and never a partial write.
+fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp_path);
+    })
+}
+
+/// The snapshot directories of the store at `base`: the global one and one
+/// per session, whether or not they exist.
+fn snapshot_dirs_at(base: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![base.join(GLOBAL_SNAPSHOTS_PREFIX)];
+    let sessions_dir = base.join(SESSIONS_PREFIX);
+    if sessions_dir.is_dir() {
+        for entry in std::fs::read_dir(&sessions_dir)
+            .with_context(|| format!("Failed to read {:?}", sessions_dir))?
+        {
+            dirs.push(
+                entry
+                    .context("Failed to read directory entry")?
+                    .path()
+                    .join("snapshots"),
+            );
+        }
+    }
+    Ok(dirs)
+}
+
+/// The most recently created readable snapshot anywhere in the store at
+/// `base`.
+fn newest_snapshot_at(base: &Path) -> Result<Option<Snapshot>> {
+    let mut newest: Option<Snapshot> = None;
+    for dir in snapshot_dirs_at(base)?.iter().filter(|dir| dir.is_dir()) {
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))?
+        {
+            let path = entry.context("Failed to read snapshot entry")?.path();
+            if snapshot_file_name(&path).is_none() {
+                continue;
+            }
+            let Ok(snapshot) = read_snapshot_file(&path) else {
+                continue;
+            };
+            if newest
+                .as_ref()
+                .is_none_or(|newest| snapshot.created_at > newest.created_at)
+            {
+                newest = Some(snapshot);
+            }
+        }
+    }
+    Ok(newest)
+}
+
+fn delete_expired_at(base: &Path, now: DateTime<Utc>) -> Result<Vec<String>> {
+    let dirs = snapshot_dirs_at(base)?;
+    let mut deleted = Vec::new();
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read directory {:?}", dir))?
+        {
+            let path = entry.context("Failed to read snapshot entry")?.path();
+            if snapshot_file_name(&path).is_none() {
+                continue;
+            }
+            // Unreadable snapshots are left for the commands that load them to report
+            let Ok(snapshot) = read_snapshot_file(&path) else {
+                continue;
+            };
+            if snapshot.is_expired(now) {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to delete expired snapshot {:?}", path))?;
+                deleted.push(snapshot.name);
+            }
+        }
+    }
+    deleted.sort();
+    Ok(deleted)
+}
+
+fn store_usage_at(base: &Path) -> Result<StoreUsage> {
+    let mut usage = StoreUsage {
+        total_bytes: dir_size(base)?,
+        ..StoreUsage::default()
+    };
+    add_snapshots_usage(&mut usage, &base.join(GLOBAL_SNAPSHOTS_PREFIX))?;
+
+    let sessions_dir = base.join(SESSIONS_PREFIX);
+    if !sessions_dir.is_dir() {
+        return Ok(usage);
+    }
+    for entry in std::fs::read_dir(&sessions_dir)
+        .with_context(|| format!("Failed to read {:?}", sessions_dir))?
+    {
+        let path = entry.context("Failed to read directory entry")?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        usage.session_count += 1;
+        let snapshots_bytes = add_snapshots_usage(&mut usage, &path.join("snapshots"))?;
+        // Files can change between the two walks, so don't underflow
+        usage.sessions_bytes += dir_size(&path)?.saturating_sub(snapshots_bytes);
+    }
+    Ok(usage)
+}
+
+/// Count the snapshots in `dir` into `usage`, returning the directory's size.
+fn add_snapshots_usage(usage: &mut StoreUsage, dir: &Path) -> Result<u64> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let path = entry.context("Failed to read directory entry")?.path();
+        if snapshot_file_name(&path).is_some() {
+            usage.snapshot_count += 1;
+        }
+    }
+    let bytes = dir_size(dir)?;
+    usage.snapshots_bytes += bytes;
+    Ok(bytes)
+}
+
+/// Total size of the files under `path`, without following symlinks.
+fn dir_size(path: &Path) -> Result<u64> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("Failed to stat {:?}", path)),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path).with_context(|| format!("Failed to read {:?}", path))? {
+        total += dir_size(&entry.context("Failed to read directory entry")?.path())?;
+    }
+    Ok(total)
+}
+
+/// Most recent modification time of anything in `dir`.
+fn last_modified(dir: &Path) -> Result<DateTime<Utc>> {
+    let mut latest = std::fs::metadata(dir)
+        .and_then(|m| m.modified())
+        .with_context(|| format!("Failed to stat {:?}", dir))?;
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let path = entry.context("Failed to read directory entry")?.path();
+        let modified = if path.is_dir() {
+            last_modified(&path)?.into()
+        } else {
+            std::fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .with_context(|| format!("Failed to stat {:?}", path))?
+        };
+        latest = latest.max(modified);
+    }
+    Ok(latest.into())
 }
 
 #[cfg(test)]
@@ -276,15 +1557,588 @@ mod tests {
     #[test]
     fn test_save_load_snapshot() {
         let temp_dir = TempDir::new().unwrap();
-        let config_path = temp_dir.path().join("config.toml");
-        let base_dir = temp_dir.path().join(".envhist");
+        let snapshot_path = temp_dir.path().join("dev.json");
+        let storage = Storage::with_config(Config::default());
+
+        let mut environment = Env::new();
+        environment.insert("MY_VAR".to_string(), "value".to_string());
+        let snapshot = Snapshot {
+            name: "dev".to_string(),
+            created_at: Utc::now(),
+            description: None,
+            environment,
+            tags: Vec::new(),
+            session_id: None,
+            keys_only: false,
+            expires_at: None,
+        };
 
-        // Create a minimal config
+        std::fs::write(&snapshot_path, serde_json::to_string(&snapshot).unwrap()).unwrap();
+        let loaded = storage.load_snapshot_from_path(&snapshot_path).unwrap();
+
+        assert_eq!(loaded.name, "dev");
+        assert_eq!(loaded.environment.get("MY_VAR"), Some(&"value".to_string()));
+        assert!(!loaded.keys_only);
+    }
+
+    #[test]
+    fn test_protect_env_matches_recorded() {
+        let mut config = Config::default();
+        config.filters.hash_patterns.push("DB_.*".to_string());
+        let storage = Storage::with_config(config);
+        storage.salt.set("salt".to_string()).unwrap();
+
+        let snapshot_env = Env::from([
+            ("DB_PASS".to_string(), "hunter2".to_string()),
+            ("EDITOR".to_string(), "vim".to_string()),
+        ]);
+        // What the daemon records for the same env
+        let recorded = storage.protect_env(snapshot_env.clone()).unwrap();
+        assert_ne!(recorded["DB_PASS"], "hunter2");
+        assert_eq!(recorded["EDITOR"], "vim");
+
+        // Hashing the snapshot side makes the two compare equal, and hashing
+        // again leaves recorded values alone
+        let protected = storage.protect_env(snapshot_env).unwrap();
+        assert_eq!(protected, recorded);
+        assert_eq!(storage.protect_env(recorded.clone()).unwrap(), recorded);
+    }
+
+    #[test]
+    fn test_recorded_env_limits_values() {
+        let mut config = Config::default();
+        config.core.max_value_bytes = 8;
+        let storage = Storage::with_config(config);
+
+        let long = "x".repeat(20);
+        let snapshot_env = Env::from([
+            ("LONG".to_string(), long.clone()),
+            ("SHORT".to_string(), "vim".to_string()),
+        ]);
+        let recorded = storage.recorded_env(snapshot_env).unwrap();
+        // The daemon's copy of the same value
+        assert_eq!(recorded["LONG"], storage.config().limit_value(long));
+        assert!(Config::is_truncated(&recorded["LONG"]));
+        assert_eq!(recorded["SHORT"], "vim");
+    }
+
+    #[test]
+    fn test_snapshot_bundle() {
         let config = Config::default();
-        std::fs::create_dir_all(&base_dir).unwrap();
-        std::fs::create_dir_all(base_dir.join("global").join("snapshots")).unwrap();
+        let snapshot = Snapshot {
+            name: "dev".to_string(),
+            created_at: Utc::now(),
+            description: Some("laptop".to_string()),
+            environment: Env::from([
+                ("MY_VAR".to_string(), "value".to_string()),
+                ("API_TOKEN".to_string(), "hunter2".to_string()),
+            ]),
+            tags: vec!["work".to_string()],
+            session_id: Some(uuid::Uuid::new_v4()),
+            keys_only: false,
+            expires_at: None,
+        };
+
+        let bundle = SnapshotBundle::new(vec![snapshot], &config);
+        let json = serde_json::to_string(&bundle).unwrap();
+        let parsed: SnapshotBundle = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.version, SNAPSHOT_BUNDLE_VERSION);
+        let dev = &parsed.snapshots[0];
+        assert_eq!(dev.description.as_deref(), Some("laptop"));
+        assert_eq!(dev.tags, vec!["work"]);
+        assert!(dev.session_id.is_none());
+        // Filtered variables are left out, as when snapshotting
+        assert!(dev.environment.contains_key("MY_VAR"));
+        assert!(!dev.environment.contains_key("API_TOKEN"));
+
+        let newer = SnapshotBundle {
+            version: SNAPSHOT_BUNDLE_VERSION + 1,
+            ..parsed
+        };
+        let storage = Storage::with_config(config.clone());
+        assert!(storage.import_bundle(&newer).is_err());
+
+        // A store export has the same top-level fields but is no bundle
+        let export = StoreExport {
+            version: STORE_EXPORT_VERSION,
+            exported_at: Utc::now(),
+            config,
+            snapshots: newer.snapshots,
+            sessions: Vec::new(),
+        };
+        let json = serde_json::to_string(&export).unwrap();
+        assert!(serde_json::from_str::<SnapshotBundle>(&json).is_err());
+    }
+
+    #[test]
+    fn test_keys_only_snapshot() {
+        let temp_dir = TempDir::new().unwrap();
+        let snapshot_path = temp_dir.path().join("shared.json");
+        let storage = Storage::with_config(Config::default());
+
+        let environment = Env::from([("TOKEN".to_string(), "secret".to_string())]);
+        let snapshot = Snapshot {
+            name: "shared".to_string(),
+            created_at: Utc::now(),
+            description: None,
+            environment: strip_values(&environment),
+            tags: Vec::new(),
+            session_id: None,
+            keys_only: true,
+            expires_at: None,
+        };
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(!json.contains("secret"));
+        std::fs::write(&snapshot_path, json).unwrap();
+        let loaded = storage.load_snapshot_from_path(&snapshot_path).unwrap();
+
+        assert!(loaded.keys_only);
+        assert_eq!(loaded.environment.get("TOKEN"), Some(&String::new()));
+    }
+
+    #[test]
+    fn test_read_timeline_without_versioned_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let timeline_path = temp_dir.path().join("timeline.jsonl");
+
+        // Entries as written before `v` existed; the unset line also omits `value`
+        let fixture = r#"{"timestamp":"2025-11-07T10:23:45Z","action":"set","key":"CANTON_NODE_1","value":"0x742d","prev":null}
+{"timestamp":"2025-11-07T10:25:03Z","action":"unset","key":"OLD_API_KEY","prev":"sk-1"}
+"#;
+        std::fs::write(&timeline_path, fixture).unwrap();
+
+        let entries = Storage::read_timeline_file(&timeline_path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].v, 0);
+        assert_eq!(entries[0].value.as_deref(), Some("0x742d"));
+        assert!(entries[1].value.is_none());
+        assert_eq!(entries[1].prev.as_deref(), Some("sk-1"));
+
+        let entry = TimelineEntry::new(Action::Set, "KEY".to_string(), None, None);
+        let reparsed: TimelineEntry =
+            serde_json::from_str(&serde_json::to_string(&entry).unwrap()).unwrap();
+        assert_eq!(reparsed.v, TIMELINE_VERSION);
+    }
+
+    #[test]
+    fn test_binary_timeline() {
+        let temp_dir = TempDir::new().unwrap();
+        let timeline_path = temp_dir.path().join("timeline.jsonl");
+        let binary_path = temp_dir.path().join(crate::session::BINARY_TIMELINE_FILE);
+
+        // Written as JSONL first, then switched to bincode
+        std::fs::write(
+            &timeline_path,
+            r#"{"timestamp":"2025-11-07T10:23:45Z","action":"set","key":"A","value":"1","prev":null}
+"#,
+        )
+        .unwrap();
+        let mut entry =
+            TimelineEntry::new(Action::Set, "A".into(), Some("2".into()), Some("1".into()));
+        entry.seq = 7;
+        let unset = TimelineEntry::new(Action::Unset, "B".into(), None, Some("x".into()));
+        let mut data = binary_record(&entry).unwrap();
+        data.extend(binary_record(&unset).unwrap());
+        std::fs::write(&binary_path, &data).unwrap();
+
+        let entries = Storage::read_timeline_file(&timeline_path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].value.as_deref(), Some("1"));
+        assert_eq!(entries[1].value.as_deref(), Some("2"));
+        assert_eq!(entries[1].prev.as_deref(), Some("1"));
+        assert_eq!(entries[1].seq, 7);
+        assert!(matches!(entries[2].action, Action::Unset));
+
+        // A record cut short by an interrupted write is skipped
+        std::fs::write(&binary_path, &data[..data.len() - 3]).unwrap();
+        let entries = Storage::read_timeline_file(&timeline_path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].value.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_append_after_torn_binary_timeline() {
+        let temp_dir = TempDir::new().unwrap();
+        let timeline_path = temp_dir.path().join("timeline.jsonl");
+        let binary_path = temp_dir.path().join(crate::session::BINARY_TIMELINE_FILE);
+
+        let first = TimelineEntry::new(Action::Set, "A".into(), Some("1".into()), None);
+        append_timeline_at(&binary_path, TimelineFormat::Bincode, &first).unwrap();
+        // An interrupted write leaves half a record behind
+        let torn = binary_record(&first).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&binary_path).unwrap();
+        file.write_all(&torn[..torn.len() / 2]).unwrap();
+        drop(file);
+
+        let second = TimelineEntry::new(Action::Set, "B".into(), Some("2".into()), None);
+        append_timeline_at(&binary_path, TimelineFormat::Bincode, &second).unwrap();
+
+        let entries = Storage::read_timeline_file(&timeline_path).unwrap();
+        let keys: Vec<&str> = entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn test_concurrent_binary_appends() {
+        let temp_dir = TempDir::new().unwrap();
+        let timeline_path = temp_dir.path().join("timeline.jsonl");
+        let binary_path = temp_dir.path().join(crate::session::BINARY_TIMELINE_FILE);
+
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let binary_path = &binary_path;
+                s.spawn(move || {
+                    for i in 0..50 {
+                        let entry = TimelineEntry::new(
+                            Action::Set,
+                            format!("K{}_{}", t, i),
+                            Some("v".into()),
+                            None,
+                        );
+                        append_timeline_at(binary_path, TimelineFormat::Bincode, &entry).unwrap();
+                    }
+                });
+            }
+        });
+
+        let entries = Storage::read_timeline_file(&timeline_path).unwrap();
+        assert_eq!(entries.len(), 200);
+    }
+
+    #[test]
+    fn test_read_timeline_applies_notes() {
+        let temp_dir = TempDir::new().unwrap();
+        let timeline_path = temp_dir.path().join("timeline.jsonl");
+        let notes_path = temp_dir.path().join(crate::session::NOTES_FILE);
+
+        let fixture = r#"{"timestamp":"2025-11-07T10:23:45Z","action":"set","key":"A","value":"1","prev":null}
+{"timestamp":"2025-11-07T10:24:00Z","action":"set","key":"A","value":"2","prev":"1"}
+"#;
+        std::fs::write(&timeline_path, fixture).unwrap();
+        std::fs::write(
+            &notes_path,
+            r#"{"timestamp":"2025-11-07T10:24:00Z","key":"A","note":"bumped for release"}
+"#,
+        )
+        .unwrap();
+
+        let entries = Storage::read_timeline_file(&timeline_path).unwrap();
+        assert!(entries[0].note.is_none());
+        assert_eq!(entries[1].note.as_deref(), Some("bumped for release"));
+    }
+
+    #[test]
+    fn test_pick_session_by_short_id() {
+        let session_with_id = |id: &str| {
+            let mut session = Session::new(0, "zsh".to_string());
+            session.id = id.parse().unwrap();
+            session
+        };
+        let sessions = vec![
+            session_with_id("11111111-0000-0000-0000-000000000000"),
+            session_with_id("11112222-0000-0000-0000-000000000000"),
+            session_with_id("33333333-0000-0000-0000-000000000000"),
+        ];
+
+        let found = pick_session(sessions.clone(), &sessions[2].short_id()).unwrap();
+        assert_eq!(found, sessions[2]);
+        assert_eq!(found.short_id(), "33333333");
+
+        let full = sessions[0].id.to_string();
+        assert_eq!(pick_session(sessions.clone(), &full).unwrap(), sessions[0]);
+
+        let ambiguous = pick_session(sessions.clone(), "1111").unwrap_err();
+        assert!(ambiguous.to_string().contains("ambiguous"));
+        assert!(pick_session(sessions.clone(), "4444").is_err());
+        assert!(pick_session(sessions, "").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_path_session_vs_global() {
+        let session = Session::new(42, "/bin/zsh".to_string());
+        let base = Config::base_dir();
+
+        assert_eq!(
+            snapshot_path("dev", Some(&session), false),
+            base.join(format!("sessions/{}/snapshots/dev.json", session.id))
+        );
+        assert_eq!(
+            snapshot_path("dev", None, false),
+            base.join("global/snapshots/dev.json")
+        );
+        assert_eq!(
+            snapshot_path("dev", None, true),
+            base.join("global/snapshots/dev.json.gz")
+        );
+    }
+
+    #[test]
+    fn test_compressed_snapshot_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut environment = Env::new();
+        environment.insert("BIG".to_string(), "x".repeat(10_000));
+        let snapshot = Snapshot {
+            name: "big".to_string(),
+            created_at: Utc::now(),
+            description: None,
+            environment,
+            tags: Vec::new(),
+            session_id: None,
+            keys_only: false,
+            expires_at: None,
+        };
+
+        let path = temp_dir.path().join("big.json.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(serde_json::to_string(&snapshot).unwrap().as_bytes())
+            .unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < 1_000);
+
+        assert_eq!(
+            find_snapshot_file(temp_dir.path(), "big"),
+            Some(path.clone())
+        );
+        assert_eq!(snapshot_file_name(&path), Some("big"));
+        assert_eq!(snapshot_file_name(Path::new("dev.json")), Some("dev"));
+        assert_eq!(snapshot_file_name(Path::new("notes.txt")), None);
+
+        let loaded = read_snapshot_file(&path).unwrap();
+        assert_eq!(loaded.environment["BIG"].len(), 10_000);
+    }
+
+    #[test]
+    fn test_salt_is_created_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let salt_path = temp_dir.path().join("salt");
+
+        let salt = load_or_create_salt(&salt_path).unwrap();
+        assert_eq!(salt.len(), 64);
+        assert_eq!(load_or_create_salt(&salt_path).unwrap(), salt);
+    }
+
+    fn baseline_test_snapshot(name: &str, created_at: DateTime<Utc>) -> Snapshot {
+        Snapshot {
+            name: name.to_string(),
+            created_at,
+            description: None,
+            environment: Env::new(),
+            tags: Vec::new(),
+            session_id: None,
+            keys_only: false,
+            expires_at: None,
+        }
+    }
+
+    /// What [`Storage::save_snapshot`] does for a global snapshot, in the
+    /// store at `base`.
+    fn save_test_snapshot(base: &Path, snapshot: &Snapshot) {
+        let dir = base.join(GLOBAL_SNAPSHOTS_PREFIX);
+        std::fs::create_dir_all(&dir).unwrap();
+        let content = serde_json::to_string_pretty(snapshot).unwrap();
+        write_atomic(&dir.join(format!("{}.json", snapshot.name)), content).unwrap();
+        update_baseline_at(base, snapshot).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_baseline_updates() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+        let baseline_path = base.join(BASELINE_FILE);
+        std::fs::write(&baseline_path, "{ not json").unwrap();
+
+        let start = Utc::now();
+        std::thread::scope(|s| {
+            for i in 0..16 {
+                s.spawn(move || {
+                    let created_at = start + chrono::Duration::seconds(i);
+                    let snapshot = baseline_test_snapshot(&format!("snap-{}", i), created_at);
+                    save_test_snapshot(base, &snapshot);
+                });
+            }
+        });
+
+        let content = std::fs::read_to_string(&baseline_path).unwrap();
+        let baseline: Snapshot = serde_json::from_str(&content).unwrap();
+        assert_eq!(baseline.name, "snap-15");
+
+        // Every saved snapshot is listed
+        let mut names: Vec<String> = std::fs::read_dir(base.join(GLOBAL_SNAPSHOTS_PREFIX))
+            .unwrap()
+            .map(|e| read_snapshot_file(&e.unwrap().path()).unwrap().name)
+            .collect();
+        names.sort_by_key(|name| name[5..].parse::<u32>().unwrap());
+        let expected: Vec<String> = (0..16).map(|i| format!("snap-{}", i)).collect();
+        assert_eq!(names, expected);
+
+        // No temporary files are left behind
+        let leftovers: Vec<_> = [base.to_path_buf(), base.join(GLOBAL_SNAPSHOTS_PREFIX)]
+            .iter()
+            .flat_map(|dir| std::fs::read_dir(dir).unwrap())
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn test_unreadable_baseline_is_rebuilt() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+        let start = Utc::now();
+        save_test_snapshot(base, &baseline_test_snapshot("newest", start));
+        std::fs::write(base.join(BASELINE_FILE), "{ not json").unwrap();
+
+        // An older snapshot, e.g. from an imported archive, doesn't take over
+        let older = baseline_test_snapshot("older", start - chrono::Duration::hours(1));
+        save_test_snapshot(base, &older);
+
+        let baseline = read_snapshot_file(&base.join(BASELINE_FILE)).unwrap();
+        assert_eq!(baseline.name, "newest");
+    }
+
+    #[test]
+    fn test_failures_keep_going() {
+        let mut stop = Failures::new(false);
+        assert_eq!(stop.check(Ok(1)).unwrap(), Some(1));
+        assert!(stop.check::<()>(Err(anyhow::anyhow!("bad"))).is_err());
+        assert!(stop.is_empty());
+
+        let mut keep_going = Failures::new(true);
+        assert_eq!(
+            keep_going.check::<()>(Err(anyhow::anyhow!("bad"))).unwrap(),
+            None
+        );
+        assert_eq!(keep_going.check(Ok(2)).unwrap(), Some(2));
+        assert_eq!(keep_going.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_delete_expired() {
+        let store = TempDir::new().unwrap();
+        let base = store.path();
+        let now = Utc::now();
+        let write = |dir: &Path, name: &str, expires_at: Option<DateTime<Utc>>| {
+            std::fs::create_dir_all(dir).unwrap();
+            let snapshot = Snapshot {
+                name: name.to_string(),
+                created_at: now - chrono::Duration::days(1),
+                description: None,
+                environment: Env::new(),
+                tags: Vec::new(),
+                session_id: None,
+                keys_only: false,
+                expires_at,
+            };
+            std::fs::write(
+                dir.join(format!("{}.json", name)),
+                serde_json::to_string(&snapshot).unwrap(),
+            )
+            .unwrap();
+        };
+
+        let global = base.join("global").join("snapshots");
+        let session = base
+            .join("sessions")
+            .join(uuid::Uuid::new_v4().to_string())
+            .join("snapshots");
+        write(&global, "keep", None);
+        write(&global, "later", Some(now + chrono::Duration::hours(1)));
+        write(&global, "gone", Some(now - chrono::Duration::hours(1)));
+        write(&session, "scratch", Some(now));
+
+        assert_eq!(
+            delete_expired_at(base, now).unwrap(),
+            vec!["gone", "scratch"]
+        );
+        assert!(global.join("keep.json").exists());
+        assert!(global.join("later.json").exists());
+        assert!(!global.join("gone.json").exists());
+        assert!(!session.join("scratch.json").exists());
+        assert!(delete_expired_at(base, now).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_store_usage() {
+        let store = TempDir::new().unwrap();
+        let base = store.path();
+        assert_eq!(store_usage_at(base).unwrap(), StoreUsage::default());
+
+        std::fs::write(base.join("config.toml"), "x".repeat(7)).unwrap();
+        let session_dir = base.join("sessions").join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(session_dir.join("snapshots")).unwrap();
+        std::fs::write(session_dir.join("timeline.jsonl"), "x".repeat(100)).unwrap();
+        std::fs::write(session_dir.join("snapshots/dev.json"), "x".repeat(20)).unwrap();
+        let global = base.join("global").join("snapshots");
+        std::fs::create_dir_all(&global).unwrap();
+        std::fs::write(global.join("prod.json.gz"), "x".repeat(30)).unwrap();
+
+        let usage = store_usage_at(base).unwrap();
+        assert_eq!(
+            usage,
+            StoreUsage {
+                sessions_bytes: 100,
+                snapshots_bytes: 50,
+                total_bytes: 157,
+                session_count: 1,
+                snapshot_count: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let store = TempDir::new().unwrap();
+        let base = store.path();
+        let session_dir = base.join("sessions").join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&session_dir).unwrap();
+        std::fs::write(session_dir.join("timeline.jsonl"), "{}\n").unwrap();
+
+        let snapshots_dir = base.join("global").join("snapshots");
+        std::fs::create_dir_all(&snapshots_dir).unwrap();
+        for (name, age_days) in [("old", 100), ("new", 1)] {
+            let snapshot = Snapshot {
+                name: name.to_string(),
+                created_at: Utc::now() - chrono::Duration::days(age_days),
+                description: None,
+                environment: Env::new(),
+                tags: Vec::new(),
+                session_id: None,
+                keys_only: false,
+                expires_at: None,
+            };
+            std::fs::write(
+                snapshots_dir.join(format!("{}.json", name)),
+                serde_json::to_string(&snapshot).unwrap(),
+            )
+            .unwrap();
+        }
+
+        let out = TempDir::new().unwrap();
+        let archive = out.path().join("store.tar.zst");
+        // Sessions are aged by file mtime, so a future cutoff catches this one
+        let cutoff = Utc::now() + chrono::Duration::seconds(5);
+        let snapshot_cutoff = Utc::now() - chrono::Duration::days(90);
+
+        let summary = archive_store(base, snapshot_cutoff, &archive).unwrap();
+        assert_eq!(summary.sessions, 0);
+        assert_eq!(summary.snapshot_names, vec!["old".to_string()]);
+        assert!(!snapshots_dir.join("old.json").exists());
+        assert!(snapshots_dir.join("new.json").exists());
+
+        let second = out.path().join("sessions.tar.zst");
+        let summary = archive_store(base, cutoff, &second).unwrap();
+        assert_eq!(summary.sessions, 1);
+        assert!(!session_dir.exists());
 
-        // Mock the config paths - this is a simplified test
-        // In real usage, we'd need to set up the directories properly
+        let restored = unpack_archive(base, &archive).unwrap();
+        assert_eq!(restored.snapshot_names, vec!["old".to_string()]);
+        let restored = unpack_archive(base, &second).unwrap();
+        assert_eq!(restored.sessions, 1);
+        assert!(session_dir.join("timeline.jsonl").exists());
+        assert!(snapshots_dir.join("old.json").exists());
     }
 }
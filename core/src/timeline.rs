@@ -0,0 +1,335 @@
+use crate::storage::{Action, TimelineEntry};
+use chrono::{DateTime, Duration, Utc};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+
+/// A timeline entry as presented to the user, after optional analysis passes.
+/// `E` is a reference to the entry, or anything carrying one along with
+/// details the caller needs to keep with it.
+#[derive(Debug, Clone, Copy)]
+pub enum TimelineItem<E> {
+    Entry(E),
+    /// `from` was unset and `to` newly set to the same value
+    Rename {
+        from: E,
+        to: E,
+    },
+}
+
+impl<E: Borrow<TimelineEntry>> TimelineItem<E> {
+    /// The variable the item is listed under.
+    pub fn key(&self) -> &str {
+        match self {
+            TimelineItem::Entry(entry) => &entry.borrow().key,
+            TimelineItem::Rename { to, .. } => &to.borrow().key,
+        }
+    }
+
+    pub fn timestamp(&self) -> chrono::DateTime<chrono::Utc> {
+        match self {
+            TimelineItem::Entry(entry) => entry.borrow().timestamp,
+            TimelineItem::Rename { from, to } => from.borrow().timestamp.min(to.borrow().timestamp),
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        match self {
+            TimelineItem::Entry(entry) => entry.borrow().seq,
+            TimelineItem::Rename { from, to } => from.borrow().seq.min(to.borrow().seq),
+        }
+    }
+}
+
+/// Collapse an `Unset` of OLD and a `Set` of a previously unset NEW to OLD's
+/// last value into a single rename, when both happen within `window` of each
+/// other (in either order). Each entry takes part in at most one rename.
+pub fn detect_renames<E: Borrow<TimelineEntry> + Copy>(
+    entries: impl IntoIterator<Item = E>,
+    window: Duration,
+) -> Vec<TimelineItem<E>> {
+    let given: Vec<E> = entries.into_iter().collect();
+    let entries: Vec<&TimelineEntry> = given.iter().map(|e| e.borrow()).collect();
+    let mut used = vec![false; entries.len()];
+    let mut items = Vec::new();
+
+    for i in 0..entries.len() {
+        if used[i] {
+            continue;
+        }
+        let first = entries[i];
+
+        let partner = entries
+            .iter()
+            .enumerate()
+            .skip(i + 1)
+            .take_while(|(_, e)| e.timestamp - first.timestamp <= window)
+            .find(|(j, e)| !used[*j] && is_rename(first, e))
+            .map(|(j, _)| j);
+
+        match partner {
+            Some(j) => {
+                used[j] = true;
+                let (from, to) = if matches!(first.action, Action::Unset) {
+                    (given[i], given[j])
+                } else {
+                    (given[j], given[i])
+                };
+                items.push(TimelineItem::Rename { from, to });
+            }
+            None => items.push(TimelineItem::Entry(given[i])),
+        }
+    }
+
+    items
+}
+
+/// How many times each variable was set or unset. Directory and baseline
+/// markers are not variable changes and are skipped.
+pub fn change_counts<'a>(
+    entries: impl IntoIterator<Item = &'a TimelineEntry>,
+) -> HashMap<String, usize> {
+    entries
+        .into_iter()
+        .filter(|e| matches!(e.action, Action::Set | Action::Unset))
+        .fold(HashMap::new(), |mut counts, e| {
+            *counts.entry(e.key.clone()).or_insert(0) += 1;
+            counts
+        })
+}
+
+/// Shortest value of the followed variable that counts as referenced when
+/// another value contains it; shorter ones match almost anything.
+const MIN_DERIVATION_LEN: usize = 3;
+
+/// `key`'s changes, in timeline order. With `derivations`, also the changes
+/// to other variables whose new value contains `key`'s value at the time.
+pub fn follow<E: Borrow<TimelineEntry> + Copy>(
+    entries: impl IntoIterator<Item = E>,
+    key: &str,
+    derivations: bool,
+) -> Vec<E> {
+    let entries: Vec<E> = entries.into_iter().collect();
+    // Before its first change the variable held that change's previous value
+    let mut current: Option<&str> = entries
+        .iter()
+        .map(|e| e.borrow())
+        .find(|e| e.key == key)
+        .and_then(|e| e.prev.as_deref());
+
+    let mut followed = Vec::new();
+    for item in &entries {
+        let entry = item.borrow();
+        if entry.key == key {
+            current = entry.value.as_deref();
+            followed.push(*item);
+            continue;
+        }
+        if !derivations || !matches!(entry.action, Action::Set) {
+            continue;
+        }
+        let derived = match (current, entry.value.as_deref()) {
+            (Some(current), Some(value)) => {
+                current.len() >= MIN_DERIVATION_LEN && value.contains(current)
+            }
+            _ => false,
+        };
+        if derived {
+            followed.push(*item);
+        }
+    }
+    followed
+}
+
+/// A variable's state at some moment, from replaying its timeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueAt {
+    /// No entry for the variable up to that moment
+    NeverSet,
+    Unset,
+    Set(String),
+}
+
+/// Replay `key`'s entries up to and including `at` to find its value then.
+/// Directory changes count as sets of `PWD`.
+pub fn value_at<'a>(
+    entries: impl IntoIterator<Item = &'a TimelineEntry>,
+    key: &str,
+    at: DateTime<Utc>,
+) -> ValueAt {
+    let mut entries: Vec<&TimelineEntry> = entries
+        .into_iter()
+        .filter(|e| e.key == key && e.timestamp <= at)
+        .filter(|e| matches!(e.action, Action::Set | Action::Unset | Action::Chdir))
+        .collect();
+    // Merged sessions arrive per session, not in time order
+    entries.sort_by_key(|e| (e.timestamp, e.seq));
+
+    entries
+        .into_iter()
+        .fold(ValueAt::NeverSet, |_, e| match (&e.action, &e.value) {
+            (Action::Unset, _) | (_, None) => ValueAt::Unset,
+            (_, Some(value)) => ValueAt::Set(value.clone()),
+        })
+}
+
+/// Whether a candidate pair, in either order, is an unset and a set that
+/// form a rename.
+fn is_rename(a: &TimelineEntry, b: &TimelineEntry) -> bool {
+    let (unset, set) = match (&a.action, &b.action) {
+        (Action::Unset, Action::Set) => (a, b),
+        (Action::Set, Action::Unset) => (b, a),
+        _ => return false,
+    };
+
+    unset.key != set.key && set.prev.is_none() && unset.prev.is_some() && unset.prev == set.value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(
+        secs: i64,
+        action: Action,
+        key: &str,
+        value: Option<&str>,
+        prev: Option<&str>,
+    ) -> TimelineEntry {
+        let mut entry = TimelineEntry::new(
+            action,
+            key.to_string(),
+            value.map(String::from),
+            prev.map(String::from),
+        );
+        entry.timestamp = Utc.timestamp_opt(1_700_000_000 + secs, 0).unwrap();
+        entry
+    }
+
+    #[test]
+    fn test_detect_renames_within_window() {
+        let entries = vec![
+            entry(0, Action::Unset, "OLD", None, Some("v")),
+            entry(2, Action::Set, "NEW", Some("v"), None),
+            entry(3, Action::Set, "OTHER", Some("x"), None),
+        ];
+
+        let items = detect_renames(&entries, Duration::seconds(5));
+        assert_eq!(items.len(), 2);
+        assert!(matches!(
+            items[0],
+            TimelineItem::Rename { from, to } if from.key == "OLD" && to.key == "NEW"
+        ));
+        assert_eq!(items[1].key(), "OTHER");
+    }
+
+    #[test]
+    fn test_detect_renames_set_before_unset() {
+        let entries = vec![
+            entry(0, Action::Set, "NEW", Some("v"), None),
+            entry(1, Action::Unset, "OLD", None, Some("v")),
+        ];
+
+        let items = detect_renames(&entries, Duration::seconds(5));
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key(), "NEW");
+    }
+
+    #[test]
+    fn test_detect_renames_outside_window_or_mismatched() {
+        let late = vec![
+            entry(0, Action::Unset, "OLD", None, Some("v")),
+            entry(10, Action::Set, "NEW", Some("v"), None),
+        ];
+        assert_eq!(detect_renames(&late, Duration::seconds(5)).len(), 2);
+
+        let different_value = vec![
+            entry(0, Action::Unset, "OLD", None, Some("v")),
+            entry(1, Action::Set, "NEW", Some("w"), None),
+        ];
+        assert_eq!(
+            detect_renames(&different_value, Duration::seconds(5)).len(),
+            2
+        );
+
+        // NEW already existed, so this is an overwrite rather than a rename
+        let existing = vec![
+            entry(0, Action::Unset, "OLD", None, Some("v")),
+            entry(1, Action::Set, "NEW", Some("v"), Some("old")),
+        ];
+        assert_eq!(detect_renames(&existing, Duration::seconds(5)).len(), 2);
+    }
+
+    #[test]
+    fn test_change_counts() {
+        let entries = vec![
+            entry(0, Action::Set, "A", Some("1"), None),
+            entry(1, Action::Set, "A", Some("2"), Some("1")),
+            entry(2, Action::Unset, "A", None, Some("2")),
+            entry(3, Action::Set, "B", Some("x"), None),
+            entry(4, Action::Chdir, "PWD", Some("/tmp"), None),
+            entry(5, Action::Baseline, "", None, None),
+        ];
+
+        let counts = change_counts(&entries);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["A"], 3);
+        assert_eq!(counts["B"], 1);
+    }
+
+    #[test]
+    fn test_follow() {
+        let entries = vec![
+            entry(0, Action::Set, "ROOT", Some("/opt/app"), Some("/old")),
+            entry(1, Action::Set, "BIN", Some("/opt/app/bin"), None),
+            entry(2, Action::Set, "OTHER", Some("/usr"), None),
+            entry(3, Action::Set, "ROOT", Some("/srv"), Some("/opt/app")),
+            entry(4, Action::Set, "LIB", Some("/opt/app/lib"), None),
+            entry(5, Action::Set, "DATA", Some("/srv/data"), None),
+        ];
+        let keys = |derivations| -> Vec<&str> {
+            follow(&entries, "ROOT", derivations)
+                .iter()
+                .map(|e| e.key.as_str())
+                .collect()
+        };
+
+        assert_eq!(keys(false), ["ROOT", "ROOT"]);
+        // LIB mentions the old value only, after ROOT moved on
+        assert_eq!(keys(true), ["ROOT", "BIN", "ROOT", "DATA"]);
+    }
+
+    #[test]
+    fn test_follow_seeds_from_prev_and_skips_short_values() {
+        let entries = vec![
+            entry(0, Action::Set, "BIN", Some("/opt/app/bin"), None),
+            entry(1, Action::Set, "ROOT", Some("/x"), Some("/opt/app")),
+            entry(2, Action::Set, "ANY", Some("/x/y"), None),
+        ];
+
+        let followed = follow(&entries, "ROOT", true);
+        let keys: Vec<&str> = followed.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, ["BIN", "ROOT"]);
+    }
+
+    #[test]
+    fn test_value_at() {
+        let entries = vec![
+            entry(0, Action::Set, "A", Some("1"), None),
+            entry(10, Action::Set, "A", Some("2"), Some("1")),
+            entry(20, Action::Unset, "A", None, Some("2")),
+            entry(5, Action::Chdir, "PWD", Some("/tmp"), None),
+        ];
+        let at = |secs: i64| Utc.timestamp_opt(1_700_000_000 + secs, 0).unwrap();
+
+        assert_eq!(value_at(&entries, "A", at(-1)), ValueAt::NeverSet);
+        assert_eq!(value_at(&entries, "A", at(0)), ValueAt::Set("1".into()));
+        assert_eq!(value_at(&entries, "A", at(15)), ValueAt::Set("2".into()));
+        assert_eq!(value_at(&entries, "A", at(25)), ValueAt::Unset);
+        assert_eq!(
+            value_at(&entries, "PWD", at(6)),
+            ValueAt::Set("/tmp".into())
+        );
+        assert_eq!(value_at(&entries, "B", at(25)), ValueAt::NeverSet);
+    }
+}
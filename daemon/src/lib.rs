@@ -1,3 +1,3 @@
 pub mod server;
 
-pub use server::{EnvEvent, EnvHistDaemon, EnvResponse};
+pub use server::{EnvEvent, EnvHistDaemon, EnvResponse, ErrorKind};
@@ -4,6 +4,7 @@ use envhist_daemon::server::EnvHistDaemon;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    Config::try_base_dir()?;
     let daemon = EnvHistDaemon::new()?;
     let socket_path = Config::daemon_socket_path();
 
@@ -1,23 +1,37 @@
 use anyhow::{Context, Result};
-use chrono::Utc;
 use envhist_core::{
-    session::Session, storage::Action, storage::Storage, storage::TimelineEntry, Config, Env,
+    config::{ConfigWatcher, TrackDecision},
+    differ::env_hash,
+    session::Session,
+    storage::Action,
+    storage::Storage,
+    storage::TimelineEntry,
+    Config, Env, StorageBackend,
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::{UnixListener, UnixStream},
-    sync::RwLock,
+    sync::{Mutex, RwLock, Semaphore},
 };
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EnvEvent {
+    /// Record `key` being set. Answered with `Ok` once recorded, or with an
+    /// `Error` of kind `Filtered` when the filters or sampling skip it
+    /// (daemons before `ErrorKind` existed answered `Ok` either way).
     Set {
         pid: u32,
         key: String,
         value: String,
     },
+    /// Record `key` being unset; answered like [`EnvEvent::Set`].
     Unset {
         pid: u32,
         key: String,
@@ -26,37 +40,204 @@ pub enum EnvEvent {
         pid: u32,
         env: Env,
     },
+    Chdir {
+        pid: u32,
+        dir: String,
+        env: Env,
+    },
+    /// The shell's full environment at startup, registered in one round trip
+    SetMany {
+        pid: u32,
+        vars: Env,
+    },
     GetSession {
         pid: u32,
     },
+    /// Label the session of `pid`
+    TagSession {
+        pid: u32,
+        tag: String,
+    },
+    /// Check that the daemon is up; answered with `Ok`
+    Ping,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EnvResponse {
     Ok,
-    Session { session: Session },
-    Error { message: String },
+    Session {
+        session: Session,
+    },
+    Error {
+        #[serde(default)]
+        kind: ErrorKind,
+        message: String,
+    },
 }
 
-pub struct EnvHistDaemon {
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// The requested session or entry does not exist
+    NotFound,
+    /// Reading or writing the store failed
+    Io,
+    /// The variable is excluded by the configured filters
+    Filtered,
+    /// The request could not be parsed
+    Parse,
+    #[default]
+    Internal,
+}
+
+impl ErrorKind {
+    /// Whether sending the same event again could succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ErrorKind::Io)
+    }
+}
+
+impl EnvEvent {
+    /// Whether handling the event twice has the same effect as once, so a
+    /// client may resend it after losing the reply.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            EnvEvent::Capture { .. }
+                | EnvEvent::GetSession { .. }
+                | EnvEvent::TagSession { .. }
+                | EnvEvent::Ping
+        )
+    }
+}
+
+impl EnvResponse {
+    fn error(kind: ErrorKind, message: String) -> Self {
+        EnvResponse::Error { kind, message }
+    }
+}
+
+/// Run the daemon with `ENVHIST_DEBUG` set to log filter decisions.
+fn debug_enabled() -> bool {
+    static ENABLED: LazyLock<bool> = LazyLock::new(|| std::env::var_os("ENVHIST_DEBUG").is_some());
+    *ENABLED
+}
+
+/// The last capture saved for a shell, to skip rewriting an identical one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CaptureMark {
+    hash: String,
+    at: chrono::DateTime<chrono::Utc>,
+}
+
+impl CaptureMark {
+    fn new(env: &Env, at: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            hash: env_hash(env),
+            at,
+        }
+    }
+
+    /// Whether `next` can be dropped: it repeats this capture, or arrives
+    /// less than `min_interval` after it.
+    fn skips(&self, next: &CaptureMark, min_interval: chrono::Duration) -> bool {
+        self.hash == next.hash || next.at - self.at < min_interval
+    }
+}
+
+type Captures = Arc<RwLock<HashMap<u32, CaptureMark>>>;
+
+/// The last [`TimelineEntry::seq`] handed out per session, seeded from the
+/// timeline on first use so numbering carries on across daemon restarts.
+type Seqs = Arc<Mutex<HashMap<Uuid, u64>>>;
+
+/// How often the daemon checks `config.toml` for edits.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the daemon deletes snapshots past their `--expire` time.
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The config and the storage built from it, replaced together when
+/// `config.toml` changes.
+#[derive(Clone)]
+struct Live {
     storage: Storage,
-    sessions: Arc<RwLock<HashMap<u32, Session>>>,
     config: Config,
 }
 
+impl Live {
+    fn new(config: Config) -> Self {
+        Self {
+            storage: Storage::with_config(config.clone()),
+            config,
+        }
+    }
+}
+
+pub struct EnvHistDaemon {
+    live: Arc<RwLock<Live>>,
+    sessions: Arc<RwLock<HashMap<u32, Session>>>,
+    captures: Captures,
+    seqs: Seqs,
+    /// Reload the config when its file changes and delete expired
+    /// snapshots; off for configs built in code
+    background_tasks: bool,
+}
+
 impl EnvHistDaemon {
     pub fn new() -> Result<Self> {
         let config = Config::load()?;
-        let storage = Storage::with_config(config.clone());
-        storage.ensure_directories()?;
-
+        Storage::with_config(config.clone()).ensure_directories()?;
         Ok(Self {
-            storage,
-            sessions: Arc::new(RwLock::new(HashMap::new())),
-            config,
+            background_tasks: true,
+            ..Self::with_config(config)
         })
     }
 
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            live: Arc::new(RwLock::new(Live::new(config))),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            captures: Arc::new(RwLock::new(HashMap::new())),
+            seqs: Arc::new(Mutex::new(HashMap::new())),
+            background_tasks: false,
+        }
+    }
+
+    /// Swap in the new config whenever `config.toml` changes. A config that
+    /// fails to load is reported and the previous one kept.
+    async fn watch_config(live: Arc<RwLock<Live>>) {
+        let mut watcher = ConfigWatcher::new();
+        let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match watcher.poll() {
+                None => {}
+                Some(Ok(config)) => {
+                    *live.write().await = Live::new(config);
+                    eprintln!("Reloaded config from {:?}", watcher.path());
+                }
+                Some(Err(e)) => eprintln!("Keeping the previous config: {:#}", e),
+            }
+        }
+    }
+
+    /// Delete expired snapshots now and every [`EXPIRY_SWEEP_INTERVAL`].
+    async fn sweep_expired(live: Arc<RwLock<Live>>) {
+        let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let storage = live.read().await.storage.clone();
+            match storage.delete_expired(chrono::Utc::now()) {
+                Ok(names) => {
+                    for name in names {
+                        eprintln!("Deleted expired snapshot '{}'", name);
+                    }
+                }
+                Err(e) => eprintln!("Failed to delete expired snapshots: {:#}", e),
+            }
+        }
+    }
+
     pub async fn run(&self, socket_path: std::path::PathBuf) -> Result<()> {
         // Remove old socket if it exists
         if socket_path.exists() {
@@ -68,18 +249,38 @@ impl EnvHistDaemon {
 
         eprintln!("Daemon listening on {:?}", socket_path);
 
+        let max_connections = self.live.read().await.config.core.daemon_max_connections;
+        let connections = Arc::new(Semaphore::new(max_connections));
+
+        if self.background_tasks {
+            tokio::spawn(Self::watch_config(Arc::clone(&self.live)));
+            tokio::spawn(Self::sweep_expired(Arc::clone(&self.live)));
+        }
+
         loop {
+            // Wait for a free slot before accepting so excess clients queue in
+            // the listen backlog instead of piling up tasks
+            let permit = Arc::clone(&connections)
+                .acquire_owned()
+                .await
+                .context("Connection limiter closed")?;
+
             match listener.accept().await {
                 Ok((stream, _)) => {
                     let sessions = Arc::clone(&self.sessions);
-                    let storage = self.storage.clone();
-                    let config = self.config.clone();
+                    let captures = Arc::clone(&self.captures);
+                    let seqs = Arc::clone(&self.seqs);
+                    // Clients keep the config they connected under
+                    let Live { storage, config } = self.live.read().await.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_client(stream, sessions, storage, config).await
+                        if let Err(e) =
+                            Self::handle_client(stream, sessions, captures, seqs, storage, config)
+                                .await
                         {
                             eprintln!("Error handling client: {}", e);
                         }
+                        drop(permit);
                     });
                 }
                 Err(e) => {
@@ -92,14 +293,26 @@ impl EnvHistDaemon {
     async fn handle_client(
         mut stream: UnixStream,
         sessions: Arc<RwLock<HashMap<u32, Session>>>,
+        captures: Captures,
+        seqs: Seqs,
         storage: Storage,
         config: Config,
     ) -> Result<()> {
         let (reader, mut writer) = stream.split();
         let mut reader = BufReader::new(reader);
         let mut line = String::new();
+        let idle_timeout = Duration::from_millis(config.core.daemon_idle_timeout_ms);
+
+        loop {
+            let read = match tokio::time::timeout(idle_timeout, reader.read_line(&mut line)).await {
+                Ok(read) => read?,
+                // Idle client: drop the connection
+                Err(_) => break,
+            };
+            if read == 0 {
+                break;
+            }
 
-        while reader.read_line(&mut line).await? > 0 {
             let trimmed = line.trim();
             if trimmed.is_empty() {
                 line.clear();
@@ -109,9 +322,10 @@ impl EnvHistDaemon {
             let event: EnvEvent = match serde_json::from_str(trimmed) {
                 Ok(e) => e,
                 Err(e) => {
-                    let response = EnvResponse::Error {
-                        message: format!("Failed to parse event: {}", e),
-                    };
+                    let response = EnvResponse::error(
+                        ErrorKind::Parse,
+                        format!("Failed to parse event: {}", e),
+                    );
                     let response_json = serde_json::to_string(&response)?;
                     writer.write_all(response_json.as_bytes()).await?;
                     writer.write_all(b"\n").await?;
@@ -120,7 +334,8 @@ impl EnvHistDaemon {
                 }
             };
 
-            let response = Self::handle_event(event, &sessions, &storage, &config).await;
+            let response =
+                Self::handle_event(event, &sessions, &captures, &seqs, &storage, &config).await;
             let response_json = serde_json::to_string(&response)?;
             writer.write_all(response_json.as_bytes()).await?;
             writer.write_all(b"\n").await?;
@@ -133,32 +348,67 @@ impl EnvHistDaemon {
     async fn handle_event(
         event: EnvEvent,
         sessions: &Arc<RwLock<HashMap<u32, Session>>>,
+        captures: &Captures,
+        seqs: &Seqs,
         storage: &Storage,
         config: &Config,
     ) -> EnvResponse {
         match event {
             EnvEvent::Set { pid, key, value } => {
-                if !config.should_track(&key) {
-                    return EnvResponse::Ok;
+                let decision = Self::track_decision(config, &key);
+                if !decision.track {
+                    return EnvResponse::error(
+                        ErrorKind::Filtered,
+                        format!("Variable '{}' is not tracked ({})", key, decision.reason),
+                    );
                 }
 
+                let value = match storage.protect_value(&key, value) {
+                    Ok(value) => config.limit_value(value),
+                    Err(e) => return Self::hash_error(e),
+                };
+
                 match Self::get_or_create_session(pid, sessions).await {
                     Ok(session) => {
+                        if let Some(interval) = config.sample_interval(&key) {
+                            let entries = storage.read_timeline(&session).unwrap_or_default();
+                            if Self::recorded_within(&entries, &key, interval, chrono::Utc::now()) {
+                                // Not recorded, but the next entry's `prev`
+                                // must still see the value
+                                if let Err(e) = Self::save_current_value(&session, &key, &value) {
+                                    return EnvResponse::error(
+                                        ErrorKind::Io,
+                                        format!("Failed to save metadata: {}", e),
+                                    );
+                                }
+                                return EnvResponse::error(
+                                    ErrorKind::Filtered,
+                                    format!(
+                                        "Variable '{}' was recorded less than {}s ago (filters.sample_patterns)",
+                                        key,
+                                        interval.num_seconds()
+                                    ),
+                                );
+                            }
+                        }
+
                         // Get previous value from session metadata if available
                         let prev = Self::get_previous_value(&session, &key, storage).await;
 
-                        let entry = TimelineEntry {
-                            timestamp: Utc::now(),
-                            action: Action::Set,
-                            key: key.clone(),
-                            value: Some(value.clone()),
-                            prev,
-                        };
-
-                        if let Err(e) = storage.append_timeline(&session, &entry) {
-                            return EnvResponse::Error {
-                                message: format!("Failed to append timeline: {}", e),
-                            };
+                        let entry =
+                            TimelineEntry::new(Action::Set, key.clone(), Some(value.clone()), prev);
+
+                        if let Err(e) = Self::append_entry(&session, entry, seqs, storage).await {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to append timeline: {}", e),
+                            );
+                        }
+                        if let Err(e) = Self::save_current_value(&session, &key, &value) {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to save metadata: {}", e),
+                            );
                         }
 
                         // Update session timestamp
@@ -171,66 +421,210 @@ impl EnvHistDaemon {
 
                         EnvResponse::Ok
                     }
-                    Err(e) => EnvResponse::Error {
-                        message: format!("Failed to get session: {}", e),
-                    },
+                    Err(e) => EnvResponse::error(
+                        ErrorKind::Internal,
+                        format!("Failed to get session: {}", e),
+                    ),
                 }
             }
             EnvEvent::Unset { pid, key } => {
-                if !config.should_track(&key) {
-                    return EnvResponse::Ok;
+                let decision = Self::track_decision(config, &key);
+                if !decision.track {
+                    return EnvResponse::error(
+                        ErrorKind::Filtered,
+                        format!("Variable '{}' is not tracked ({})", key, decision.reason),
+                    );
                 }
 
                 match Self::get_or_create_session(pid, sessions).await {
                     Ok(session) => {
                         let prev = Self::get_previous_value(&session, &key, storage).await;
 
-                        let entry = TimelineEntry {
-                            timestamp: Utc::now(),
-                            action: Action::Unset,
-                            key: key.clone(),
-                            value: None,
-                            prev,
-                        };
-
-                        if let Err(e) = storage.append_timeline(&session, &entry) {
-                            return EnvResponse::Error {
-                                message: format!("Failed to append timeline: {}", e),
-                            };
+                        let entry = TimelineEntry::new(Action::Unset, key.clone(), None, prev);
+
+                        if let Err(e) = Self::append_entry(&session, entry, seqs, storage).await {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to append timeline: {}", e),
+                            );
+                        }
+                        if let Err(e) = Self::remove_current_value(&session, &key) {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to save metadata: {}", e),
+                            );
                         }
 
                         EnvResponse::Ok
                     }
-                    Err(e) => EnvResponse::Error {
-                        message: format!("Failed to get session: {}", e),
-                    },
+                    Err(e) => EnvResponse::error(
+                        ErrorKind::Internal,
+                        format!("Failed to get session: {}", e),
+                    ),
                 }
             }
             EnvEvent::Capture { pid, env } => {
+                let env = match storage.protect_env(env) {
+                    Ok(env) => config.limit_env(env),
+                    Err(e) => return Self::hash_error(e),
+                };
+
+                let mark = CaptureMark::new(&env, chrono::Utc::now());
+                let min_interval =
+                    chrono::Duration::seconds(config.core.capture_min_interval as i64);
+                if captures
+                    .read()
+                    .await
+                    .get(&pid)
+                    .is_some_and(|last| last.skips(&mark, min_interval))
+                {
+                    return EnvResponse::Ok;
+                }
+
                 match Self::get_or_create_session(pid, sessions).await {
                     Ok(session) => {
                         // Save current env state to metadata
                         if let Err(e) = session.save_metadata(&env) {
-                            return EnvResponse::Error {
-                                message: format!("Failed to save metadata: {}", e),
-                            };
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to save metadata: {}", e),
+                            );
+                        }
+                        captures.write().await.insert(pid, mark);
+                        EnvResponse::Ok
+                    }
+                    Err(e) => EnvResponse::error(
+                        ErrorKind::Internal,
+                        format!("Failed to get session: {}", e),
+                    ),
+                }
+            }
+            EnvEvent::Chdir { pid, dir, env } => {
+                if !config.core.capture_on_cd {
+                    return EnvResponse::Ok;
+                }
+                let (dir, env) = match storage
+                    .protect_value("PWD", dir)
+                    .and_then(|dir| Ok((dir, storage.protect_env(env)?)))
+                {
+                    Ok((dir, env)) => (config.limit_value(dir), config.limit_env(env)),
+                    Err(e) => return Self::hash_error(e),
+                };
+
+                match Self::get_or_create_session(pid, sessions).await {
+                    Ok(session) => {
+                        let prev = Self::get_previous_value(&session, "PWD", storage).await;
+                        if prev.as_deref() == Some(dir.as_str()) {
+                            return EnvResponse::Ok;
+                        }
+
+                        let entry =
+                            TimelineEntry::new(Action::Chdir, "PWD".to_string(), Some(dir), prev);
+                        if let Err(e) = Self::append_entry(&session, entry, seqs, storage).await {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to append timeline: {}", e),
+                            );
+                        }
+
+                        if let Err(e) = session.save_metadata(&env) {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to save metadata: {}", e),
+                            );
+                        }
+                        EnvResponse::Ok
+                    }
+                    Err(e) => EnvResponse::error(
+                        ErrorKind::Internal,
+                        format!("Failed to get session: {}", e),
+                    ),
+                }
+            }
+            EnvEvent::SetMany { pid, vars } => {
+                let vars = match storage.protect_env(vars) {
+                    Ok(vars) => config.limit_env(vars),
+                    Err(e) => return Self::hash_error(e),
+                };
+
+                match Self::get_or_create_session(pid, sessions).await {
+                    Ok(session) => {
+                        if let Err(e) = session.save_metadata(&vars) {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to save metadata: {}", e),
+                            );
+                        }
+
+                        let entry = TimelineEntry::new(Action::Baseline, String::new(), None, None);
+                        if let Err(e) = Self::append_entry(&session, entry, seqs, storage).await {
+                            return EnvResponse::error(
+                                ErrorKind::Io,
+                                format!("Failed to append timeline: {}", e),
+                            );
                         }
                         EnvResponse::Ok
                     }
-                    Err(e) => EnvResponse::Error {
-                        message: format!("Failed to get session: {}", e),
-                    },
+                    Err(e) => EnvResponse::error(
+                        ErrorKind::Internal,
+                        format!("Failed to get session: {}", e),
+                    ),
                 }
             }
+            EnvEvent::TagSession { pid, tag } => {
+                if let Err(e) = Self::get_or_create_session(pid, sessions).await {
+                    return EnvResponse::error(
+                        ErrorKind::Internal,
+                        format!("Failed to get session: {}", e),
+                    );
+                }
+
+                let session = {
+                    let mut sessions_guard = sessions.write().await;
+                    let Some(sess) = sessions_guard.get_mut(&pid) else {
+                        return EnvResponse::error(
+                            ErrorKind::NotFound,
+                            format!("No session for pid {}", pid),
+                        );
+                    };
+                    if !sess.tags.contains(&tag) {
+                        sess.tags.push(tag);
+                    }
+                    sess.clone()
+                };
+
+                // Keep the last captured env; only the session header changes
+                let current_env = Session::load_metadata(&session.metadata_path())
+                    .map(|m| m.current_env)
+                    .unwrap_or_default();
+                if let Err(e) = session.save_metadata(&current_env) {
+                    return EnvResponse::error(
+                        ErrorKind::Io,
+                        format!("Failed to save metadata: {}", e),
+                    );
+                }
+                EnvResponse::Session { session }
+            }
             EnvEvent::GetSession { pid } => {
                 match Self::get_or_create_session(pid, sessions).await {
                     Ok(session) => EnvResponse::Session { session },
-                    Err(e) => EnvResponse::Error {
-                        message: format!("Failed to get session: {}", e),
-                    },
+                    Err(e) => EnvResponse::error(
+                        ErrorKind::Internal,
+                        format!("Failed to get session: {}", e),
+                    ),
                 }
             }
+            EnvEvent::Ping => EnvResponse::Ok,
+        }
+    }
+
+    fn track_decision(config: &Config, key: &str) -> TrackDecision {
+        let decision = config.should_track_explained(key);
+        if debug_enabled() {
+            let verdict = if decision.track { "track" } else { "skip" };
+            eprintln!("[debug] {} {}: {}", verdict, key, decision.reason);
         }
+        decision
     }
 
     async fn get_or_create_session(
@@ -257,9 +651,88 @@ impl EnvHistDaemon {
         Ok(session)
     }
 
+    fn hash_error(e: anyhow::Error) -> EnvResponse {
+        EnvResponse::error(ErrorKind::Io, format!("Failed to hash value: {}", e))
+    }
+
+    /// Whether the last entry for `key` is less than `interval` older than
+    /// `now`, so a sampled variable's change is dropped.
+    fn recorded_within(
+        entries: &[TimelineEntry],
+        key: &str,
+        interval: chrono::Duration,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> bool {
+        entries
+            .iter()
+            .rev()
+            .find(|entry| entry.key == key)
+            .is_some_and(|entry| now - entry.timestamp < interval)
+    }
+
+    /// The last recorded value of `key`, hashed if `filters.hash_patterns`
+    /// matches it (older data may predate the pattern).
     async fn get_previous_value(session: &Session, key: &str, storage: &Storage) -> Option<String> {
+        Self::find_previous_value(session, key, storage)
+            .and_then(|value| storage.protect_value(key, value).ok())
+    }
+
+    /// Set `key` in the env saved in `session`'s metadata, where the previous
+    /// value of the next change is looked up. Sessions without a captured env
+    /// fall back to the timeline for that instead.
+    fn save_current_value(session: &Session, key: &str, value: &str) -> Result<()> {
+        let Ok(metadata) = Session::load_metadata(&session.metadata_path()) else {
+            return Ok(());
+        };
+        let mut env = metadata.current_env;
+        env.insert(key.to_string(), value.to_string());
+        session.save_metadata(&env)
+    }
+
+    /// Drop `key` from the env saved in `session`'s metadata, so the next
+    /// change records no previous value.
+    fn remove_current_value(session: &Session, key: &str) -> Result<()> {
+        let Ok(metadata) = Session::load_metadata(&session.metadata_path()) else {
+            return Ok(());
+        };
+        let mut env = metadata.current_env;
+        if env.remove(key).is_none() {
+            return Ok(());
+        }
+        session.save_metadata(&env)
+    }
+
+    /// Append `entry` with the session's next sequence number.
+    async fn append_entry(
+        session: &Session,
+        mut entry: TimelineEntry,
+        seqs: &Seqs,
+        storage: &(dyn StorageBackend + Sync),
+    ) -> Result<()> {
+        // Held across the write so concurrent events can't share a number
+        let mut seqs = seqs.lock().await;
+        let last = match seqs.get(&session.id) {
+            Some(last) => *last,
+            None => storage
+                .read_timeline(session)?
+                .iter()
+                .map(|e| e.seq)
+                .max()
+                .unwrap_or(0),
+        };
+        entry.seq = last + 1;
+        storage.append_timeline(session, &entry)?;
+        seqs.insert(session.id, entry.seq);
+        Ok(())
+    }
+
+    fn find_previous_value(
+        session: &Session,
+        key: &str,
+        storage: &dyn StorageBackend,
+    ) -> Option<String> {
         // Try to get from metadata first
-        if let Ok(metadata) = Session::load_metadata(&session.metadata_path()) {
+        if let Ok(metadata) = storage.load_metadata(session) {
             return metadata.current_env.get(key).cloned();
         }
 
@@ -275,3 +748,200 @@ impl EnvHistDaemon {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_idle_client_is_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket_path = temp_dir.path().join("daemon.sock");
+
+        let mut config = Config::default();
+        config.core.daemon_idle_timeout_ms = 100;
+        let daemon = Arc::new(EnvHistDaemon::with_config(config));
+
+        let server_path = socket_path.clone();
+        let server = Arc::clone(&daemon);
+        tokio::spawn(async move { server.run(server_path).await });
+
+        while !socket_path.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Connect and send nothing; the daemon should close its end
+        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("daemon kept the idle connection open")
+            .unwrap();
+        assert_eq!(read, 0);
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        let captures = Arc::new(RwLock::new(HashMap::new()));
+        let seqs = Arc::new(Mutex::new(HashMap::new()));
+        let storage = Storage::with_config(Config::default());
+        let response = EnvHistDaemon::handle_event(
+            EnvEvent::Ping,
+            &sessions,
+            &captures,
+            &seqs,
+            &storage,
+            &Config::default(),
+        )
+        .await;
+        assert!(matches!(response, EnvResponse::Ok));
+        assert!(sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_set_after_unset_has_no_prev() {
+        let temp_dir = TempDir::new().unwrap();
+        std::env::set_var(envhist_core::config::ENVHIST_HOME_VAR, temp_dir.path());
+
+        let sessions = Arc::new(RwLock::new(HashMap::new()));
+        let captures = Arc::new(RwLock::new(HashMap::new()));
+        let seqs = Arc::new(Mutex::new(HashMap::new()));
+        let config = Config::default();
+        let storage = Storage::with_config(config.clone());
+        let pid = std::process::id();
+        let set = |value: &str| EnvEvent::Set {
+            pid,
+            key: "FOO".to_string(),
+            value: value.to_string(),
+        };
+        let events = [
+            // Metadata only exists once the shell's env has been captured
+            EnvEvent::Capture {
+                pid,
+                env: Env::new(),
+            },
+            set("1"),
+            EnvEvent::Unset {
+                pid,
+                key: "FOO".to_string(),
+            },
+            set("2"),
+        ];
+        for event in events {
+            let response =
+                EnvHistDaemon::handle_event(event, &sessions, &captures, &seqs, &storage, &config)
+                    .await;
+            assert!(matches!(response, EnvResponse::Ok), "{:?}", response);
+        }
+
+        let session = sessions.read().await[&pid].clone();
+        let entries = storage.read_timeline(&session).unwrap();
+        let prevs: Vec<Option<&str>> = entries.iter().map(|e| e.prev.as_deref()).collect();
+        assert_eq!(prevs, vec![None, Some("1"), None]);
+    }
+
+    #[tokio::test]
+    async fn test_seq_continues_after_restart() {
+        let backend = envhist_core::MemoryBackend::new();
+        let session = Session::new(std::process::id(), "zsh".to_string());
+        let entry = || TimelineEntry::new(Action::Set, "A".to_string(), Some("1".into()), None);
+
+        let seqs = Arc::new(Mutex::new(HashMap::new()));
+        for _ in 0..2 {
+            EnvHistDaemon::append_entry(&session, entry(), &seqs, &backend)
+                .await
+                .unwrap();
+        }
+        // A fresh daemon picks up from the timeline
+        let seqs = Arc::new(Mutex::new(HashMap::new()));
+        EnvHistDaemon::append_entry(&session, entry(), &seqs, &backend)
+            .await
+            .unwrap();
+
+        let seqs: Vec<u64> = backend
+            .read_timeline(&session)
+            .unwrap()
+            .iter()
+            .map(|e| e.seq)
+            .collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_previous_value_from_timeline() {
+        let backend = envhist_core::MemoryBackend::new();
+        let session = Session::new(std::process::id(), "zsh".to_string());
+        for (action, value) in [(Action::Set, Some("1")), (Action::Set, Some("2"))] {
+            let entry = TimelineEntry::new(action, "A".to_string(), value.map(String::from), None);
+            backend.append_timeline(&session, &entry).unwrap();
+        }
+        let unset = TimelineEntry::new(Action::Unset, "B".to_string(), None, Some("x".into()));
+        backend.append_timeline(&session, &unset).unwrap();
+
+        let previous = |key| EnvHistDaemon::find_previous_value(&session, key, &backend);
+        assert_eq!(previous("A"), Some("2".to_string()));
+        assert_eq!(previous("B"), Some("x".to_string()));
+        assert_eq!(previous("C"), None);
+
+        // A captured env takes precedence over the timeline
+        let captured = Env::from([("A".to_string(), "3".to_string())]);
+        backend.save_metadata(&session, &captured).unwrap();
+        assert_eq!(previous("A"), Some("3".to_string()));
+        assert_eq!(previous("B"), None);
+    }
+
+    #[test]
+    fn test_capture_mark_skips() {
+        let env = Env::from([("A".to_string(), "1".to_string())]);
+        let changed = Env::from([("A".to_string(), "2".to_string())]);
+        let start = chrono::Utc::now();
+        let later = start + chrono::Duration::seconds(30);
+        let last = CaptureMark::new(&env, start);
+
+        // Identical env is skipped however late it comes
+        let no_throttle = chrono::Duration::zero();
+        assert!(last.skips(&CaptureMark::new(&env, later), no_throttle));
+        assert!(!last.skips(&CaptureMark::new(&changed, later), no_throttle));
+
+        // A changed env is throttled within the minimum interval
+        let min_interval = chrono::Duration::seconds(60);
+        assert!(last.skips(&CaptureMark::new(&changed, later), min_interval));
+        let much_later = start + chrono::Duration::seconds(90);
+        assert!(!last.skips(&CaptureMark::new(&changed, much_later), min_interval));
+    }
+
+    #[test]
+    fn test_rapid_sets_are_sampled() {
+        let interval = chrono::Duration::seconds(60);
+        let start = chrono::Utc::now();
+        let mut entries: Vec<TimelineEntry> = Vec::new();
+
+        // COLUMNS changes every 10s for five minutes; OTHER shares the timeline
+        for i in 0..30 {
+            let now = start + chrono::Duration::seconds(i * 10);
+            if !EnvHistDaemon::recorded_within(&entries, "COLUMNS", interval, now) {
+                let mut entry = TimelineEntry::new(
+                    Action::Set,
+                    "COLUMNS".to_string(),
+                    Some((80 + i).to_string()),
+                    None,
+                );
+                entry.timestamp = now;
+                entries.push(entry);
+            }
+            let mut other = TimelineEntry::new(Action::Set, "OTHER".to_string(), None, None);
+            other.timestamp = now;
+            entries.push(other);
+        }
+
+        let recorded: Vec<i64> = entries
+            .iter()
+            .filter(|entry| entry.key == "COLUMNS")
+            .map(|entry| (entry.timestamp - start).num_seconds())
+            .collect();
+        assert_eq!(recorded, vec![0, 60, 120, 180, 240]);
+    }
+}